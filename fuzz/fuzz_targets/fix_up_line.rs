@@ -0,0 +1,20 @@
+// fix_up_line must not panic, and its result must have no stray brackets or
+// unknown tags left. Mismatched and unclosed tags are not fixed by design
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use dsldoc::{check_line, fix_up_line, TagError};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(s) = std::str::from_utf8(data) {
+        let fixed = fix_up_line(s);
+        match check_line(&fixed) {
+            Err(e @ TagError::BracketInsideTag(_))
+            | Err(e @ TagError::OrphanClosingBracket)
+            | Err(e @ TagError::UnknownTag(_))
+            | Err(e @ TagError::UnexpectedClosingTag(_))
+            | Err(e @ TagError::UnfinishedTag(_)) => panic!("{:?} is fixed as {:?}: {}", s, fixed, e),
+            _ => {},
+        }
+    }
+});
@@ -0,0 +1,16 @@
+// parse_line and check_line_span must not panic on any line, and the range
+// of an error must point inside the line
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use dsldoc::{check_line_span, parse_line};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(s) = std::str::from_utf8(data) {
+        let _ = parse_line(s);
+        if let Err((_, span)) = check_line_span(s) {
+            assert!(span.start <= span.end && span.end <= s.len());
+            assert!(s.is_char_boundary(span.start) && s.is_char_boundary(span.end));
+        }
+    }
+});
@@ -0,0 +1,399 @@
+// Splitting a dictionary into cards
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::iter::{Enumerate, Peekable};
+use std::ops::Range;
+
+use crate::{check_line_span, index_forms, line_type, lookup_tag, margin_level, render_snippet, split_pos, text_lines, visible_text, DState, TagKind, TextLines};
+
+/// One dictionary entry: one or more headword lines followed by its body
+#[derive(Debug,PartialEq,Clone)]
+pub struct Card {
+    /// line number of the first headword
+    pub line: usize,
+    /// headwords without the part-of-speech label, see `split_pos`
+    pub headwords: Vec<String>,
+    /// part of speech written on a headword line, like `n` for
+    /// `bank [p]n[/p]`: the label of the first headword that has one
+    pub pos: Option<String>,
+    /// body lines as they are in the file, with leading TABs
+    pub body: Vec<String>,
+}
+
+impl Card {
+    /// plain text of the card: headwords, then body lines without tags
+    /// indented by their margin levels
+    pub fn to_text(&self) -> String {
+        let mut res = String::new();
+        for h in self.headwords.iter() {
+            res.push_str(&visible_text(h, &[]));
+            res.push('\n');
+        }
+        for l in self.body.iter() {
+            let text = visible_text(l, &[]);
+            if text.trim().is_empty() {
+                continue;
+            }
+            let level = margin_level(l).unwrap_or(1).max(1) as usize;
+            res.push_str(&"  ".repeat(level));
+            res.push_str(text.trim());
+            res.push('\n');
+        }
+        res
+    }
+
+    /// the same as `to_text` for a terminal: ANSI escapes make headwords and
+    /// `[b]` bold, `[i]` italic, and comments `[com]` dim
+    pub fn to_ansi(&self) -> String {
+        let mut res = String::new();
+        for h in self.headwords.iter() {
+            res.push_str(&format!("{}{}{}\n", ANSI_BOLD, visible_text(h, &[]), ANSI_RESET));
+        }
+        for l in self.body.iter() {
+            if visible_text(l, &[]).trim().is_empty() {
+                continue;
+            }
+            let level = margin_level(l).unwrap_or(1).max(1) as usize;
+            res.push_str(&"  ".repeat(level));
+            res.push_str(&ansi_line(l.trim()));
+            res.push('\n');
+        }
+        res
+    }
+}
+
+const ANSI_RESET: &str = "\x1b[0m";
+const ANSI_BOLD: &str = "\x1b[1m";
+
+// a body line with tags replaced by ANSI escapes. A terminal cannot turn off
+// one style of several, so every change resets all styles and sets the ones
+// of the tags still open. Spaces around the visible text are dropped, like
+// `to_text` trims a line
+fn ansi_line(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    let mut styles: Vec<&str> = Vec::new();
+    // spaces are held back until the next visible character
+    let mut spaces = String::new();
+    let mut started = false;
+    let mut escaped = false;
+    let mut pos = 0;
+    while let Some(c) = s[pos..].chars().next() {
+        let tag = if c == '[' && !escaped { s[pos..].find(']').and_then(|e| lookup_tag(&s[pos..=pos + e]).map(|t| (t, e + 1))) } else { None };
+        escaped = c == '\\' && !escaped;
+        let ((info, closing), len) = match tag {
+            None => {
+                if !escaped && c.is_whitespace() {
+                    spaces.push(c);
+                } else if !escaped {
+                    if started {
+                        res.push_str(&spaces);
+                    }
+                    spaces.clear();
+                    started = true;
+                    res.push(c);
+                }
+                pos += c.len_utf8();
+                continue;
+            }
+            Some(t) => t,
+        };
+        pos += len;
+        if info.opaque && !closing {
+            // file names of media are not shown, like in `to_text`
+            pos = s[pos..].find(&format!("[/{}]", info.name)).map_or(s.len(), |e| pos + e);
+            continue;
+        }
+        let style = match info.kind {
+            TagKind::B => "\x1b[1m",
+            TagKind::I => "\x1b[3m",
+            TagKind::Comment => "\x1b[2m",
+            _ => continue,
+        };
+        if closing {
+            if let Some(i) = styles.iter().rposition(|&st| st == style) {
+                styles.remove(i);
+            }
+        } else {
+            styles.push(style);
+        }
+        if started {
+            res.push_str(&spaces);
+            spaces.clear();
+        }
+        res.push_str(ANSI_RESET);
+        res.push_str(&styles.concat());
+    }
+    if !styles.is_empty() {
+        res.push_str(ANSI_RESET);
+    }
+    res
+}
+
+/// The first problem found in a card
+#[derive(Debug,PartialEq,Clone)]
+pub struct ParseError {
+    pub line: usize,
+    /// byte range of the offending text in the line
+    pub span: Range<usize>,
+    pub message: String,
+}
+
+impl ParseError {
+    /// the error message followed by the line with the offending text
+    /// underlined, `line` must be the text of the line the error points to
+    pub fn snippet(&self, line: &str) -> String {
+        format!("error: {}\n{}", self.message, render_snippet(line, self.line, &self.span))
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ParseError {}
+
+pub struct Cards<'a> {
+    lines: Peekable<Enumerate<TextLines<'a>>>,
+    in_header: bool,
+}
+
+/// Slug of a headword to be used as an HTML `id` or a link target: the
+/// indexed form of the headword in lower case, spaces replaced with `-`, and
+/// all characters except ASCII letters, digits, `-`, and `_` percent-encoded
+pub fn card_anchor(headword: &str) -> String {
+    let (full, _) = index_forms(headword);
+    let mut res = String::with_capacity(full.len());
+    for c in full.to_lowercase().chars() {
+        match c {
+            ' ' => res.push('-'),
+            _ if c.is_ascii_alphanumeric() || c == '-' || c == '_' => res.push(c),
+            _ => {
+                let mut buf = [0; 4];
+                for b in c.encode_utf8(&mut buf).bytes() {
+                    res.push_str(&format!("%{:02X}", b));
+                }
+            }
+        }
+    }
+    res
+}
+
+/// Unique anchors of the cards of one dictionary: a headword whose
+/// `card_anchor` is already taken gets a numeric suffix, `bank`, `bank-2`
+#[derive(Debug,Clone,Default)]
+pub struct Anchors {
+    used: HashSet<String>,
+}
+
+impl Anchors {
+    pub fn new() -> Anchors {
+        Anchors::default()
+    }
+
+    /// the same headwords always get the same anchors if they are added in
+    /// the same order
+    pub fn anchor(&mut self, headword: &str) -> String {
+        let base = card_anchor(headword);
+        let mut res = base.clone();
+        let mut n = 1;
+        while self.used.contains(&res) {
+            n += 1;
+            res = format!("{}-{}", base, n);
+        }
+        self.used.insert(res.clone());
+        res
+    }
+}
+
+/// Iterates over cards of a decoded dictionary. A broken card is returned as
+/// `Err` and the iteration goes on from the next headword, so all bad cards
+/// can be reported in one pass
+pub fn cards(content: &str) -> Cards<'_> {
+    let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    Cards { lines: text_lines(content).enumerate().peekable(), in_header: true }
+}
+
+impl<'a> Cards<'a> {
+    // skips lines up to the next headword
+    fn resync(&mut self) {
+        while let Some((_, l)) = self.lines.peek() {
+            if line_type(l) == DState::Key {
+                return;
+            }
+            self.lines.next();
+        }
+    }
+}
+
+impl<'a> Iterator for Cards<'a> {
+    type Item = Result<Card, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (idx, l) = self.lines.peek()?;
+            let tp = line_type(l);
+            if tp == DState::EmptyLine || tp == DState::Include || tp == DState::EditorComment
+                || (self.in_header && l.starts_with('#')) {
+                self.lines.next();
+                continue;
+            }
+            if tp == DState::Key {
+                break;
+            }
+            let err = ParseError { line: *idx, span: 0..l.len(), message: String::from("card body without headword") };
+            self.resync();
+            return Some(Err(err));
+        }
+        self.in_header = false;
+
+        let mut card = Card { line: 0, headwords: Vec::new(), pos: None, body: Vec::new() };
+        let mut err: Option<ParseError> = None;
+        // consecutive headwords are variants of one card, unless they are
+        // separated with a body or an empty line
+        let mut after_headwords = false;
+        while let Some((idx, l)) = self.lines.peek() {
+            let tp = line_type(l);
+            if (tp == DState::Key && after_headwords) || tp == DState::Include {
+                break;
+            }
+            if tp == DState::EditorComment {
+                self.lines.next();
+                continue;
+            }
+            after_headwords = tp != DState::Key;
+            if tp == DState::Key && card.headwords.is_empty() {
+                card.line = *idx;
+            }
+            if err.is_none() {
+                if let Err((e, span)) = check_line_span(l) {
+                    err = Some(ParseError { line: *idx, span, message: e.to_string() });
+                }
+            }
+            match tp {
+                DState::Key => {
+                    let (headword, pos) = split_pos(l);
+                    card.headwords.push(headword.to_string());
+                    if card.pos.is_none() {
+                        card.pos = pos.map(|p| p.to_string());
+                    }
+                }
+                DState::EmptyLine => {},
+                _ => card.body.push(l.to_string()),
+            }
+            self.lines.next();
+        }
+        match err {
+            Some(e) => Some(Err(e)),
+            None => Some(Ok(card)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn broken_card_does_not_stop_iteration() {
+        let text = "#NAME \"t\"\r\n\r\ncat\r\n\t[m1]a cat[/m]\r\ndog\r\n\t[m1]a dog[/m]\r\n\t[m1]a [x]dog[/x][/m]\r\nfox\r\n\t[m1]a fox[/m]\r\n";
+        let res: Vec<Result<Card, ParseError>> = cards(text).collect();
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[0].as_ref().map(|c| c.headwords.clone()), Ok(vec![String::from("cat")]));
+        let err = res[1].as_ref().unwrap_err();
+        assert_eq!((err.line, err.span.clone()), (6, 7..10));
+        assert_eq!(res[2].as_ref().map(|c| (c.line, c.body.len())), Ok((7, 1)));
+    }
+
+    #[test]
+    fn body_without_headword() {
+        let text = "#NAME \"t\"\r\n\r\n\t[m1]lost[/m]\r\n\t[m1]lines[/m]\r\ncat\r\n\t[m1]a cat[/m]\r\n";
+        let res: Vec<Result<Card, ParseError>> = cards(text).collect();
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].as_ref().unwrap_err().message, "card body without headword");
+        assert_eq!(res[1].as_ref().map(|c| c.line), Ok(4));
+    }
+
+    #[test]
+    fn variants_share_a_card() {
+        let text = "colour\r\ncolor\r\n\t[m1]цвет[/m]\r\n\r\nbank [p]n[/p]\r\n\r\nbank\r\n\t[m1]берег[/m]\r\n";
+        let res: Vec<Card> = cards(text).map(|c| c.unwrap()).collect();
+        assert_eq!(res[0].headwords, vec!["colour", "color"]);
+        // an empty line ends the list of variants
+        assert_eq!((res[1].headwords.clone(), res[1].pos.clone()), (vec![String::from("bank")], Some(String::from("n"))));
+        assert_eq!(res[2].body, vec!["\t[m1]берег[/m]"]);
+    }
+
+    #[test]
+    fn error_snippet() {
+        let text = "cat\r\n\t[m1]a [x]cat[/m]\r\n";
+        let err = cards(text).next().unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "line 1: unknown tag '[x]'");
+        assert_eq!(err.snippet("\t[m1]a [x]cat[/m]"), "error: unknown tag '[x]'\n1 | \t[m1]a [x]cat[/m]\n  | \t      ^^^");
+        let source: &dyn Error = &err;
+        assert_eq!(source.to_string(), err.to_string());
+    }
+
+    #[test]
+    fn card_anchors() {
+        assert_eq!(card_anchor("Big cat"), "big-cat");
+        assert_eq!(card_anchor("a_b-c 1"), "a_b-c-1");
+        assert_eq!(card_anchor("dog's"), "dog%27s");
+        assert_eq!(card_anchor("Кот"), "%D0%BA%D0%BE%D1%82");
+        // the indexed form, without optional parts and escapes
+        assert_eq!(card_anchor("colo(u)r"), card_anchor("colour"));
+        assert_eq!(card_anchor("a\\[b\\]"), "a%5Bb%5D");
+        let mut anchors = Anchors::new();
+        let res: Vec<String> = ["bank", "Bank", "bank-2", "bank", "кот"].iter().map(|h| anchors.anchor(h)).collect();
+        assert_eq!(res, ["bank", "bank-2", "bank-2-2", "bank-3", "%D0%BA%D0%BE%D1%82"]);
+    }
+
+    #[test]
+    fn bare_cr_ends_lines() {
+        let text = "#NAME \"t\"\r\rcat\r\t[m1]a cat[/m]\r\rdog\r\t[m1]a dog[/m]\r";
+        let res: Vec<Card> = cards(text).map(|c| c.unwrap()).collect();
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[1].line, 5);
+        assert_eq!(res[1].body, vec!["\t[m1]a dog[/m]"]);
+    }
+
+    // removes ANSI escapes `ESC [ ... m`
+    fn strip_ansi(s: &str) -> String {
+        let mut res = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().find(|&c| c == 'm');
+            } else {
+                res.push(c);
+            }
+        }
+        res
+    }
+
+    #[test]
+    fn ansi_rendering() {
+        let text = "#NAME \"t\"\r\n\r\ncat\r\n\t[m1][b]кошка[/b] [s]cat.wav[/s][/m]\r\n\t\t[m2][ex][i]a [b]big[/b] cat[/i][/ex] [com]note[/com] \\[1\\][/m]\r\n\t\r\n";
+        let card = cards(text).next().unwrap().unwrap();
+        let ansi = card.to_ansi();
+        assert_eq!(strip_ansi(&ansi), card.to_text());
+        assert_eq!(card.to_text(), "cat\n  кошка\n    a big cat note [1]\n");
+        assert_eq!(ansi.lines().collect::<Vec<_>>(), [
+            "\x1b[1mcat\x1b[0m",
+            "  \x1b[0m\x1b[1mкошка\x1b[0m",
+            "    \x1b[0m\x1b[3ma \x1b[0m\x1b[3m\x1b[1mbig\x1b[0m\x1b[3m cat\x1b[0m \x1b[0m\x1b[2mnote\x1b[0m [1]",
+        ]);
+    }
+
+    #[test]
+    fn part_of_speech() {
+        let text = "#NAME \"t\"\r\n\r\nbank\r\nbank [p]n[/p]\r\n\t[m1]берег[/m]\r\nrun\r\n\t[m1]бежать[/m]\r\n";
+        let res: Vec<Card> = cards(text).map(|c| c.unwrap()).collect();
+        assert_eq!(res[0].headwords, ["bank", "bank"]);
+        assert_eq!(res[0].pos.as_deref(), Some("n"));
+        assert_eq!(res[1].pos, None);
+    }
+}
@@ -0,0 +1,1414 @@
+// Checking a whole decoded dictionary
+
+use std::collections::hash_map::{DefaultHasher, Entry};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use crate::schema::Schema;
+use crate::{
+    bare_url, can_end, can_follow, can_follow_lenient, card, check_line, check_line_open,
+    detect_tabs_per_level, edit_distance, empty_formatting, fold_case, has_tags, has_translation,
+    header_value, headword_specials, index_forms, index_key, is_body, lang_depth, lang_ids,
+    lang_name, line_type, margin_level, raw_tag_contents, redundant_margins, script, split_lines,
+    split_pos, tabs_inside_tags, tag_columns, tag_contents, tag_info, untranslated_examples,
+    visible_text, DState, TagKind, INDENT_SAMPLE_CARDS,
+};
+
+#[derive(Debug,PartialEq,Copy,Clone)]
+pub enum Severity {
+    Error,
+    Warning,
+    // not a problem, extra information requested by user
+    Info,
+}
+
+impl Severity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+        }
+    }
+}
+
+#[derive(Debug,PartialEq,Clone)]
+pub struct Diagnostic {
+    /// 0-based line number
+    pub line: usize,
+    /// offset of the line start in UTF-16 bytes, see `Checker::start_offset`
+    pub byte_offset: usize,
+    pub severity: Severity,
+    /// short name of the problem, e.g. `unknown-tag`
+    pub kind: &'static str,
+    pub message: String,
+    /// machine-readable details, e.g. `expected_tabs` of `wrong-indent`
+    pub data: Vec<(&'static str, usize)>,
+}
+
+/// Where a line checked by a `LineRule` is
+#[derive(Debug,PartialEq,Clone)]
+pub struct LineContext<'a> {
+    /// the first headword of the card the line belongs to, empty in the header
+    pub headword: &'a str,
+    /// 0-based line number
+    pub line: usize,
+    /// offset of the line start, the same as `Diagnostic::byte_offset`
+    pub byte_offset: usize,
+    /// margin `[mN]` the line starts with
+    pub margin: Option<u8>,
+}
+
+/// Type of a line and whether it may follow the previous one, see
+/// `Checker::check_traced`
+#[derive(Debug,PartialEq,Copy,Clone)]
+pub struct TraceEvent {
+    /// 0-based line number
+    pub line: usize,
+    pub state: DState,
+    /// type of the previous line, `None` for editor comments that are skipped
+    pub prev: Option<DState>,
+    pub accepted: bool,
+}
+
+/// Project-specific check of a line, added with `Checker::rule`. It runs
+/// after the built-in checks on every line except editor comments
+pub trait LineRule: fmt::Debug {
+    fn check(&self, line: &str, ctx: &LineContext) -> Vec<Diagnostic>;
+}
+
+/// Set of checks to run on a dictionary, e.g.
+/// `Checker::new().near_dup(Some(1)).allow_tag("url").check(&text)`
+#[derive(Debug,Default)]
+pub struct Checker {
+    start_offset: usize,
+    allow_monolingual: bool,
+    no_duplicate_check: bool,
+    hash_headwords: bool,
+    verbose: bool,
+    lenient: bool,
+    near_dup: Option<usize>,
+    dup_ignore_trailing_punct: bool,
+    require_translation: bool,
+    check_script: bool,
+    check_direction: bool,
+    expand_optional: bool,
+    warn_bare_urls: bool,
+    check_redundant_margins: bool,
+    flag_empty_formatting: bool,
+    flag_untranslated: bool,
+    max_line_length: Option<usize>,
+    min_translation_length: Option<usize>,
+    max_lang_depth: Option<usize>,
+    check_indent: bool,
+    resolve_lang_ids: bool,
+    check_examples: bool,
+    abbrev_consistency: bool,
+    schema: Option<Schema>,
+    allowed_tags: Vec<String>,
+    multiline_tags: Vec<TagKind>,
+    forbidden_tags: Vec<String>,
+    // headwords of included files and the file names
+    included: HashMap<String, String>,
+    // severities that replace the default ones of problem kinds
+    severities: Vec<(String, Severity)>,
+    rules: Vec<Box<dyn LineRule>>,
+}
+
+// every report repeats the line, so a huge line with thousands of problems
+// must not print thousands of copies of itself
+const MAX_LINE_REPORTS: usize = 10;
+
+// number of body lines to sample for check_script
+const SCRIPT_SAMPLE_LINES: usize = 1000;
+
+impl Checker {
+    pub fn new() -> Checker {
+        Checker::default()
+    }
+
+    /// offset of the content in the file, e.g. the BOM length, that is added
+    /// to byte offsets of all diagnostics
+    pub fn start_offset(mut self, offset: usize) -> Self {
+        self.start_offset = offset;
+        self
+    }
+
+    /// do not warn about the same index and contents languages
+    pub fn allow_monolingual(mut self, on: bool) -> Self {
+        self.allow_monolingual = on;
+        self
+    }
+
+    /// look for duplicated headwords (on by default)
+    pub fn duplicates(mut self, on: bool) -> Self {
+        self.no_duplicate_check = !on;
+        self
+    }
+
+    /// keep 64-bit hashes of headwords instead of their copies to find
+    /// duplicates with less memory
+    pub fn hash_headwords(mut self, on: bool) -> Self {
+        self.hash_headwords = on;
+        self
+    }
+
+    /// report extra information, e.g. editor comments `## ...`
+    pub fn verbose(mut self, on: bool) -> Self {
+        self.verbose = on;
+        self
+    }
+
+    /// accept lines in any order, except body lines before the first
+    /// headword. Tags are checked as usual
+    pub fn lenient(mut self, on: bool) -> Self {
+        self.lenient = on;
+        self
+    }
+
+    /// report headwords that differ from another one by at most the given
+    /// number of edits
+    pub fn near_dup(mut self, dist: Option<usize>) -> Self {
+        self.near_dup = dist;
+        self
+    }
+
+    /// warn about headwords of different cards that are the same without
+    /// trailing `.,;:`, like `word` and `word.`
+    pub fn dup_ignore_trailing_punct(mut self, on: bool) -> Self {
+        self.dup_ignore_trailing_punct = on;
+        self
+    }
+
+    /// report cards that contain only comments and examples
+    pub fn require_translation(mut self, on: bool) -> Self {
+        self.require_translation = on;
+        self
+    }
+
+    /// warn about cards that look reversed: the headword is written in the
+    /// script of `#CONTENTS_LANGUAGE`, or a translation `[trn]` is in the
+    /// script of `#INDEX_LANGUAGE`. Only if the languages use different scripts
+    pub fn check_direction(mut self, on: bool) -> Self {
+        self.check_direction = on;
+        self
+    }
+
+    /// warn if card texts are not in the script of #CONTENTS_LANGUAGE
+    pub fn check_script(mut self, on: bool) -> Self {
+        self.check_script = on;
+        self
+    }
+
+    /// show how headwords with optional parts are indexed
+    pub fn expand_optional(mut self, on: bool) -> Self {
+        self.expand_optional = on;
+        self
+    }
+
+    /// warn about links that are not wrapped in [url]
+    pub fn warn_bare_urls(mut self, on: bool) -> Self {
+        self.warn_bare_urls = on;
+        self
+    }
+
+    /// warn about margins repeated without text between them, `[m1][m1]`
+    pub fn check_redundant_margins(mut self, on: bool) -> Self {
+        self.check_redundant_margins = on;
+        self
+    }
+
+    /// warn about paired tags without text, like `[b][/b]` or `[i] [/i]`
+    pub fn flag_empty_formatting(mut self, on: bool) -> Self {
+        self.flag_empty_formatting = on;
+        self
+    }
+
+    /// warn about translations [trn] that repeat the headword of their card
+    pub fn flag_untranslated(mut self, on: bool) -> Self {
+        self.flag_untranslated = on;
+        self
+    }
+
+    /// warn about body lines with more visible characters than the limit
+    pub fn max_line_length(mut self, len: Option<usize>) -> Self {
+        self.max_line_length = len;
+        self
+    }
+
+    /// warn about translations `[trn]...[/trn]` with fewer visible characters
+    /// than the limit, they are likely stubs
+    pub fn min_translation_length(mut self, len: Option<usize>) -> Self {
+        self.min_translation_length = len;
+        self
+    }
+
+    /// warn about `[lang]` blocks nested deeper than the limit: a block of
+    /// one language inside a block of another is fine, but deeper nesting
+    /// is usually a copy-paste slip
+    pub fn max_lang_depth(mut self, depth: Option<usize>) -> Self {
+        self.max_lang_depth = depth;
+        self
+    }
+
+    /// warn about body lines with leading TABs that do not agree with their
+    /// margins, using the TABs per level the dictionary uses
+    pub fn check_indent(mut self, on: bool) -> Self {
+        self.check_indent = on;
+        self
+    }
+
+    /// warn about `[lang id=N]` with an id that is not a known language
+    pub fn resolve_lang_ids(mut self, on: bool) -> Self {
+        self.resolve_lang_ids = on;
+        self
+    }
+
+    /// report examples [ex] without a translation
+    pub fn check_examples(mut self, on: bool) -> Self {
+        self.check_examples = on;
+        self
+    }
+
+    /// report labels [p] written in different ways, like `n.` and `n`
+    pub fn abbrev_consistency(mut self, on: bool) -> Self {
+        self.abbrev_consistency = on;
+        self
+    }
+
+    pub fn schema(mut self, schema: Option<Schema>) -> Self {
+        self.schema = schema;
+        self
+    }
+
+    /// accept a tag the checker does not know, e.g. `url`
+    pub fn allow_tag(mut self, name: &str) -> Self {
+        self.allowed_tags.push(name.trim_start_matches('[').trim_end_matches(']').to_string());
+        self
+    }
+
+    /// let tag [NAME] span several lines of a card, e.g. `com`; other tags
+    /// must be closed on the line they are opened. Unknown tags are ignored
+    pub fn multiline_tag(mut self, name: &str) -> Self {
+        if let Some(t) = tag_info(name) {
+            self.multiline_tags.push(t.kind);
+        }
+        self
+    }
+
+    /// report every use of tag [NAME] in card bodies as an error, even if
+    /// the tag is valid, e.g. a deprecated `p`
+    pub fn forbid_tag(mut self, name: &str) -> Self {
+        self.forbidden_tags.push(name.trim_start_matches('[').trim_end_matches(']').to_string());
+        self
+    }
+
+    /// headwords of a file included with #INCLUDE, they are checked for
+    /// duplicates as well
+    pub fn include(mut self, file: &str, headwords: &[String]) -> Self {
+        for h in headwords.iter() {
+            self.included.entry(h.clone()).or_insert_with(|| file.to_string());
+        }
+        self
+    }
+
+    /// change the severity of problems of a kind, e.g. make `line-too-long`
+    /// an error. The last change of a kind wins
+    pub fn severity(mut self, kind: &str, severity: Severity) -> Self {
+        self.severities.push((kind.to_string(), severity));
+        self
+    }
+
+    /// applies the severities set with `severity` to problems found
+    /// elsewhere, `check` applies them itself
+    pub fn adjust_severity(&self, diags: &mut [Diagnostic]) {
+        for d in diags.iter_mut() {
+            if let Some((_, sev)) = self.severities.iter().rev().find(|(k, _)| k == d.kind) {
+                d.severity = *sev;
+            }
+        }
+    }
+
+    /// run a custom check on every line
+    pub fn rule<R: LineRule + 'static>(mut self, rule: R) -> Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// checks decoded dictionary text, diagnostics are sorted by line. A
+    /// leading BOM is skipped
+    pub fn check(&self, content: &str) -> Vec<Diagnostic> {
+        self.check_traced(content, &mut |_| {})
+    }
+
+    /// the same as `check`, `trace` gets the type of every line and whether
+    /// it may follow the previous one
+    pub fn check_traced(&self, content: &str, trace: &mut dyn FnMut(&TraceEvent)) -> Vec<Diagnostic> {
+        let mut offset = self.start_offset;
+        let content = match content.strip_prefix('\u{feff}') {
+            None => content,
+            Some(rest) => {
+                offset += 2;
+                rest
+            }
+        };
+        let mut prev = DState::Begin;
+        let mut words = Headwords::new(self.hash_headwords);
+        let mut card_start = 0;
+        let mut headword = "";
+        let mut seen_key = false;
+        // the type of the last line that is not empty
+        let mut last_filled = DState::Begin;
+        let mut index_lang = String::new();
+        let mut diags: Vec<Diagnostic> = Vec::new();
+        let mut lines: Vec<&str> = Vec::new();
+        let mut offsets: Vec<usize> = Vec::new();
+        // headword line, its offset, and whether the card has a translation
+        let mut card: Option<(usize, usize, bool)> = None;
+        let mut contents_lang: Option<(usize, usize, &str)> = None;
+        let mut script_lines = 0;
+        let mut scripts: HashMap<script::Script, usize> = HashMap::new();
+        // scripts of the index and the contents languages for check_direction
+        let mut direction: Option<(script::Script, script::Script)> = None;
+        let mut labels = Labels::new();
+        // multi-line tags left open by the previous lines of the card and
+        // where the first of them is opened
+        let mut open_tags: Vec<TagKind> = Vec::new();
+        let mut open_at = (0, 0);
+        let tabs_per_level = if self.check_indent { detect_tabs_per_level(content, INDENT_SAMPLE_CARDS).unwrap_or(1) } else { 0 };
+
+        // lines that end with a bare CR and the first of them
+        let mut bare_cr: Option<(usize, usize)> = None;
+        let mut bare_cr_lines = 0;
+        for (idx, raw) in split_lines(content).enumerate() {
+            let line_offset = offset;
+            offset += raw.encode_utf16().count() * 2;
+            if raw.ends_with('\r') {
+                bare_cr_lines += 1;
+                bare_cr.get_or_insert((idx, line_offset));
+            }
+            let l = raw.strip_suffix('\n').unwrap_or(raw);
+            let l = l.strip_suffix('\r').unwrap_or(l);
+            lines.push(l);
+            offsets.push(line_offset);
+            let tp = line_type(l);
+            // editor comments may be anywhere and do not change the state
+            if tp == DState::EditorComment {
+                trace(&TraceEvent { line: idx, state: tp, prev: None, accepted: true });
+                if self.verbose {
+                    diags.push(Diagnostic { line: idx, byte_offset: line_offset, severity: Severity::Info, kind: "editor-comment",
+                        message: format!("editor comment ==> {}", l), data: Vec::new() });
+                }
+                if has_tags(l) {
+                    diags.push(Diagnostic { line: idx, byte_offset: line_offset, severity: Severity::Error, kind: "tag-in-comment",
+                        message: format!("tags inside editor comment ==> {}", l), data: Vec::new() });
+                }
+                continue;
+            }
+            if !open_tags.is_empty() && !l.starts_with('\t') {
+                report_open_tags(&mut diags, &lines, open_at, &open_tags);
+                open_tags.clear();
+            }
+            if self.require_translation {
+                if tp == DState::Key {
+                    report_untranslated(&mut diags, &lines, card);
+                    card = Some((idx, line_offset, false));
+                } else if let Some((_, _, translated)) = card.as_mut() {
+                    *translated = *translated || (l.starts_with('\t') && has_translation(l));
+                }
+            }
+            if self.check_script {
+                if tp == DState::Lang {
+                    contents_lang = Some((idx, line_offset, header_value(l)));
+                } else if l.starts_with('\t') && script_lines < SCRIPT_SAMPLE_LINES {
+                    script_lines += 1;
+                    for c in visible_text(l, &["com", "ex"]).chars().filter(|c| c.is_alphabetic()) {
+                        let sc = script::char_script(c);
+                        if sc != script::Script::Other {
+                            *scripts.entry(sc).or_insert(0) += 1;
+                        }
+                    }
+                }
+            }
+            let mut report = |severity: Severity, kind: &'static str, message: String| {
+                diags.push(Diagnostic { line: idx, byte_offset: line_offset, severity, kind, message, data: Vec::new() })
+            };
+
+            if let Some(col) = l.chars().position(|c| c == '\u{feff}') {
+                report(Severity::Error, "bom-inside-text", format!("byte order mark inside the text at column {} ==> {}", col + 1, l));
+            }
+            if tp == DState::Invalid {
+                report(Severity::Error, "invalid-line", l.to_string());
+            }
+            if tp == DState::Index {
+                index_lang = header_value(l).to_string();
+            }
+            if tp == DState::Lang && self.check_direction {
+                direction = match (script::language_script(&index_lang), script::language_script(header_value(l))) {
+                    (Some(i), Some(c)) if !script::script_matches(i, c) && !script::script_matches(c, i) => Some((i, c)),
+                    _ => None,
+                };
+            }
+            if let (Some((_, contents)), DState::Key) = (direction, tp) {
+                if script::text_script(&index_forms(split_pos(l).0).0) == Some(contents) {
+                    report(Severity::Warning, "reversed-card", format!("headword is in {:?} script of the contents language, the card may be reversed ==> {}", contents, l));
+                }
+            }
+            if let (Some((index, _)), true) = (direction, l.starts_with('\t')) {
+                for trn in tag_contents(l, "trn").iter().take(MAX_LINE_REPORTS) {
+                    let text = visible_text(trn, &[]);
+                    if script::text_script(&text) == Some(index) {
+                        report(Severity::Warning, "reversed-card", format!("translation '{}' is in {:?} script of the index language, the card may be reversed ==> {}", text.trim(), index, l));
+                    }
+                }
+            }
+            if tp == DState::Lang && !self.allow_monolingual && header_value(l) == index_lang {
+                report(Severity::Warning, "same-languages", format!("index and contents languages are the same ({}) ==> {}", index_lang, l));
+            }
+            if tp == DState::Key {
+                if l.contains('\t') {
+                    report(Severity::Error, "tab-in-headword", format!("tab inside headword ==> {}", l));
+                }
+                // the part-of-speech label is not a part of the headword
+                let specials = headword_specials(split_pos(l).0);
+                for (col, c) in specials.iter().take(MAX_LINE_REPORTS) {
+                    let what = if *c == '[' || *c == ']' { "unescaped" } else { "unbalanced" };
+                    report(Severity::Error, "headword-special-char", format!("{} '{}' in headword at column {} ==> {}", what, c, col, l));
+                }
+                if specials.len() > MAX_LINE_REPORTS {
+                    report(Severity::Error, "headword-special-char", format!("{} more special characters in headword", specials.len() - MAX_LINE_REPORTS));
+                }
+                if self.expand_optional && l.contains('(') {
+                    let (full, stripped) = index_forms(l);
+                    report(Severity::Info, "index-forms", format!("{} indexed as '{}' and '{}'", l, full, stripped));
+                }
+                // consecutive headwords are variants of one card
+                if prev != DState::Key {
+                    card_start = idx;
+                    headword = l;
+                }
+                // the keyword list is needed for near duplicates as well
+                if !self.no_duplicate_check || self.near_dup.is_some() || self.dup_ignore_trailing_punct {
+                    match words.get(l, &lines) {
+                        None if self.included.contains_key(l) && !self.no_duplicate_check => {
+                            words.insert(l, (idx, card_start));
+                            report(Severity::Error, "duplicate", format!("{} already exists in {}", l, self.included[l]));
+                        }
+                        None => words.insert(l, (idx, card_start)),
+                        Some(_) if self.no_duplicate_check => {},
+                        // a variant of one card is the headword or a variant of another one
+                        Some((v, start)) if start != card_start && (v != start || idx != card_start) => {
+                            report(Severity::Error, "variant-collision", format!("{} is also a headword of the card at {}", l, start));
+                        }
+                        Some((v, _)) => report(Severity::Error, "duplicate", format!("{} already exists at {}", l, v)),
+                    }
+                }
+            }
+            seen_key |= tp == DState::Key;
+            let accepted = if self.lenient { can_follow_lenient(seen_key, tp) } else { can_follow(prev, tp) };
+            trace(&TraceEvent { line: idx, state: tp, prev: Some(prev), accepted });
+            if !accepted && prev == DState::EmptyLine && is_body(tp) && is_body(last_filled) {
+                report(Severity::Error, "empty-line-in-card", format!("card body goes on after an empty line, indent the empty line with a TAB ==> {}", l));
+            } else if !accepted {
+                report(Severity::Error, "wrong-order", format!("PREV {:?}, CURR: {:?}{}", prev, tp, l));
+            }
+            if tp != DState::EmptyLine {
+                last_filled = tp;
+            }
+            let checked = if self.allowed_tags.is_empty() { l.to_string() } else { strip_tags(l, &self.allowed_tags) };
+            let res = if self.multiline_tags.is_empty() || !l.starts_with('\t') {
+                check_line(&checked)
+            } else {
+                let was_open = !open_tags.is_empty();
+                let res = check_line_open(&checked, &mut open_tags, &self.multiline_tags).map_err(|(e, _)| e);
+                if !was_open && !open_tags.is_empty() {
+                    open_at = (idx, line_offset);
+                }
+                res
+            };
+            if let Err(e) = res {
+                report(Severity::Error, e.kind(), format!("{} ==> {}", e, l));
+            }
+            if tp != DState::Key {
+                if let Some(col) = tabs_inside_tags(l).first() {
+                    report(Severity::Warning, "tab-inside-tag", format!("tab inside tag at column {} ==> {}", col, l));
+                }
+            }
+            if self.abbrev_consistency && l.starts_with('\t') {
+                for label in tag_contents(l, "p") {
+                    labels.add(label, idx, line_offset);
+                }
+            }
+            if self.check_examples && l.starts_with('\t') {
+                for ex in untranslated_examples(l).iter().take(MAX_LINE_REPORTS) {
+                    report(Severity::Warning, "untranslated-example", format!("example '{}' has no translation ==> {}", ex, l));
+                }
+            }
+            if self.warn_bare_urls && l.starts_with('\t') {
+                if let Some(url) = bare_url(l) {
+                    report(Severity::Warning, "bare-url", format!("link {} is not wrapped in [url]...[/url] ==> {}", url, l));
+                }
+            }
+            if l.starts_with('\t') {
+                for name in self.forbidden_tags.iter() {
+                    for col in tag_columns(l, name).iter().take(MAX_LINE_REPORTS) {
+                        report(Severity::Error, "forbidden-tag", format!("forbidden tag [{}] at column {} ==> {}", name, col, l));
+                    }
+                }
+            }
+            if self.check_redundant_margins && l.starts_with('\t') {
+                for col in redundant_margins(l).iter().take(MAX_LINE_REPORTS) {
+                    report(Severity::Warning, "redundant-margin", format!("margin repeated at column {}, use fix-tags --collapse-margins ==> {}", col, l));
+                }
+            }
+            if self.flag_empty_formatting && l.starts_with('\t') {
+                for col in empty_formatting(l).iter().take(MAX_LINE_REPORTS) {
+                    report(Severity::Warning, "empty-formatting", format!("tag without text at column {}, use fix-tags --remove-empty-formatting ==> {}", col, l));
+                }
+            }
+            if self.flag_untranslated && l.starts_with('\t') && copies_headword(l, split_pos(headword).0) {
+                report(Severity::Warning, "untranslated-headword", format!("translation repeats the headword '{}' ==> {}", headword, l));
+            }
+            if self.resolve_lang_ids && l.starts_with('\t') {
+                for id in lang_ids(l).iter().take(MAX_LINE_REPORTS) {
+                    if id.parse::<u16>().ok().and_then(lang_name).is_none() {
+                        report(Severity::Warning, "unknown-lang-id", format!("unknown language id '{}' ==> {}", id, l));
+                    }
+                }
+            }
+            if let (Some(min), true) = (self.min_translation_length, l.starts_with('\t')) {
+                for trn in raw_tag_contents(l, "trn").iter().take(MAX_LINE_REPORTS) {
+                    let text = visible_text(trn, &[]);
+                    let len = text.trim().chars().count();
+                    if trn.is_empty() {
+                        report(Severity::Warning, "empty-translation", format!("empty translation [trn][/trn] ==> {}", l));
+                    } else if text.trim().is_empty() {
+                        report(Severity::Warning, "empty-translation", format!("translation [trn] has no text, only spaces or tags ==> {}", l));
+                    } else if len < min {
+                        report(Severity::Warning, "short-translation", format!("translation '{}' has {} character(s), minimum is {} ==> {}", text.trim(), len, min, l));
+                    }
+                }
+            }
+            if let (Some(max), true) = (self.max_lang_depth, l.starts_with('\t')) {
+                let (depth, col) = lang_depth(l);
+                if depth > max {
+                    report(Severity::Warning, "deep-lang-nesting", format!("[lang] blocks nested {} deep at column {}, maximum is {} ==> {}", depth, col, max, l));
+                }
+            }
+            if let Some(max) = self.max_line_length {
+                let len = visible_text(l, &[]).trim().chars().count();
+                if l.starts_with('\t') && len > max {
+                    report(Severity::Warning, "line-too-long", format!("line has {} characters, maximum is {} ==> {}", len, max, l));
+                }
+            }
+            if let (true, true, Some(level)) = (self.check_indent, l.starts_with('\t'), margin_level(l)) {
+                let actual = l.chars().take_while(|&c| c == '\t').count();
+                let expected = std::cmp::max(level as usize * tabs_per_level, 1);
+                let spaces = l[actual..].starts_with(' ');
+                if actual != expected || spaces {
+                    let found = if spaces { format!("{} and spaces", actual) } else { actual.to_string() };
+                    diags.push(Diagnostic { line: idx, byte_offset: line_offset, severity: Severity::Warning, kind: "wrong-indent",
+                        message: format!("margin [m{}] needs {} leading TAB(s), found {} ==> {}", level, expected, found, l),
+                        data: vec![("expected_tabs", expected), ("actual_tabs", actual), ("margin_level", level as usize)] });
+                }
+            }
+            if !self.rules.is_empty() {
+                let ctx = LineContext { headword, line: idx, byte_offset: line_offset, margin: margin_level(l) };
+                for r in self.rules.iter() {
+                    diags.extend(r.check(l, &ctx));
+                }
+            }
+            prev = tp;
+        }
+        if !open_tags.is_empty() {
+            report_open_tags(&mut diags, &lines, open_at, &open_tags);
+        }
+        if let Some((line, byte_offset)) = bare_cr {
+            diags.push(Diagnostic { line, byte_offset, severity: Severity::Error, kind: "bare-cr",
+                message: format!("line ends with a bare CR (old Mac style), Lingvo expects CRLF; {} line(s) end this way ==> {}", bare_cr_lines, lines[line]),
+                data: Vec::new() });
+        }
+        // Lingvo may lose the last card of a file without a line end
+        if !content.is_empty() && !content.ends_with(['\n', '\r']) {
+            let last = lines.len() - 1;
+            diags.push(Diagnostic { line: last, byte_offset: offsets[last], severity: Severity::Warning, kind: "missing-final-newline",
+                message: format!("the file does not end with a line end ==> {}", lines[last]), data: Vec::new() });
+        }
+        if !can_end(prev) {
+            let last = lines.len().saturating_sub(1);
+            diags.push(Diagnostic { line: last, byte_offset: offsets.get(last).copied().unwrap_or(self.start_offset),
+                severity: Severity::Error, kind: "unexpected-eof", message: format!("unexpected end of file after {:?}", prev), data: Vec::new() });
+        }
+        report_untranslated(&mut diags, &lines, card);
+        if self.check_script {
+            report_wrong_script(&mut diags, contents_lang, &scripts);
+        }
+        if let Some(sch) = &self.schema {
+            report_schema_violations(&mut diags, content, &offsets, sch);
+        }
+        labels.report(&mut diags);
+        if let Some(dist) = self.near_dup {
+            report_near_duplicates(&mut diags, &words.entries(&lines), &offsets, dist);
+        }
+        if self.dup_ignore_trailing_punct {
+            report_punct_duplicates(&mut diags, &words, &lines, &offsets);
+        }
+        diags.sort_by_key(|d| d.line);
+        self.adjust_severity(&mut diags);
+        diags
+    }
+}
+
+/// drops everything reported after the `max`-th error. It goes after all
+/// filters, so the errors that are kept are the ones the user asked for
+pub fn limit_errors(diags: &mut Vec<Diagnostic>, max: usize) {
+    let mut errors = 0;
+    diags.retain(|d| {
+        if d.severity == Severity::Error {
+            errors += 1;
+        }
+        errors <= max && !(errors == max && d.severity != Severity::Error)
+    });
+}
+
+// headwords with their line and the first headword line of their card. In
+// hashed mode only hashes are kept, and a found headword is compared with the
+// line the hash points to, so different headwords with the same hash are
+// never reported as duplicates. Such rare headwords are kept as strings
+struct Headwords {
+    hashed: bool,
+    exact: HashMap<String, (usize, usize)>,
+    hashes: HashMap<u64, (usize, usize)>,
+}
+
+fn headword_hash(s: &str) -> u64 {
+    let mut h = DefaultHasher::new();
+    s.hash(&mut h);
+    h.finish()
+}
+
+impl Headwords {
+    fn new(hashed: bool) -> Headwords {
+        Headwords { hashed, exact: HashMap::new(), hashes: HashMap::new() }
+    }
+
+    fn get(&self, s: &str, lines: &[&str]) -> Option<(usize, usize)> {
+        if self.hashed {
+            if let Some(v) = self.hashes.get(&headword_hash(s)) {
+                if lines[v.0] == s {
+                    return Some(*v);
+                }
+            }
+        }
+        self.exact.get(s).copied()
+    }
+
+    // must be called only for headwords that `get` has not found
+    fn insert(&mut self, s: &str, v: (usize, usize)) {
+        if self.hashed {
+            if let Entry::Vacant(e) = self.hashes.entry(headword_hash(s)) {
+                e.insert(v);
+                return;
+            }
+        }
+        self.exact.insert(s.to_string(), v);
+    }
+
+    // all headwords and their lines
+    fn entries<'a>(&'a self, lines: &[&'a str]) -> Vec<(&'a str, usize)> {
+        let mut res: Vec<(&str, usize)> = self.exact.iter().map(|(w, (idx, _))| (w.as_str(), *idx)).collect();
+        res.extend(self.hashes.values().map(|(idx, _)| (lines[*idx], *idx)));
+        res
+    }
+}
+
+// one way to write a label: the text, how many times it is used, and the
+// line and offset of its first use
+struct LabelForm {
+    text: String,
+    count: usize,
+    line: usize,
+    offset: usize,
+}
+
+// labels grouped by letters and digits they consist of: `n.`, `n`, and `N`
+// are the same label
+struct Labels {
+    groups: HashMap<String, Vec<LabelForm>>,
+}
+
+impl Labels {
+    fn new() -> Labels {
+        Labels { groups: HashMap::new() }
+    }
+
+    fn add(&mut self, text: String, line: usize, offset: usize) {
+        let key: String = text.chars().filter(|c| c.is_alphanumeric()).flat_map(|c| c.to_lowercase()).collect();
+        if key.is_empty() {
+            return;
+        }
+        let forms = self.groups.entry(key).or_default();
+        match forms.iter_mut().find(|f| f.text == text) {
+            Some(f) => f.count += 1,
+            None => forms.push(LabelForm { text, count: 1, line, offset }),
+        }
+    }
+
+    // every form but the most used one is reported where it appears first
+    fn report(&self, diags: &mut Vec<Diagnostic>) {
+        for forms in self.groups.values().filter(|f| f.len() > 1) {
+            let best = forms.iter().rev().max_by_key(|f| f.count).unwrap();
+            for f in forms.iter().filter(|f| f.text != best.text) {
+                diags.push(Diagnostic { line: f.line, byte_offset: f.offset, severity: Severity::Warning, kind: "abbrev-inconsistency",
+                    message: format!("label '{}' ({} times) is also written as '{}' ({} times), consider using one form",
+                        f.text, f.count, best.text, best.count), data: Vec::new() });
+            }
+        }
+    }
+}
+
+// removes tags with the given names, so the rest of the line can be checked
+fn strip_tags(s: &str, names: &[String]) -> String {
+    let mut res = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('[') {
+        let escaped = rest[..start].ends_with('\\');
+        let end = match rest[start..].find(']') {
+            Some(e) if !escaped => start + e + 1,
+            _ => {
+                res.push_str(&rest[..start + 1]);
+                rest = &rest[start + 1..];
+                continue;
+            }
+        };
+        let inner = &rest[start + 1..end - 1];
+        let name = inner.trim_start_matches('/').split(' ').next().unwrap_or("");
+        res.push_str(&rest[..start]);
+        if !names.iter().any(|n| n == name) {
+            res.push_str(&rest[start..end]);
+        }
+        rest = &rest[end..];
+    }
+    res.push_str(rest);
+    res
+}
+
+// reports headwords that differ by at most `dist` edits. To avoid comparing
+// every pair, headwords are bucketed by the first letter and only the words
+// with close lengths are compared
+fn report_near_duplicates(diags: &mut Vec<Diagnostic>, words: &[(&str, usize)], offsets: &[usize], dist: usize) {
+    let mut buckets: HashMap<char, Vec<(Vec<char>, usize)>> = HashMap::new();
+    for (w, idx) in words.iter() {
+        let chars: Vec<char> = index_key(w).chars().collect();
+        if let Some(c) = chars.first() {
+            buckets.entry(*c).or_default().push((chars, *idx));
+        }
+    }
+    for bucket in buckets.values_mut() {
+        bucket.sort_by_key(|(w, idx)| (w.len(), *idx));
+        for (i, (w1, idx1)) in bucket.iter().enumerate() {
+            for (w2, idx2) in bucket[i + 1..].iter() {
+                if w2.len() - w1.len() > dist {
+                    break;
+                }
+                if edit_distance(w1, w2) <= dist {
+                    let (first, second) = if idx1 < idx2 { (*idx1, *idx2) } else { (*idx2, *idx1) };
+                    diags.push(Diagnostic { line: second, byte_offset: offsets[second], severity: Severity::Warning, kind: "near-duplicate",
+                        message: format!("possible duplicate of the keyword at {}", first), data: Vec::new() });
+                }
+            }
+        }
+    }
+}
+
+// reports headwords of different cards that are the same without trailing
+// punctuation. Every headword is reported once, with the first of them
+fn report_punct_duplicates(diags: &mut Vec<Diagnostic>, words: &Headwords, lines: &[&str], offsets: &[usize]) {
+    let mut entries = words.entries(lines);
+    entries.sort_by_key(|(_, idx)| *idx);
+    let mut seen: HashMap<&str, (usize, usize)> = HashMap::new();
+    for (w, idx) in entries {
+        let card = words.get(w, lines).map_or(idx, |(_, card)| card);
+        let key = w.trim_end_matches(['.', ',', ';', ':']).trim_end();
+        match seen.get(key) {
+            None => {
+                seen.insert(key, (idx, card));
+            }
+            Some((first, first_card)) if *first_card != card => {
+                diags.push(Diagnostic { line: idx, byte_offset: offsets[idx], severity: Severity::Warning, kind: "punct-duplicate",
+                    message: format!("{} differs from the keyword at {} only by trailing punctuation", w, first), data: Vec::new() });
+            }
+            Some(_) => {},
+        }
+    }
+}
+
+// warns if the most of card texts is written in another script than the
+// contents language uses: it usually means that the file is reversed
+fn report_wrong_script(diags: &mut Vec<Diagnostic>, header: Option<(usize, usize, &str)>, counts: &HashMap<script::Script, usize>) {
+    let (line, byte_offset, lang) = match header {
+        None => return,
+        Some(h) => h,
+    };
+    let expected = match script::language_script(lang) {
+        None => return,
+        Some(s) => s,
+    };
+    let total: usize = counts.values().sum();
+    let wrong: usize = counts.iter().filter(|(s, _)| !script::script_matches(expected, **s)).map(|(_, n)| *n).sum();
+    if total == 0 || wrong * 2 <= total {
+        return;
+    }
+    let (most, _) = counts.iter().max_by_key(|(_, n)| **n).unwrap();
+    diags.push(Diagnostic { line, byte_offset, severity: Severity::Warning, kind: "wrong-script",
+        message: format!("{}% of text is not in {:?} script expected for {} (mostly {:?})",
+            wrong * 100 / total, expected, lang, most), data: Vec::new() });
+}
+
+fn report_schema_violations(diags: &mut Vec<Diagnostic>, content: &str, offsets: &[usize], sch: &Schema) {
+    for c in card::cards(content).flatten() {
+        for msg in sch.validate(&c) {
+            diags.push(Diagnostic { line: c.line, byte_offset: offsets[c.line], severity: Severity::Error, kind: "schema-violation",
+                message: format!("schema violation: {} ==> {}", msg, c.headwords[0]), data: Vec::new() });
+        }
+    }
+}
+
+// a translation [trn], or one of its comma or semicolon separated variants,
+// is the headword itself
+fn copies_headword(l: &str, headword: &str) -> bool {
+    let key = index_key(headword);
+    if key.is_empty() {
+        return false;
+    }
+    tag_contents(l, "trn").iter().any(|t| {
+        let text = fold_case(&visible_text(t, &["com"]));
+        text.split([',', ';']).any(|v| v.trim() == key)
+    })
+}
+
+fn report_open_tags(diags: &mut Vec<Diagnostic>, lines: &[&str], (line, byte_offset): (usize, usize), open: &[TagKind]) {
+    let names: Vec<String> = open.iter().map(|k| format!("[{}]", k.name())).collect();
+    diags.push(Diagnostic { line, byte_offset, severity: Severity::Error, kind: "unclosed-tag",
+        message: format!("{} not closed before the end of the card ==> {}", names.join(", "), lines[line]), data: Vec::new() });
+}
+
+fn report_untranslated(diags: &mut Vec<Diagnostic>, lines: &[&str], card: Option<(usize, usize, bool)>) {
+    if let Some((line, byte_offset, false)) = card {
+        diags.push(Diagnostic { line, byte_offset, severity: Severity::Warning, kind: "no-translation",
+            message: format!("card has no translation ==> {}", lines[line]), data: Vec::new() });
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a dictionary with the header and the given cards, its lines are
+    // numbered from 4
+    fn dict(cards: &str) -> String {
+        format!("#NAME \"Test\"\r\n#INDEX_LANGUAGE \"English\"\r\n#CONTENTS_LANGUAGE \"Russian\"\r\n\r\n{}", cards)
+    }
+
+    // lines and kinds of the problems found
+    fn problems(checker: &Checker, cards: &str) -> Vec<(usize, &'static str)> {
+        checker.check(&dict(cards)).iter().map(|d| (d.line, d.kind)).collect()
+    }
+
+    #[test]
+    fn tab_in_headword() {
+        let checker = Checker::new();
+        assert_eq!(problems(&checker, "foo\tbar\r\n\t[m1][trn]x[/trn][/m]\r\n"), vec![(4, "tab-in-headword")]);
+        assert_eq!(problems(&checker, "foo bar\r\n\t[m1][trn]x[/trn][/m]\r\n"), Vec::new());
+    }
+
+    #[test]
+    fn byte_offsets_of_lines() {
+        // Cyrillic letters take 2 bytes in UTF-16, the emoji takes 4
+        let cards = "кот\r\n\t[m1][x]кошка[/m]\r\n😀\r\n\t[m1][y][/m]\r\n";
+        let text = dict(cards);
+        let diags = Checker::new().start_offset(2).check(&text);
+        let offsets: Vec<(usize, usize)> = diags.iter().map(|d| (d.line, d.byte_offset)).collect();
+        // the BOM, 4 header lines of 14, 27, 30 and 2 characters, `кот\r\n`
+        assert_eq!(offsets[0], (5, 2 + 2 * (14 + 27 + 30 + 2 + 5)));
+        let utf16_len = |n: usize| text.split_inclusive('\n').take(n).map(|l| l.encode_utf16().count() * 2).sum::<usize>();
+        assert_eq!(offsets, vec![(5, 2 + utf16_len(5)), (7, 2 + utf16_len(7))]);
+        // a BOM of the text itself is counted as well
+        let with_bom = Checker::new().check(&format!("\u{feff}{}", text));
+        assert_eq!(with_bom.iter().map(|d| d.byte_offset).collect::<Vec<_>>(), vec![offsets[0].1, offsets[1].1]);
+    }
+
+    #[test]
+    fn same_languages() {
+        let monolingual = "#NAME \"Test\"\r\n#INDEX_LANGUAGE \"English\"\r\n#CONTENTS_LANGUAGE \"English\"\r\n\r\ncat\r\n\t[m1]a pet[/m]\r\n";
+        let diags: Vec<(usize, &str, Severity)> = Checker::new().check(monolingual).iter().map(|d| (d.line, d.kind, d.severity)).collect();
+        assert_eq!(diags, vec![(2, "same-languages", Severity::Warning)]);
+        assert_eq!(Checker::new().allow_monolingual(true).check(monolingual), Vec::new());
+        assert_eq!(problems(&Checker::new(), "cat\r\n\t[m1][trn]кошка[/trn][/m]\r\n"), Vec::new());
+    }
+
+    #[test]
+    fn headword_special_chars() {
+        let checker = Checker::new();
+        assert_eq!(problems(&checker, "colo(u)r {to }go\r\n\t[m1][trn]цвет[/trn][/m]\r\n"), Vec::new());
+        assert_eq!(problems(&checker, "a}b{c\r\n\t[m1][trn]x[/trn][/m]\r\n"), vec![(4, "headword-special-char"), (4, "headword-special-char")]);
+        let diags = checker.check(&dict("a}b\r\n\t[m1][trn]x[/trn][/m]\r\n"));
+        assert_eq!(diags[0].message, "unbalanced '}' in headword at column 2 ==> a}b");
+        // the part-of-speech label is not a part of the headword
+        assert_eq!(problems(&checker, "bank [p]n[/p]\r\n\t[m1][trn]берег[/trn][/m]\r\n"), Vec::new());
+    }
+
+    #[test]
+    fn require_translation() {
+        let checker = Checker::new().require_translation(true);
+        assert_eq!(problems(&checker, "cat\r\n\t[m1][trn]кошка[/trn][/m]\r\n"), Vec::new());
+        assert_eq!(problems(&checker, "cat\r\n\t[m1]кошка[/m]\r\n"), Vec::new());
+        let comments = "cat\r\n\t[m1][com]a pet[/com][/m]\r\n\t[m2][ex]a black cat[/ex][/m]\r\n";
+        assert_eq!(problems(&checker, comments), vec![(4, "no-translation")]);
+        assert_eq!(problems(&Checker::new(), comments), Vec::new());
+    }
+
+    #[test]
+    fn near_duplicates() {
+        let cards = "receive\r\n\t[m1][trn]получать[/trn][/m]\r\nrecieve\r\n\t[m1][trn]получать[/trn][/m]\r\nremove\r\n\t[m1][trn]удалять[/trn][/m]\r\n";
+        assert_eq!(problems(&Checker::new().near_dup(Some(1)), cards), vec![(6, "near-duplicate")]);
+        let diags = Checker::new().near_dup(Some(1)).check(&dict(cards));
+        assert_eq!(diags[0].message, "possible duplicate of the keyword at 4");
+        assert_eq!(problems(&Checker::new(), cards), Vec::new());
+    }
+
+    #[test]
+    fn wrong_script() {
+        let checker = Checker::new().check_script(true);
+        let latin = "cat\r\n\t[m1][trn]a pet[/trn][/m]\r\ndog\r\n\t[m1][trn]a friend[/trn][/m]\r\n";
+        let diags = checker.check(&dict(latin));
+        assert_eq!(diags.iter().map(|d| (d.line, d.kind)).collect::<Vec<_>>(), vec![(2, "wrong-script")]);
+        assert_eq!(diags[0].message, "100% of text is not in Cyrillic script expected for Russian (mostly Latin)");
+        // comments and examples are not sampled
+        assert_eq!(problems(&checker, "cat\r\n\t[m1][trn]кошка[/trn] [com]a pet[/com][/m]\r\n"), Vec::new());
+        assert_eq!(problems(&Checker::new(), latin), Vec::new());
+    }
+
+    #[test]
+    fn optional_parts_of_headwords() {
+        let diags = Checker::new().check(&dict("word(s\r\n\t[m1][trn]слово[/trn][/m]\r\n"));
+        assert_eq!(diags.iter().map(|d| (d.line, d.message.as_str())).collect::<Vec<_>>(),
+            vec![(4, "unbalanced '(' in headword at column 5 ==> word(s")]);
+        let checker = Checker::new().expand_optional(true);
+        let diags = checker.check(&dict("colo(u)r\r\n\t[m1][trn]цвет[/trn][/m]\r\n"));
+        assert_eq!(diags.iter().map(|d| (d.line, d.severity, d.message.as_str())).collect::<Vec<_>>(),
+            vec![(4, Severity::Info, "colo(u)r indexed as 'colour' and 'color'")]);
+    }
+
+    #[test]
+    fn reports_of_a_line_are_limited() {
+        let headword = "}".repeat(100_000);
+        let diags = Checker::new().check(&dict(&format!("{}\r\n\t[m1][trn]x[/trn][/m]\r\n", headword)));
+        assert_eq!(diags.len(), MAX_LINE_REPORTS + 1);
+        assert_eq!(diags[MAX_LINE_REPORTS].message, "99990 more special characters in headword");
+    }
+
+    #[test]
+    fn bom_inside_text() {
+        let text = format!("\u{feff}{}", dict("cat\r\n\t[m1][trn]\u{feff}кошка[/trn][/m]\r\n"));
+        let diags = Checker::new().check(&text);
+        assert_eq!(diags.iter().map(|d| (d.line, d.message.as_str())).collect::<Vec<_>>(),
+            vec![(5, "byte order mark inside the text at column 11 ==> \t[m1][trn]\u{feff}кошка[/trn][/m]")]);
+    }
+
+    #[test]
+    fn tab_inside_tag() {
+        let diags = Checker::new().check(&dict("cat\r\n\t[m1][trn]кошка[/trn] [com]a\tpet[/com][/m]\r\n"));
+        assert_eq!(diags.iter().map(|d| (d.line, d.severity, d.message.as_str())).collect::<Vec<_>>(),
+            vec![(5, Severity::Warning, "tab inside tag at column 29 ==> \t[m1][trn]кошка[/trn] [com]a\tpet[/com][/m]")]);
+    }
+
+    #[test]
+    fn unexpected_eof() {
+        let checker = Checker::new();
+        let diags = checker.check(&dict("cat\r\n\t[m1]кошка[/m]\r\ndog\r\n"));
+        assert_eq!(diags.iter().map(|d| (d.line, d.kind, d.message.as_str())).collect::<Vec<_>>(),
+            vec![(6, "unexpected-eof", "unexpected end of file after Key")]);
+    }
+
+    #[test]
+    fn no_duplicate_check() {
+        let cards = "cat\r\n\t[m1][trn]кошка[/trn][/m]\r\ncat\r\n\t[m1][x]кот[/m]\r\n";
+        assert_eq!(problems(&Checker::new(), cards), vec![(6, "duplicate"), (7, "unknown-tag")]);
+        // other checks still run
+        assert_eq!(problems(&Checker::new().duplicates(false), cards), vec![(7, "unknown-tag")]);
+    }
+
+    #[test]
+    fn builder_options() {
+        let cards = "cat\r\n\t[m1][sup]2[/sup] [x]a[/x][/m]\r\ndog\r\n\t[m1][y]b[/y][/m]\r\n\t[m1][z]c[/z][/m]\r\n";
+        assert_eq!(problems(&Checker::new(), cards), vec![(5, "unknown-tag"), (7, "unknown-tag"), (8, "unknown-tag")]);
+        let checker = Checker::new().allow_tag("[sup]").allow_tag("x");
+        assert_eq!(problems(&checker, cards), vec![(7, "unknown-tag"), (8, "unknown-tag")]);
+    }
+
+    #[test]
+    fn error_limit() {
+        let cards = "cat\r\n\t[m1][x]a[/m]\r\ndog\r\n\t[m1][com]b[/com][/m]\r\ncat\r\n\t[m1]c[/m]\r\n";
+        let diags = Checker::new().require_translation(true).check(&dict(cards));
+        let kinds = |diags: &[Diagnostic]| diags.iter().map(|d| (d.line, d.kind)).collect::<Vec<_>>();
+        assert_eq!(kinds(&diags), vec![(5, "unknown-tag"), (6, "no-translation"), (8, "duplicate")]);
+        let mut limited = diags.clone();
+        limit_errors(&mut limited, 2);
+        assert_eq!(limited, diags);
+        // nothing is reported after the last error, warnings included
+        limit_errors(&mut limited, 1);
+        assert_eq!(kinds(&limited), vec![(5, "unknown-tag")]);
+    }
+
+    #[test]
+    fn bare_urls() {
+        let checker = Checker::new().warn_bare_urls(true);
+        let cards = "cat\r\n\t[m1][trn]кошка, https://example.com/cat[/trn][/m]\r\ndog\r\n\t[m1][trn]собака[/trn] [url]https://example.com/dog[/url][/m]\r\n";
+        let diags = checker.check(&dict(cards));
+        assert_eq!(diags.iter().map(|d| (d.line, d.severity, d.message.as_str())).collect::<Vec<_>>(),
+            vec![(5, Severity::Warning, "link https://example.com/cat is not wrapped in [url]...[/url] ==> \t[m1][trn]кошка, https://example.com/cat[/trn][/m]")]);
+        assert_eq!(problems(&Checker::new(), cards), Vec::new());
+    }
+
+    #[test]
+    fn untranslated_examples() {
+        let checker = Checker::new().check_examples(true);
+        let cards = "cat\r\n\t[m1][trn]кошка[/trn][/m]\r\n\t[m2][ex]a black cat — чёрная кошка[/ex][/m]\r\n\t[m2][ex]a lazy cat[/ex][/m]\r\n";
+        let diags = checker.check(&dict(cards));
+        assert_eq!(diags.iter().map(|d| (d.line, d.severity, d.message.as_str())).collect::<Vec<_>>(),
+            vec![(7, Severity::Warning, "example 'a lazy cat' has no translation ==> \t[m2][ex]a lazy cat[/ex][/m]")]);
+        assert_eq!(problems(&Checker::new(), cards), Vec::new());
+    }
+
+    #[test]
+    fn variant_collision() {
+        // the strict order reports consecutive headwords, --lenient accepts them
+        let checker = Checker::new().lenient(true);
+        // a variant of the first card is the headword of the second one
+        let cards = "colour\r\ncolor\r\n\t[m1][trn]цвет[/trn][/m]\r\ncolor\r\n\t[m1][trn]раскрашивать[/trn][/m]\r\n";
+        let diags = checker.check(&dict(cards));
+        assert_eq!(diags.iter().map(|d| (d.line, d.kind, d.message.as_str())).collect::<Vec<_>>(),
+            vec![(7, "variant-collision", "color is also a headword of the card at 4")]);
+        // and the other way around
+        let cards = "color\r\n\t[m1][trn]цвет[/trn][/m]\r\ncolour\r\ncolor\r\n\t[m1][trn]раскрашивать[/trn][/m]\r\n";
+        assert_eq!(problems(&checker, cards), vec![(7, "variant-collision")]);
+        // repeated primary headwords are plain duplicates, a variant repeated in one card too
+        assert_eq!(problems(&checker, "cat\r\n\t[m1]a[/m]\r\ncat\r\n\t[m1]b[/m]\r\n"), vec![(6, "duplicate")]);
+        assert_eq!(problems(&checker, "cat\r\nkitty\r\nkitty\r\n\t[m1]a[/m]\r\n"), vec![(6, "duplicate")]);
+    }
+
+    #[test]
+    fn headwords_of_included_files() {
+        let checker = Checker::new().include("other.dsl", &[String::from("dog")]);
+        let diags = checker.check(&dict("cat\r\n\t[m1]кошка[/m]\r\ndog\r\n\t[m1]собака[/m]\r\n"));
+        assert_eq!(diags.iter().map(|d| (d.line, d.message.as_str())).collect::<Vec<_>>(), vec![(6, "dog already exists in other.dsl")]);
+        assert_eq!(problems(&checker.duplicates(false), "dog\r\n\t[m1]собака[/m]\r\n"), Vec::new());
+    }
+
+    #[test]
+    fn abbrev_consistency() {
+        let checker = Checker::new().abbrev_consistency(true);
+        let cards = "cat\r\n\t[m1][p]n.[/p] кошка[/m]\r\ndog\r\n\t[m1][p]n[/p] собака[/m]\r\nfox\r\n\t[m1][p]n.[/p] лиса[/m]\r\nrun\r\n\t[m1][p]v.[/p] бежать[/m]\r\n";
+        let diags = checker.check(&dict(cards));
+        assert_eq!(diags.iter().map(|d| (d.line, d.severity, d.message.as_str())).collect::<Vec<_>>(),
+            vec![(7, Severity::Warning, "label 'n' (1 times) is also written as 'n.' (2 times), consider using one form")]);
+        // with a tie the form seen first wins
+        let diags = checker.check(&dict("cat\r\n\t[m1][p]N[/p] кошка[/m]\r\ndog\r\n\t[m1][p]n.[/p] собака[/m]\r\n"));
+        assert_eq!(diags.iter().map(|d| d.line).collect::<Vec<_>>(), vec![7]);
+        assert_eq!(problems(&Checker::new(), cards), Vec::new());
+    }
+
+    #[test]
+    fn hashed_headwords() {
+        let cards = "cat\r\n\t[m1]кошка[/m]\r\ndog\r\n\t[m1]собака[/m]\r\ncat\r\n\t[m1]кот[/m]\r\n";
+        let exact = problems(&Checker::new(), cards);
+        assert_eq!(exact, vec![(8, "duplicate")]);
+        assert_eq!(problems(&Checker::new().hash_headwords(true), cards), exact);
+        let mut words = Headwords::new(true);
+        let lines = ["cat", "dog"];
+        words.insert("cat", (0, 0));
+        assert_eq!(words.get("cat", &lines), Some((0, 0)));
+        assert_eq!(words.get("dog", &lines), None);
+    }
+
+    #[test]
+    fn editor_comments() {
+        let cards = "cat\r\n## TODO\r\n\t[m1]кошка[/m]\r\n## TODO\r\n\t[m1][ex]a cat[/ex][/m]\r\n; old card\r\ndog\r\n\t[m1]собака[/m]\r\n";
+        assert_eq!(problems(&Checker::new(), cards), Vec::new());
+        let header = "#NAME \"Test\"\r\n## TODO\r\n#INDEX_LANGUAGE \"English\"\r\n#CONTENTS_LANGUAGE \"Russian\"\r\n\r\ncat\r\n\t[m1]кошка[/m]\r\n";
+        assert_eq!(Checker::new().check(header), Vec::new());
+        let diags = Checker::new().verbose(true).check(&dict(cards));
+        assert_eq!(diags.iter().map(|d| (d.line, d.severity, d.kind)).collect::<Vec<_>>(),
+            vec![(5, Severity::Info, "editor-comment"), (7, Severity::Info, "editor-comment"), (9, Severity::Info, "editor-comment")]);
+        assert_eq!(diags[0].message, "editor comment ==> ## TODO");
+        assert_eq!(problems(&Checker::new(), "cat\r\n## [b]TODO[/b]\r\n\t[m1]кошка[/m]\r\n"), vec![(5, "tag-in-comment")]);
+    }
+
+    #[test]
+    fn line_too_long() {
+        let checker = Checker::new().max_line_length(Some(10));
+        // tags do not count, only the visible text
+        assert_eq!(problems(&checker, "cat\r\n\t[m1][trn]кошка кот[/trn][/m]\r\n"), Vec::new());
+        assert_eq!(problems(&checker, "cat\r\n\t[m1][trn]кошка, кот[/trn][/m]\r\n"), Vec::new());
+        let diags = checker.check(&dict("cat\r\n\t[m1][trn]кошка, кот![/trn][/m]\r\n"));
+        assert_eq!(diags.iter().map(|d| (d.line, d.severity, d.kind)).collect::<Vec<_>>(), vec![(5, Severity::Warning, "line-too-long")]);
+        assert!(diags[0].message.starts_with("line has 11 characters, maximum is 10"), "{}", diags[0].message);
+        // headwords are not checked
+        assert_eq!(problems(&checker, "a very long headword\r\n\t[m1]x[/m]\r\n"), Vec::new());
+        assert_eq!(problems(&Checker::new(), "cat\r\n\t[m1][trn]кошка, кот![/trn][/m]\r\n"), Vec::new());
+    }
+
+    #[test]
+    fn unknown_lang_ids() {
+        let checker = Checker::new().resolve_lang_ids(true);
+        assert_eq!(problems(&checker, "cat\r\n\t[m1][lang id=1033]cat[/lang][/m]\r\n"), Vec::new());
+        let diags = checker.check(&dict("cat\r\n\t[m1][lang id=1]cat[/lang] [lang id=9999]cat[/lang] [lang id=x]cat[/lang][/m]\r\n"));
+        assert_eq!(diags.iter().map(|d| (d.line, d.kind)).collect::<Vec<_>>(), vec![(5, "unknown-lang-id"), (5, "unknown-lang-id")]);
+        assert!(diags[0].message.starts_with("unknown language id '9999'"), "{}", diags[0].message);
+        assert_eq!(problems(&Checker::new(), "cat\r\n\t[m1][lang id=9999]cat[/lang][/m]\r\n"), Vec::new());
+    }
+
+    #[test]
+    fn margin_close_without_open() {
+        // such a line has no margin, so its type is invalid as well
+        assert!(problems(&Checker::new(), "cat\r\n\t[/m]кошка\r\n").contains(&(5, "margin-close-without-open")));
+        assert_eq!(problems(&Checker::new(), "cat\r\n\t[m1]кошка[/m][/m]\r\n"), vec![(5, "margin-close-without-open")]);
+    }
+
+    // reports lines that contain "TODO" with their context
+    #[derive(Debug)]
+    struct ContainsTodo;
+
+    impl LineRule for ContainsTodo {
+        fn check(&self, line: &str, ctx: &LineContext) -> Vec<Diagnostic> {
+            if !line.contains("TODO") {
+                return Vec::new();
+            }
+            vec![Diagnostic { line: ctx.line, byte_offset: ctx.byte_offset, severity: Severity::Info, kind: "todo",
+                message: format!("{} {:?}", ctx.headword, ctx.margin), data: Vec::new() }]
+        }
+    }
+
+    #[test]
+    fn custom_line_rules() {
+        let text = "#NAME \"TODO\"\r\n#INDEX_LANGUAGE \"English\"\r\n#CONTENTS_LANGUAGE \"Russian\"\r\n\r\ncat\r\n\t[m1]кошка TODO[/m]\r\n## TODO: check\r\nTODO\r\n\t[m1]a[/m]\r\n\t\t[m2]b TODO[/m]\r\n\t[com]TODO[/com]\r\n";
+        let diags = Checker::new().rule(ContainsTodo).check(text);
+        // offsets are in UTF-16 bytes; editor comments are not checked
+        assert_eq!(diags.iter().map(|d| (d.line, d.byte_offset, d.message.as_str())).collect::<Vec<_>>(), vec![
+            (0, 0, " None"),
+            (5, 156, "cat Some(1)"),
+            (7, 230, "TODO None"),
+            (9, 266, "TODO Some(2)"),
+            (10, 302, "TODO None"),
+        ]);
+        assert_eq!(Checker::new().check(text), Vec::new());
+    }
+
+    #[test]
+    fn wrong_indent() {
+        let checker = Checker::new().check_indent(true);
+        let cards = "cat\r\n\t[m1]кошка[/m]\r\n\t[m2]a pet[/m]\r\n\t\t [m2]a friend[/m]\r\n";
+        let diags = checker.check(&dict(cards));
+        assert_eq!(diags.iter().map(|d| (d.line, d.message.as_str())).collect::<Vec<_>>(), vec![
+            (6, "margin [m2] needs 2 leading TAB(s), found 1 ==> \t[m2]a pet[/m]"),
+            (7, "margin [m2] needs 2 leading TAB(s), found 2 and spaces ==> \t\t [m2]a friend[/m]"),
+        ]);
+        assert_eq!(diags[0].data, vec![("expected_tabs", 2), ("actual_tabs", 1), ("margin_level", 2)]);
+    }
+
+    #[test]
+    fn indent_of_two_tabs_per_level() {
+        let checker = Checker::new().check_indent(true);
+        // nine lines of ten follow the convention
+        let cards = "cat\r\n\t\t[m1]кошка[/m]\r\n\t\t\t\t[m2]a pet[/m]\r\n".repeat(4) + "dog\r\n\t\t[m1]собака[/m]\r\n\t\t[m2]a friend[/m]\r\n";
+        let diags = checker.duplicates(false).check(&dict(&cards));
+        assert_eq!(diags.iter().map(|d| (d.line, d.message.as_str())).collect::<Vec<_>>(),
+            vec![(18, "margin [m2] needs 4 leading TAB(s), found 2 ==> \t\t[m2]a friend[/m]")]);
+    }
+
+    #[test]
+    fn traced_line_types() {
+        let mut events = Vec::new();
+        let diags = Checker::new().check_traced(&dict("cat\r\n## note\r\n\t[m1]кошка[/m]\r\n"), &mut |ev| events.push(*ev));
+        assert_eq!(diags, Vec::new());
+        assert_eq!(events[4..], [
+            TraceEvent { line: 4, state: DState::Key, prev: Some(DState::EmptyLine), accepted: true },
+            TraceEvent { line: 5, state: DState::EditorComment, prev: None, accepted: true },
+            TraceEvent { line: 6, state: DState::M1, prev: Some(DState::Key), accepted: true },
+        ]);
+    }
+
+    #[test]
+    fn lenient_order() {
+        let text = "#NAME \"Test\"\r\n#CONTENTS_LANGUAGE \"Russian\"\r\n#INDEX_LANGUAGE \"English\"\r\ncat\r\n\t[m1]кошка[/m]\r\n\r\ndog\r\n\t[m1]собака[/m]\r\n";
+        let strict: Vec<(usize, &str)> = Checker::new().check(text).iter().map(|d| (d.line, d.kind)).collect();
+        assert_eq!(strict, vec![(1, "wrong-order"), (2, "wrong-order"), (3, "wrong-order"), (5, "wrong-order")]);
+        assert_eq!(Checker::new().lenient(true).check(text), Vec::new());
+        // tags are still checked, and a body needs a headword before it
+        let text = "#NAME \"Test\"\r\n#INDEX_LANGUAGE \"English\"\r\n#CONTENTS_LANGUAGE \"Russian\"\r\n\t[m1]lost[/m]\r\ncat\r\n\t[m1][x]кошка[/m]\r\n";
+        let diags: Vec<(usize, &str)> = Checker::new().lenient(true).check(text).iter().map(|d| (d.line, d.kind)).collect();
+        assert_eq!(diags, vec![(3, "wrong-order"), (5, "unknown-tag")]);
+    }
+
+    #[test]
+    fn paragraphs_of_a_sense() {
+        let checker = Checker::new();
+        assert_eq!(problems(&checker, "cat\r\n\t[m1]кошка[/m]\r\n\t[m1]домашнее животное[/m]\r\ndog\r\n\t[m1]собака[/m]\r\n"), Vec::new());
+        // an indented blank line is an empty paragraph of the card
+        let cards = "cat\r\n\t[m1]кошка[/m]\r\n\t\r\n\t[m1]домашнее животное[/m]\r\ndog\r\n\t[m1]собака[/m]\r\n";
+        assert_eq!(problems(&checker, cards), Vec::new());
+        assert_eq!(line_type("\t"), DState::Text);
+        assert_eq!(line_type("\t\t "), DState::Text);
+        // a really empty line ends the card
+        let diags = checker.check(&dict("cat\r\n\t[m1]кошка[/m]\r\n\r\n\t[m1]домашнее животное[/m]\r\n"));
+        assert_eq!(diags.iter().map(|d| (d.line, d.kind)).collect::<Vec<_>>(), vec![(6, "wrong-order"), (7, "empty-line-in-card")]);
+        assert!(diags[1].message.starts_with("card body goes on after an empty line, indent the empty line with a TAB"), "{}", diags[1].message);
+    }
+
+    #[test]
+    fn untranslated_headwords() {
+        let checker = Checker::new().flag_untranslated(true);
+        assert_eq!(problems(&checker, "cat\r\n\t[m1][trn]кошка[/trn][/m]\r\n"), Vec::new());
+        // the headword is only a part of the translation
+        assert_eq!(problems(&checker, "cat\r\n\t[m1][trn]cat-o'-nine-tails, плеть[/trn][/m]\r\n"), Vec::new());
+        let diags = checker.check(&dict("Cat\r\n\t[m1][trn]CAT[/trn][/m]\r\n\t[m1][trn]кошка; cat [com](informal)[/com][/trn][/m]\r\n"));
+        assert_eq!(diags.iter().map(|d| (d.line, d.severity, d.kind)).collect::<Vec<_>>(),
+            vec![(5, Severity::Warning, "untranslated-headword"), (6, Severity::Warning, "untranslated-headword")]);
+        assert_eq!(diags[0].message, "translation repeats the headword 'Cat' ==> \t[m1][trn]CAT[/trn][/m]");
+        // a part of speech label is not a part of the headword
+        assert_eq!(problems(&checker, "bank [p]n[/p]\r\n\t[m1][trn]bank[/trn][/m]\r\n"), vec![(5, "untranslated-headword")]);
+        assert_eq!(problems(&Checker::new(), "cat\r\n\t[m1][trn]cat[/trn][/m]\r\n"), Vec::new());
+    }
+
+    #[test]
+    fn multiline_tags() {
+        let cards = "cat\r\n\t[m1]кошка[/m]\r\n\t[com]a long\r\n\tnote[/com]\r\n";
+        assert_eq!(problems(&Checker::new().multiline_tag("com"), cards), Vec::new());
+        assert_eq!(problems(&Checker::new(), cards), vec![(6, "unclosed-tag"), (7, "superfluous-closing-tag")]);
+        // tags that are not listed must be closed on their line
+        let checker = Checker::new().multiline_tag("com").multiline_tag("x");
+        let diags = checker.check(&dict("cat\r\n\t[m1]кошка[/m]\r\n\t[com][b]a long\r\n\tnote[/b][/com]\r\n"));
+        assert_eq!(diags.iter().map(|d| (d.line, d.kind)).collect::<Vec<_>>(), vec![(6, "unclosed-tag"), (7, "superfluous-closing-tag")]);
+        assert!(diags[0].message.starts_with("unclosed tags"), "{}", diags[0].message);
+    }
+
+    #[test]
+    fn missing_final_newline() {
+        assert_eq!(problems(&Checker::new(), "cat\r\n\t[m1]кошка[/m]\r\n"), Vec::new());
+        assert_eq!(problems(&Checker::new(), "cat\n\t[m1]кошка[/m]\n"), Vec::new());
+        let diags = Checker::new().check(&dict("cat\r\n\t[m1]кошка[/m]"));
+        assert_eq!(diags.iter().map(|d| (d.line, d.severity, d.message.as_str())).collect::<Vec<_>>(),
+            vec![(5, Severity::Warning, "the file does not end with a line end ==> \t[m1]кошка[/m]")]);
+    }
+
+    #[test]
+    fn redundant_margins() {
+        let checker = Checker::new().check_redundant_margins(true);
+        let diags = checker.check(&dict("cat\r\n\t[m1][m1]word[/m][/m]\r\n\t[m1]кошка[/m]\r\n"));
+        assert_eq!(diags.iter().map(|d| (d.line, d.severity, d.message.as_str())).collect::<Vec<_>>(),
+            vec![(5, Severity::Warning, "margin repeated at column 6, use fix-tags --collapse-margins ==> \t[m1][m1]word[/m][/m]")]);
+        assert_eq!(problems(&Checker::new(), "cat\r\n\t[m1][m1]word[/m][/m]\r\n"), Vec::new());
+    }
+
+    #[test]
+    fn bare_cr_line_ends() {
+        let diags = Checker::new().check(&dict("cat\r\t[m1]кошка[/m]\r\ndog\r\n\t[m1]собака[/m]\r"));
+        assert_eq!(diags.iter().map(|d| (d.line, d.kind)).collect::<Vec<_>>(), vec![(4, "bare-cr")]);
+        assert!(diags[0].message.contains("2 line(s) end this way ==> cat"), "{}", diags[0].message);
+        assert_eq!(problems(&Checker::new(), "cat\r\n\t[m1]кошка[/m]\r\n"), Vec::new());
+    }
+
+    #[test]
+    fn forbidden_tags() {
+        let checker = Checker::new().forbid_tag("b").forbid_tag("[p]");
+        let cards = "cat\r\n\t[m1][b]кошка[/b], [b]кот[/b][/m]\r\n\t[m1][i]pet[/i][/m]\r\ndog\r\n\t[m1][p]n[/p] [b]собака[/b][/m]\r\n";
+        let diags = checker.check(&dict(cards));
+        assert_eq!(diags.iter().map(|d| (d.line, d.severity, d.message.as_str())).collect::<Vec<_>>(), vec![
+            (5, Severity::Error, "forbidden tag [b] at column 6 ==> \t[m1][b]кошка[/b], [b]кот[/b][/m]"),
+            (5, Severity::Error, "forbidden tag [b] at column 20 ==> \t[m1][b]кошка[/b], [b]кот[/b][/m]"),
+            (8, Severity::Error, "forbidden tag [b] at column 15 ==> \t[m1][p]n[/p] [b]собака[/b][/m]"),
+            (8, Severity::Error, "forbidden tag [p] at column 6 ==> \t[m1][p]n[/p] [b]собака[/b][/m]"),
+        ]);
+        assert_eq!(problems(&Checker::new(), cards), Vec::new());
+    }
+
+    #[test]
+    fn short_translations() {
+        let checker = Checker::new().min_translation_length(Some(3));
+        assert_eq!(problems(&checker, "cat\r\n\t[m1][trn]кот[/trn][/m]\r\n"), Vec::new());
+        assert_eq!(problems(&checker, "cat\r\n\t[m1][trn] [b]кот[/b] [/trn][/m]\r\n"), Vec::new());
+        let diags = checker.check(&dict("cat\r\n\t[m1][trn]ко[/trn] [trn][/trn] [trn] [/trn] [trn][i] [/i][/trn][/m]\r\n"));
+        let line = "\t[m1][trn]ко[/trn] [trn][/trn] [trn] [/trn] [trn][i] [/i][/trn][/m]";
+        assert_eq!(diags.iter().map(|d| (d.kind, d.message.clone())).collect::<Vec<_>>(), vec![
+            ("short-translation", format!("translation 'ко' has 2 character(s), minimum is 3 ==> {}", line)),
+            ("empty-translation", format!("empty translation [trn][/trn] ==> {}", line)),
+            ("empty-translation", format!("translation [trn] has no text, only spaces or tags ==> {}", line)),
+            ("empty-translation", format!("translation [trn] has no text, only spaces or tags ==> {}", line)),
+        ]);
+        // empty translations are reported with any minimum
+        assert_eq!(problems(&Checker::new().min_translation_length(Some(0)), "cat\r\n\t[m1][trn][/trn][/m]\r\n"), vec![(5, "empty-translation")]);
+        assert_eq!(problems(&Checker::new(), "cat\r\n\t[m1][trn][/trn][/m]\r\n"), Vec::new());
+    }
+
+    #[test]
+    fn punct_duplicates() {
+        let checker = Checker::new().dup_ignore_trailing_punct(true);
+        let cards = "word\r\n\t[m1]слово[/m]\r\nword.\r\n\t[m1]слово[/m]\r\nwords\r\n\t[m1]слова[/m]\r\nword;:\r\n\t[m1]слово[/m]\r\n";
+        let diags = checker.check(&dict(cards));
+        assert_eq!(diags.iter().map(|d| (d.line, d.severity, d.message.as_str())).collect::<Vec<_>>(), vec![
+            (6, Severity::Warning, "word. differs from the keyword at 4 only by trailing punctuation"),
+            (10, Severity::Warning, "word;: differs from the keyword at 4 only by trailing punctuation"),
+        ]);
+        // exact duplicates are still errors of their own
+        let cards = "word\r\n\t[m1]слово[/m]\r\nword\r\n\t[m1]слово[/m]\r\n";
+        assert_eq!(problems(&checker, cards), vec![(6, "duplicate")]);
+        assert_eq!(problems(&Checker::new(), "word\r\n\t[m1]слово[/m]\r\nword.\r\n\t[m1]слово[/m]\r\n"), Vec::new());
+    }
+
+    #[test]
+    fn deep_lang_nesting() {
+        let checker = Checker::new().max_lang_depth(Some(1));
+        assert_eq!(problems(&checker, "cat\r\n\t[m1][lang id=1]a[/lang] [lang id=2]b[/lang][/m]\r\n"), Vec::new());
+        let diags = checker.check(&dict("cat\r\n\t[m1][lang id=1]a [lang id=2]b[/lang][/lang][/m]\r\n"));
+        assert_eq!(diags.iter().map(|d| (d.line, d.severity, d.message.as_str())).collect::<Vec<_>>(), vec![
+            (5, Severity::Warning, "[lang] blocks nested 2 deep at column 19, maximum is 1 ==> \t[m1][lang id=1]a [lang id=2]b[/lang][/lang][/m]"),
+        ]);
+        assert_eq!(problems(&Checker::new(), "cat\r\n\t[m1][lang id=1]a [lang id=2]b[/lang][/lang][/m]\r\n"), Vec::new());
+    }
+
+    #[test]
+    fn empty_formatting() {
+        let checker = Checker::new().flag_empty_formatting(true);
+        let diags = checker.check(&dict("cat\r\n\t[m1][b][/b]кошка [i] [/i][/m]\r\n"));
+        assert_eq!(diags.iter().map(|d| (d.line, d.severity, d.message.as_str())).collect::<Vec<_>>(), vec![
+            (5, Severity::Warning, "tag without text at column 6, use fix-tags --remove-empty-formatting ==> \t[m1][b][/b]кошка [i] [/i][/m]"),
+            (5, Severity::Warning, "tag without text at column 19, use fix-tags --remove-empty-formatting ==> \t[m1][b][/b]кошка [i] [/i][/m]"),
+        ]);
+        assert_eq!(problems(&Checker::new(), "cat\r\n\t[m1][b][/b]кошка[/m]\r\n"), Vec::new());
+    }
+
+    #[test]
+    fn part_of_speech_label() {
+        let checker = Checker::new();
+        assert_eq!(problems(&checker, "bank [p]n[/p]\r\n\t[m1]берег[/m]\r\n"), Vec::new());
+        assert_eq!(problems(&checker, "run[p]v[/p]\r\n\t[m1]бежать[/m]\r\n"), vec![(4, "headword-special-char"), (4, "headword-special-char"), (4, "headword-special-char"), (4, "headword-special-char")]);
+    }
+
+    #[test]
+    fn reversed_cards() {
+        let checker = Checker::new().check_direction(true);
+        let cards = "cat\r\n\t[m1][trn]кошка[/trn][/m]\r\nкот\r\n\t[m1][trn]cat[/trn], [trn]кошка[/trn][/m]\r\n";
+        let diags = checker.check(&dict(cards));
+        assert_eq!(diags.iter().map(|d| (d.line, d.message.as_str())).collect::<Vec<_>>(), vec![
+            (6, "headword is in Cyrillic script of the contents language, the card may be reversed ==> кот"),
+            (7, "translation 'cat' is in Latin script of the index language, the card may be reversed ==> \t[m1][trn]cat[/trn], [trn]кошка[/trn][/m]"),
+        ]);
+        assert_eq!(problems(&Checker::new(), cards), Vec::new());
+        // both languages are written in Latin script
+        let german = "#NAME \"Test\"\r\n#INDEX_LANGUAGE \"English\"\r\n#CONTENTS_LANGUAGE \"German\"\r\n\r\ncat\r\n\t[m1][trn]cat[/trn][/m]\r\n";
+        assert!(checker.check(german).is_empty());
+    }
+}
@@ -0,0 +1,251 @@
+// Hash functions for content hashes of dictionaries: SHA-256 (FIPS 180-4)
+// and BLAKE3 with the default 32-byte output
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+// initial hash value of SHA-256, BLAKE3 uses it as well
+const IV: [u32; 8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut h = IV;
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+    for block in msg.chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+        let mut v = h;
+        for i in 0..64 {
+            let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^ v[4].rotate_right(25);
+            let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+            let t1 = v[7].wrapping_add(s1).wrapping_add(ch).wrapping_add(SHA256_K[i]).wrapping_add(w[i]);
+            let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^ v[0].rotate_right(22);
+            let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            let t2 = s0.wrapping_add(maj);
+            v = [t1.wrapping_add(t2), v[0], v[1], v[2], v[3].wrapping_add(t1), v[4], v[5], v[6]];
+        }
+        for (x, y) in h.iter_mut().zip(v.iter()) {
+            *x = x.wrapping_add(*y);
+        }
+    }
+    let mut res = [0u8; 32];
+    for (i, x) in h.iter().enumerate() {
+        res[i * 4..i * 4 + 4].copy_from_slice(&x.to_be_bytes());
+    }
+    res
+}
+
+const BLAKE3_CHUNK_LEN: usize = 1024;
+const BLAKE3_BLOCK_LEN: usize = 64;
+const CHUNK_START: u32 = 1;
+const CHUNK_END: u32 = 2;
+const PARENT: u32 = 4;
+const ROOT: u32 = 8;
+const MSG_PERMUTATION: [usize; 16] = [2, 6, 3, 10, 7, 0, 4, 13, 1, 11, 12, 5, 9, 14, 15, 8];
+
+fn g(st: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize, mx: u32, my: u32) {
+    st[a] = st[a].wrapping_add(st[b]).wrapping_add(mx);
+    st[d] = (st[d] ^ st[a]).rotate_right(16);
+    st[c] = st[c].wrapping_add(st[d]);
+    st[b] = (st[b] ^ st[c]).rotate_right(12);
+    st[a] = st[a].wrapping_add(st[b]).wrapping_add(my);
+    st[d] = (st[d] ^ st[a]).rotate_right(8);
+    st[c] = st[c].wrapping_add(st[d]);
+    st[b] = (st[b] ^ st[c]).rotate_right(7);
+}
+
+// BLAKE3 compression function, returns the first 8 words of the result:
+// that is all the chaining values and a 32-byte output need
+fn compress(cv: &[u32; 8], block: &[u32; 16], counter: u64, block_len: u32, flags: u32) -> [u32; 8] {
+    let mut st = [
+        cv[0], cv[1], cv[2], cv[3], cv[4], cv[5], cv[6], cv[7],
+        IV[0], IV[1], IV[2], IV[3], counter as u32, (counter >> 32) as u32, block_len, flags,
+    ];
+    let mut m = *block;
+    for round in 0..7 {
+        g(&mut st, 0, 4, 8, 12, m[0], m[1]);
+        g(&mut st, 1, 5, 9, 13, m[2], m[3]);
+        g(&mut st, 2, 6, 10, 14, m[4], m[5]);
+        g(&mut st, 3, 7, 11, 15, m[6], m[7]);
+        g(&mut st, 0, 5, 10, 15, m[8], m[9]);
+        g(&mut st, 1, 6, 11, 12, m[10], m[11]);
+        g(&mut st, 2, 7, 8, 13, m[12], m[13]);
+        g(&mut st, 3, 4, 9, 14, m[14], m[15]);
+        if round < 6 {
+            let prev = m;
+            for (i, x) in m.iter_mut().enumerate() {
+                *x = prev[MSG_PERMUTATION[i]];
+            }
+        }
+    }
+    let mut res = [0u32; 8];
+    for (i, x) in res.iter_mut().enumerate() {
+        *x = st[i] ^ st[i + 8];
+    }
+    res
+}
+
+// the last compression of a chunk or a parent node: it is done with the
+// ROOT flag if the node turns out to be the root of the tree
+struct Output {
+    cv: [u32; 8],
+    block: [u32; 16],
+    counter: u64,
+    block_len: u32,
+    flags: u32,
+}
+
+impl Output {
+    fn chaining_value(&self) -> [u32; 8] {
+        compress(&self.cv, &self.block, self.counter, self.block_len, self.flags)
+    }
+
+    fn root_hash(&self) -> [u8; 32] {
+        let words = compress(&self.cv, &self.block, 0, self.block_len, self.flags | ROOT);
+        let mut res = [0u8; 32];
+        for (i, x) in words.iter().enumerate() {
+            res[i * 4..i * 4 + 4].copy_from_slice(&x.to_le_bytes());
+        }
+        res
+    }
+}
+
+fn block_words(bytes: &[u8]) -> [u32; 16] {
+    let mut buf = [0u8; BLAKE3_BLOCK_LEN];
+    buf[..bytes.len()].copy_from_slice(bytes);
+    let mut res = [0u32; 16];
+    for (i, x) in res.iter_mut().enumerate() {
+        *x = u32::from_le_bytes([buf[i * 4], buf[i * 4 + 1], buf[i * 4 + 2], buf[i * 4 + 3]]);
+    }
+    res
+}
+
+fn chunk_output(chunk: &[u8], counter: u64) -> Output {
+    let mut cv = IV;
+    let blocks: Vec<&[u8]> = if chunk.is_empty() { vec![chunk] } else { chunk.chunks(BLAKE3_BLOCK_LEN).collect() };
+    let last = blocks.len() - 1;
+    for (i, b) in blocks[..last].iter().enumerate() {
+        let start = if i == 0 { CHUNK_START } else { 0 };
+        cv = compress(&cv, &block_words(b), counter, BLAKE3_BLOCK_LEN as u32, start);
+    }
+    let start = if last == 0 { CHUNK_START } else { 0 };
+    Output { cv, block: block_words(blocks[last]), counter, block_len: blocks[last].len() as u32, flags: start | CHUNK_END }
+}
+
+fn parent_output(left: &[u32; 8], right: &[u32; 8]) -> Output {
+    let mut block = [0u32; 16];
+    block[..8].copy_from_slice(left);
+    block[8..].copy_from_slice(right);
+    Output { cv: IV, block, counter: 0, block_len: BLAKE3_BLOCK_LEN as u32, flags: PARENT }
+}
+
+pub fn blake3(data: &[u8]) -> [u8; 32] {
+    let chunks: Vec<&[u8]> = if data.is_empty() { vec![data] } else { data.chunks(BLAKE3_CHUNK_LEN).collect() };
+    // chaining values of complete subtrees, the number of chunks in a subtree
+    // is given by the bits of the chunk counter
+    let mut stack: Vec<[u32; 8]> = Vec::new();
+    let last = chunks.len() - 1;
+    for (i, chunk) in chunks[..last].iter().enumerate() {
+        let mut cv = chunk_output(chunk, i as u64).chaining_value();
+        let mut total = i + 1;
+        while total & 1 == 0 {
+            cv = parent_output(&stack.pop().unwrap(), &cv).chaining_value();
+            total >>= 1;
+        }
+        stack.push(cv);
+    }
+    let mut out = chunk_output(chunks[last], last as u64);
+    while let Some(left) = stack.pop() {
+        out = parent_output(&left, &out.chaining_value());
+    }
+    out.root_hash()
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the input of the official BLAKE3 test vectors: bytes 0, 1, ..., 250, 0, ...
+    fn input(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn sha256_vectors() {
+        // FIPS 180-4 examples
+        assert_eq!(to_hex(&sha256(b"")), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(to_hex(&sha256(b"abc")), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(to_hex(&sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1");
+        assert_eq!(to_hex(&sha256(&[b'a'; 1_000_000])), "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0");
+    }
+
+    #[test]
+    fn sha256_padding_boundaries() {
+        // the length does not fit the last block from 56 bytes on
+        let vectors = [
+            (55, "463eb28e72f82e0a96c0a4cc53690c571281131f672aa229e0d45ae59b598b59"),
+            (56, "da2ae4d6b36748f2a318f23e7ab1dfdf45acdc9d049bd80e59de82a60895f562"),
+            (63, "29af2686fd53374a36b0846694cc342177e428d1647515f078784d69cdb9e488"),
+            (64, "fdeab9acf3710362bd2658cdc9a29e8f9c757fcf9811603a8c447cd1d9151108"),
+            (65, "4bfd2c8b6f1eec7a2afeb48b934ee4b2694182027e6d0fc075074f2fabb31781"),
+            (119, "da18797ed7c3a777f0847f429724a2d8cd5138e6ed2895c3fa1a6d39d18f7ec6"),
+            (120, "f52b23db1fbb6ded89ef42a23ce0c8922c45f25c50b568a93bf1c075420bbb7c"),
+        ];
+        for (len, hash) in vectors.iter() {
+            assert_eq!(to_hex(&sha256(&input(*len))), *hash, "{}", len);
+        }
+    }
+
+    #[test]
+    fn blake3_vectors() {
+        // from test_vectors.json of the BLAKE3 reference implementation:
+        // one chunk is 1024 bytes, so longer inputs build trees of chunks
+        let vectors = [
+            (0, "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"),
+            (1, "2d3adedff11b61f14c886e35afa036736dcd87a74d27b5c1510225d0f592e213"),
+            (1023, "10108970eeda3eb932baac1428c7a2163b0e924c9a9e25b35bba72b28f70bd11"),
+            (1024, "42214739f095a406f3fc83deb889744ac00df831c10daa55189b5d121c855af7"),
+            (1025, "d00278ae47eb27b34faecf67b4fe263f82d5412916c1ffd97c8cb7fb814b8444"),
+            (2048, "e776b6028c7cd22a4d0ba182a8bf62205d2ef576467e838ed6f2529b85fba24a"),
+            (2049, "5f4d72f40d7a5f82b15ca2b2e44b1de3c2ef86c426c95c1af0b6879522563030"),
+            (3072, "b98cb0ff3623be03326b373de6b9095218513e64f1ee2edd2525c7ad1e5cffd2"),
+            (3073, "7124b49501012f81cc7f11ca069ec9226cecb8a2c850cfe644e327d22d3e1cd3"),
+            (4096, "015094013f57a5277b59d8475c0501042c0b642e531b0a1c8f58d2163229e969"),
+            (4097, "9b4052b38f1c5fc8b1f9ff7ac7b27cd242487b3d890d15c96a1c25b8aa0fb995"),
+            (5120, "9cadc15fed8b5d854562b26a9536d9707cadeda9b143978f319ab34230535833"),
+            (8192, "aae792484c8efe4f19e2ca7d371d8c467ffb10748d8a5a1ae579948f718a2a63"),
+            (8193, "bab6c09cb8ce8cf459261398d2e7aef35700bf488116ceb94a36d0f5f1b7bc3b"),
+            (16384, "f875d6646de28985646f34ee13be9a576fd515f76b5b0a26bb324735041ddde4"),
+            (31744, "62b6960e1a44bcc1eb1a611a8d6235b6b4b78f32e7abc4fb4c6cdcce94895c47"),
+            (102400, "bc3e3d41a1146b069abffad3c0d44860cf664390afce4d9661f7902e7943e085"),
+        ];
+        for (len, hash) in vectors.iter() {
+            assert_eq!(to_hex(&blake3(&input(*len))), *hash, "{}", len);
+        }
+        assert_eq!(to_hex(&blake3(b"abc")), "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85");
+    }
+}
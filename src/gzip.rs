@@ -0,0 +1,546 @@
+// Minimal gzip support: enough to unpack .dsl.dz dictionaries (dictzip is
+// a regular gzip stream with an extra header field) and to pack results back
+
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+const LEN_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LEN_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+// a dictionary is far smaller: a bigger result is a broken or a malicious
+// stream (a "zip bomb" that unpacks a few kilobytes into gigabytes)
+pub const MAX_DECOMPRESSED_SIZE: usize = 1 << 30;
+
+const CLEN_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+pub fn is_gzip(bytes: &[u8]) -> bool {
+    bytes.len() > 2 && bytes[0] == 0x1f && bytes[1] == 0x8b
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for b in data {
+        crc ^= u32::from(*b);
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+    nbits: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader { data, pos: 0, bit: 0, nbits: 0 }
+    }
+
+    fn bits(&mut self, n: u32) -> Result<u32, String> {
+        while self.nbits < n {
+            if self.pos >= self.data.len() {
+                return Err(String::from("unexpected end of compressed data"));
+            }
+            self.bit |= u32::from(self.data[self.pos]) << self.nbits;
+            self.pos += 1;
+            self.nbits += 8;
+        }
+        let v = self.bit & ((1u32 << n) - 1);
+        self.bit >>= n;
+        self.nbits -= n;
+        Ok(v)
+    }
+
+    fn align(&mut self) {
+        self.bit = 0;
+        self.nbits = 0;
+    }
+}
+
+// canonical Huffman table: counts[len] and symbols sorted by code
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for l in lengths {
+            counts[*l as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offs = [0u16; 16];
+        for i in 1..16 {
+            offs[i] = offs[i - 1] + counts[i - 1];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (sym, l) in lengths.iter().enumerate() {
+            if *l != 0 {
+                symbols[offs[*l as usize] as usize] = sym as u16;
+                offs[*l as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, br: &mut BitReader) -> Result<u16, String> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for len in 1..16 {
+            code |= br.bits(1)? as i32;
+            let count = i32::from(self.counts[len]);
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err(String::from("invalid Huffman code"))
+    }
+}
+
+fn too_large(limit: usize) -> String {
+    format!("decompressed data is larger than {} bytes", limit)
+}
+
+fn inflate_block(br: &mut BitReader, out: &mut Vec<u8>, limit: usize, lit: &Huffman, dist: &Huffman) -> Result<(), String> {
+    loop {
+        let sym = lit.decode(br)? as usize;
+        if sym < 256 {
+            if out.len() >= limit {
+                return Err(too_large(limit));
+            }
+            out.push(sym as u8);
+            continue;
+        }
+        if sym == 256 {
+            return Ok(());
+        }
+        let sym = sym - 257;
+        if sym >= LEN_BASE.len() {
+            return Err(String::from("invalid length code"));
+        }
+        let len = LEN_BASE[sym] as usize + br.bits(u32::from(LEN_EXTRA[sym]))? as usize;
+        let dsym = dist.decode(br)? as usize;
+        if dsym >= DIST_BASE.len() {
+            return Err(String::from("invalid distance code"));
+        }
+        let d = DIST_BASE[dsym] as usize + br.bits(u32::from(DIST_EXTRA[dsym]))? as usize;
+        if d > out.len() {
+            return Err(String::from("distance is too far back"));
+        }
+        if out.len() + len > limit {
+            return Err(too_large(limit));
+        }
+        let start = out.len() - d;
+        for i in 0..len {
+            let b = out[start + i];
+            out.push(b);
+        }
+    }
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    for (i, l) in lengths.iter_mut().enumerate() {
+        *l = match i {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    (Huffman::new(&lengths), Huffman::new(&[5u8; 30]))
+}
+
+fn dynamic_tables(br: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let hlit = br.bits(5)? as usize + 257;
+    let hdist = br.bits(5)? as usize + 1;
+    let hclen = br.bits(4)? as usize + 4;
+    let mut clen = [0u8; 19];
+    for idx in CLEN_ORDER.iter().take(hclen) {
+        clen[*idx] = br.bits(3)? as u8;
+    }
+    let clen_table = Huffman::new(&clen);
+    let mut lengths: Vec<u8> = Vec::with_capacity(hlit + hdist);
+    while lengths.len() < hlit + hdist {
+        let sym = clen_table.decode(br)?;
+        let (val, rep) = match sym {
+            0..=15 => (sym as u8, 1),
+            16 => match lengths.last() {
+                None => return Err(String::from("repeat code without previous length")),
+                Some(l) => (*l, 3 + br.bits(2)?),
+            },
+            17 => (0, 3 + br.bits(3)?),
+            _ => (0, 11 + br.bits(7)?),
+        };
+        for _ in 0..rep {
+            lengths.push(val);
+        }
+    }
+    if lengths.len() > hlit + hdist {
+        return Err(String::from("too many code lengths"));
+    }
+    Ok((Huffman::new(&lengths[..hlit]), Huffman::new(&lengths[hlit..])))
+}
+
+// unpacks a deflate stream of at most `limit` bytes, returns the data and
+// the number of bytes of the stream
+fn inflate(data: &[u8], limit: usize) -> Result<(Vec<u8>, usize), String> {
+    let mut br = BitReader::new(data);
+    let mut out: Vec<u8> = Vec::new();
+    loop {
+        let last = br.bits(1)? == 1;
+        match br.bits(2)? {
+            0 => {
+                br.align();
+                if br.pos + 4 > data.len() {
+                    return Err(String::from("unexpected end of compressed data"));
+                }
+                let len = u16::from_le_bytes([data[br.pos], data[br.pos + 1]]);
+                let nlen = u16::from_le_bytes([data[br.pos + 2], data[br.pos + 3]]);
+                if nlen != !len {
+                    return Err(String::from("stored block length does not match its complement"));
+                }
+                let len = usize::from(len);
+                br.pos += 4;
+                if br.pos + len > data.len() {
+                    return Err(String::from("unexpected end of compressed data"));
+                }
+                if out.len() + len > limit {
+                    return Err(too_large(limit));
+                }
+                out.extend_from_slice(&data[br.pos..br.pos + len]);
+                br.pos += len;
+            }
+            1 => {
+                let (lit, dist) = fixed_tables();
+                inflate_block(&mut br, &mut out, limit, &lit, &dist)?;
+            }
+            2 => {
+                let (lit, dist) = dynamic_tables(&mut br)?;
+                inflate_block(&mut br, &mut out, limit, &lit, &dist)?;
+            }
+            _ => return Err(String::from("invalid block type")),
+        }
+        if last {
+            return Ok((out, br.pos));
+        }
+    }
+}
+
+fn skip_zero_terminated(bytes: &[u8], pos: usize) -> Result<usize, String> {
+    match bytes.get(pos..).and_then(|rest| rest.iter().position(|b| *b == 0)) {
+        None => Err(String::from("truncated gzip header")),
+        Some(p) => Ok(pos + p + 1),
+    }
+}
+
+// unpacks all gzip members of the stream one by one
+pub fn decompress(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    decompress_limited(bytes, MAX_DECOMPRESSED_SIZE)
+}
+
+// the same as decompress, but fails if the unpacked data of all members is
+// larger than `limit` bytes
+pub fn decompress_limited(bytes: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    let mut res: Vec<u8> = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let member = &bytes[pos..];
+        if member.len() < 18 || !is_gzip(member) {
+            return Err(String::from("not a gzip stream"));
+        }
+        if member[2] != 8 {
+            return Err(format!("unsupported compression method {}", member[2]));
+        }
+        let flags = member[3];
+        let mut hdr = 10;
+        if flags & FEXTRA != 0 {
+            if member.len() < hdr + 2 {
+                return Err(String::from("truncated gzip header"));
+            }
+            hdr += 2 + (usize::from(member[hdr]) | (usize::from(member[hdr + 1]) << 8));
+        }
+        if flags & FNAME != 0 {
+            hdr = skip_zero_terminated(member, hdr)?;
+        }
+        if flags & FCOMMENT != 0 {
+            hdr = skip_zero_terminated(member, hdr)?;
+        }
+        if flags & FHCRC != 0 {
+            hdr += 2;
+        }
+        if hdr >= member.len() {
+            return Err(String::from("truncated gzip header"));
+        }
+        let (data, used) = inflate(&member[hdr..], limit - res.len())?;
+        let tail = hdr + used;
+        if tail + 8 > member.len() {
+            return Err(String::from("truncated gzip trailer"));
+        }
+        let crc = u32::from_le_bytes([member[tail], member[tail + 1], member[tail + 2], member[tail + 3]]);
+        if crc != crc32(&data) {
+            return Err(String::from("gzip checksum mismatch"));
+        }
+        let size = u32::from_le_bytes([member[tail + 4], member[tail + 5], member[tail + 6], member[tail + 7]]);
+        if size != data.len() as u32 {
+            return Err(String::from("gzip size mismatch"));
+        }
+        res.extend_from_slice(&data);
+        pos += tail + 8;
+    }
+    Ok(res)
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    bit: u32,
+    nbits: u32,
+}
+
+impl BitWriter {
+    fn put(&mut self, val: u32, n: u32) {
+        self.bit |= val << self.nbits;
+        self.nbits += n;
+        while self.nbits >= 8 {
+            self.out.push(self.bit as u8);
+            self.bit >>= 8;
+            self.nbits -= 8;
+        }
+    }
+
+    // Huffman codes are stored starting from the most significant bit
+    fn put_code(&mut self, code: u32, n: u32) {
+        let mut rev = 0;
+        for i in 0..n {
+            rev |= ((code >> i) & 1) << (n - 1 - i);
+        }
+        self.put(rev, n);
+    }
+
+    fn flush(&mut self) {
+        if self.nbits > 0 {
+            self.out.push(self.bit as u8);
+        }
+        self.bit = 0;
+        self.nbits = 0;
+    }
+
+    fn literal(&mut self, sym: u16) {
+        let sym = u32::from(sym);
+        match sym {
+            0..=143 => self.put_code(0x30 + sym, 8),
+            144..=255 => self.put_code(0x190 + sym - 144, 9),
+            256..=279 => self.put_code(sym - 256, 7),
+            _ => self.put_code(0xc0 + sym - 280, 8),
+        }
+    }
+
+    fn copy(&mut self, len: usize, dist: usize) {
+        let li = LEN_BASE.iter().rposition(|b| usize::from(*b) <= len).unwrap_or(0);
+        self.literal(257 + li as u16);
+        self.put((len - usize::from(LEN_BASE[li])) as u32, u32::from(LEN_EXTRA[li]));
+        let di = DIST_BASE.iter().rposition(|b| usize::from(*b) <= dist).unwrap_or(0);
+        self.put_code(di as u32, 5);
+        self.put((dist - usize::from(DIST_BASE[di])) as u32, u32::from(DIST_EXTRA[di]));
+    }
+}
+
+const WINDOW: usize = 32768;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+
+fn hash3(data: &[u8], pos: usize) -> usize {
+    let v = u32::from(data[pos]) | (u32::from(data[pos + 1]) << 8) | (u32::from(data[pos + 2]) << 16);
+    (v.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+}
+
+// packs data into a single-member gzip stream: one deflate block with
+// fixed Huffman codes and a greedy LZ77 pass
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut bw = BitWriter { out: vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff], bit: 0, nbits: 0 };
+    bw.put(1, 1);
+    bw.put(1, 2);
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut pos = 0;
+    while pos < data.len() {
+        let mut best = 0;
+        let mut dist = 0;
+        if pos + 3 <= data.len() {
+            let h = hash3(data, pos);
+            let cand = head[h];
+            head[h] = pos;
+            if cand != usize::MAX && pos - cand <= WINDOW {
+                let max = MAX_MATCH.min(data.len() - pos);
+                while best < max && data[cand + best] == data[pos + best] {
+                    best += 1;
+                }
+                dist = pos - cand;
+            }
+        }
+        if best >= 3 {
+            bw.copy(best, dist);
+            for p in pos + 1..pos + best {
+                if p + 3 <= data.len() {
+                    head[hash3(data, p)] = p;
+                }
+            }
+            pos += best;
+        } else {
+            bw.literal(u16::from(data[pos]));
+            pos += 1;
+        }
+    }
+    bw.literal(256);
+    bw.flush();
+    let mut out = bw.out;
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // fixtures are packed by `gzip -9 -n`, named.dsl.gz by `gzip -9 -N` to
+    // keep the file name in the header
+    const SMALL: &[u8] = include_bytes!("../tests/data/small.dsl");
+    const SMALL_DZ: &[u8] = include_bytes!("../tests/data/small.dsl.dz");
+    const NAMED_GZ: &[u8] = include_bytes!("../tests/data/named.dsl.gz");
+    const LARGE_DZ: &[u8] = include_bytes!("../tests/data/large.dsl.dz");
+    const RANDOM_GZ: &[u8] = include_bytes!("../tests/data/random.bin.gz");
+
+    // the text of large.dsl.dz: 300 cards, long enough for dynamic Huffman codes
+    fn large() -> Vec<u8> {
+        let words = ["cat", "dog", "house", "tree", "river", "stone", "bird", "fish", "cloud", "road"];
+        let mut text = String::from("#NAME \"Test\"\r\n#INDEX_LANGUAGE \"English\"\r\n#CONTENTS_LANGUAGE \"English\"\r\n\r\n");
+        for i in 0..300 {
+            let k = i * 7 % 10;
+            text.push_str(&format!("{}{}\r\n\t[m1][trn]{} {}[/trn][/m]\r\n\t[m2][ex]the {} number {}[/ex][/m]\r\n\r\n",
+                words[k], i, words[(k + 3) % 10], i, words[k], i));
+        }
+        text.encode_utf16().flat_map(|c| c.to_le_bytes()).collect()
+    }
+
+    // the bytes of random.bin.gz: incompressible, so gzip writes a stored block
+    fn random() -> Vec<u8> {
+        let mut x: u32 = 1;
+        (0..3000).map(|_| {
+            x = x.wrapping_mul(1_103_515_245).wrapping_add(12345) & 0x7fff_ffff;
+            (x >> 16) as u8
+        }).collect()
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn decompress_gzip_output() {
+        assert!(is_gzip(SMALL_DZ));
+        assert!(!is_gzip(SMALL));
+        assert_eq!(decompress(SMALL_DZ).unwrap(), SMALL);
+        assert_eq!(decompress(NAMED_GZ).unwrap(), SMALL);
+        assert_eq!(decompress(LARGE_DZ).unwrap(), large());
+        assert_eq!(decompress(RANDOM_GZ).unwrap(), random());
+    }
+
+    #[test]
+    fn decompress_all_members() {
+        let stream = [SMALL_DZ, NAMED_GZ].concat();
+        assert_eq!(decompress(&stream).unwrap(), [SMALL, SMALL].concat());
+    }
+
+    #[test]
+    fn decompress_dictzip_header() {
+        // dictzip keeps its chunk table in an extra field `RA`
+        let mut dz = SMALL_DZ[..10].to_vec();
+        dz[3] |= FEXTRA;
+        dz.extend_from_slice(&[8, 0, b'R', b'A', 4, 0, 1, 0, 0, 0]);
+        dz.extend_from_slice(&SMALL_DZ[10..]);
+        assert_eq!(decompress(&dz).unwrap(), SMALL);
+    }
+
+    #[test]
+    fn compress_round_trip() {
+        for data in [Vec::new(), SMALL.to_vec(), large(), random(), vec![b'a'; 100_000]] {
+            let packed = compress(&data);
+            assert!(is_gzip(&packed));
+            assert_eq!(decompress(&packed).unwrap(), data);
+        }
+        assert!(compress(&large()).len() < large().len() / 4);
+    }
+
+    #[test]
+    fn stored_block_length_is_checked() {
+        // the stored block starts right after the 10-byte header: 1 byte of
+        // the block type, then LEN and NLEN
+        assert_eq!(RANDOM_GZ[11..13], 3000u16.to_le_bytes());
+        let mut broken = RANDOM_GZ.to_vec();
+        broken[13] ^= 1;
+        assert_eq!(decompress(&broken).unwrap_err(), "stored block length does not match its complement");
+    }
+
+    #[test]
+    fn size_is_limited() {
+        let bomb = compress(&vec![0; 1 << 20]);
+        assert!(bomb.len() < 10_000);
+        assert_eq!(decompress_limited(&bomb, 1 << 16).unwrap_err(), "decompressed data is larger than 65536 bytes");
+        assert!(decompress_limited(&bomb, 1 << 20).is_ok());
+        assert!(decompress_limited(RANDOM_GZ, 2999).is_err());
+        // the limit is for all members together
+        let stream = [SMALL_DZ, SMALL_DZ].concat();
+        assert!(decompress_limited(&stream, SMALL.len()).is_err());
+        assert!(decompress_limited(&stream, SMALL.len() * 2).is_ok());
+    }
+
+    #[test]
+    fn broken_streams_are_errors() {
+        for len in 1..SMALL_DZ.len() {
+            assert!(decompress(&SMALL_DZ[..len]).is_err(), "{}", len);
+        }
+        let mut bad_crc = SMALL_DZ.to_vec();
+        let n = bad_crc.len();
+        bad_crc[n - 8] ^= 1;
+        assert_eq!(decompress(&bad_crc).unwrap_err(), "gzip checksum mismatch");
+        let mut bad_size = SMALL_DZ.to_vec();
+        bad_size[n - 4] ^= 1;
+        assert_eq!(decompress(&bad_size).unwrap_err(), "gzip size mismatch");
+        let mut bad_method = SMALL_DZ.to_vec();
+        bad_method[2] = 7;
+        assert!(decompress(&bad_method).is_err());
+        // a name without its terminating zero
+        let mut no_name_end = SMALL_DZ[..18].to_vec();
+        no_name_end[3] = FEXTRA | FNAME;
+        no_name_end[10] = 0xff;
+        assert!(decompress(&no_name_end).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+// Rendering of cards to HTML: body lines become `<div>`s with classes named
+// after margins and tags, so a style sheet decides how a dictionary looks
+
+use crate::card::Card;
+use crate::{index_forms, lookup_tag, margin_level, visible_text, TagKind};
+
+pub fn escape(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+// HTML that replaces an opening and a closing DSL tag. Margins are not
+// rendered inline: a line gets the class of its margin level instead
+fn html_tag(kind: TagKind, closing: bool) -> &'static str {
+    let (open, close) = match kind {
+        TagKind::B => ("<b>", "</b>"),
+        TagKind::I => ("<i>", "</i>"),
+        TagKind::Br => ("<br>", ""),
+        TagKind::M1 | TagKind::M2 => ("", ""),
+        TagKind::Comment => ("<span class=\"com\">", "</span>"),
+        TagKind::P => ("<span class=\"p\">", "</span>"),
+        TagKind::Ex => ("<span class=\"ex\">", "</span>"),
+        TagKind::C => ("<span class=\"c\">", "</span>"),
+        TagKind::Trn => ("<span class=\"trn\">", "</span>"),
+        TagKind::LangID => ("<span class=\"lang\">", "</span>"),
+        TagKind::Optional => ("<span class=\"opt\">", "</span>"),
+        TagKind::Sound | TagKind::Video | TagKind::Url => ("<a>", "</a>"),
+    };
+    if closing { close } else { open }
+}
+
+/// Renders a body line. Media files and `[url]` become links, references
+/// `<<word>>` are links to the targets `link` returns for them, or plain
+/// text of class `ref` if the word is not in the dictionary
+pub fn render_line(s: &str, link: &dyn Fn(&str) -> Option<String>) -> String {
+    let s = s.trim_start_matches('\t');
+    let mut res = String::with_capacity(s.len() * 2);
+    let mut escaped = false;
+    let mut pos = 0;
+    while let Some(c) = s[pos..].chars().next() {
+        if escaped {
+            res.push_str(&escape(&c.to_string()));
+            escaped = false;
+            pos += c.len_utf8();
+            continue;
+        }
+        if c == '\\' {
+            escaped = true;
+            pos += 1;
+            continue;
+        }
+        let tag = if c == '[' { s[pos..].find(']').and_then(|e| lookup_tag(&s[pos..=pos + e]).map(|t| (t, e + 1))) } else { None };
+        if let Some(((info, closing), len)) = tag {
+            pos += len;
+            if info.opaque || info.kind == TagKind::Url {
+                // the contents of a media tag is a file name, the contents
+                // of [url] is both the target and the text of the link
+                let close = format!("[/{}]", info.name);
+                let end = s[pos..].find(&close).map_or(s.len(), |e| pos + e);
+                let target = visible_text(&s[pos..end], &[]);
+                if info.opaque {
+                    res.push_str(&format!("<a class=\"{}\" href=\"{}\">{}</a>", info.name, escape(&target), escape(&target)));
+                    pos = (end + close.len()).min(s.len());
+                } else if !closing {
+                    res.push_str(&format!("<a class=\"url\" href=\"{}\">", escape(target.trim())));
+                } else {
+                    res.push_str("</a>");
+                }
+                continue;
+            }
+            res.push_str(html_tag(info.kind, closing));
+            continue;
+        }
+        if let (true, Some(e)) = (s[pos..].starts_with("<<"), s[pos..].find(">>")) {
+            let word = visible_text(&s[pos + 2..pos + e], &[]);
+            match link(&word) {
+                Some(href) => res.push_str(&format!("<a href=\"{}\">{}</a>", escape(&href), escape(&word))),
+                None => res.push_str(&format!("<span class=\"ref\">{}</span>", escape(&word))),
+            }
+            pos += e + 2;
+            continue;
+        }
+        res.push_str(&escape(&c.to_string()));
+        pos += c.len_utf8();
+    }
+    res
+}
+
+/// Renders a card as a `<div>` with the id `anchor`: its headwords and body
+/// lines of classes `m1`, `m2`, ... by their margin levels
+pub fn render_card(card: &Card, anchor: &str, link: &dyn Fn(&str) -> Option<String>) -> String {
+    let headwords: Vec<String> = card.headwords.iter().map(|h| escape(&index_forms(h).0)).collect();
+    let mut res = format!("<div class=\"card\" id=\"{}\">\n<h2>{}</h2>\n", escape(anchor), headwords.join(", "));
+    for l in card.body.iter() {
+        let level = margin_level(l).unwrap_or(1).max(1);
+        res.push_str(&format!("<div class=\"m{}\">{}</div>\n", level, render_line(l, link)));
+    }
+    res.push_str("</div>\n");
+    res
+}
+
+const STYLE: &str = ".m2 { margin-left: 2em; } .m3 { margin-left: 3em; } .trn { color: #036; } .ex { color: #555; } .com { color: #777; } .p { color: #080; font-style: italic; } nav { margin: 1em 0; }";
+
+/// A complete HTML document with a title, the navigation bar `nav` (if any)
+/// above and below the body, and a minimal style sheet
+pub fn page(title: &str, nav: &str, body: &str) -> String {
+    let nav = if nav.is_empty() { String::new() } else { format!("<nav>{}</nav>\n", nav) };
+    format!("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n{}{}{}</body>\n</html>\n",
+        escape(title), STYLE, nav, body, nav)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_links(_: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn br_becomes_a_line_break() {
+        assert_eq!(render_line("\t[m1]line1[br]line2[/m]", &no_links), "line1<br>line2");
+    }
+}
@@ -0,0 +1,303 @@
+// Small JSON reader for configuration files
+
+use std::collections::HashMap;
+
+#[derive(Debug,PartialEq,Clone)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    Str(String),
+    Array(Vec<Value>),
+    Object(HashMap<String, Value>),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(m) => m.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+// arrays and objects are parsed recursively: deeper nesting is rejected
+// instead of overflowing the stack
+pub const MAX_DEPTH: usize = 128;
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    // number of arrays and objects the parser is inside of
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_ws(&mut self) {
+        while let Some((_, c)) = self.chars.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.chars.next();
+        }
+    }
+
+    fn err<T>(&mut self, what: &str) -> Result<T, String> {
+        match self.chars.peek() {
+            None => Err(format!("{} at the end of JSON", what)),
+            Some((pos, _)) => Err(format!("{} at position {}", what, pos)),
+        }
+    }
+
+    fn expect(&mut self, word: &str) -> Result<(), String> {
+        for c in word.chars() {
+            match self.chars.next() {
+                Some((_, v)) if v == c => {},
+                _ => return self.err(&format!("expected '{}'", word)),
+            }
+        }
+        Ok(())
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.chars.next();
+        let mut res = String::new();
+        loop {
+            match self.chars.next() {
+                None => return self.err("unterminated string"),
+                Some((_, '"')) => return Ok(res),
+                Some((_, '\\')) => match self.chars.next() {
+                    Some((_, 'n')) => res.push('\n'),
+                    Some((_, 't')) => res.push('\t'),
+                    Some((_, 'r')) => res.push('\r'),
+                    Some((_, 'b')) => res.push('\u{8}'),
+                    Some((_, 'f')) => res.push('\u{c}'),
+                    Some((_, 'u')) => {
+                        let code = self.hex4()?;
+                        // a character outside the BMP is a pair of escaped
+                        // surrogates, a lone surrogate is replaced
+                        let code = match code {
+                            0xd800..=0xdbff if self.low_surrogate_follows() => {
+                                self.chars.next();
+                                self.chars.next();
+                                match self.hex4()? {
+                                    low @ 0xdc00..=0xdfff => 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00),
+                                    low => {
+                                        res.push('\u{fffd}');
+                                        low
+                                    }
+                                }
+                            }
+                            _ => code,
+                        };
+                        res.push(std::char::from_u32(code).unwrap_or('\u{fffd}'));
+                    }
+                    Some((_, c)) if c == '"' || c == '\\' || c == '/' => res.push(c),
+                    Some(_) => return self.err("invalid escape"),
+                    None => return self.err("unterminated string"),
+                },
+                Some((_, c)) => res.push(c),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let mut code = 0;
+        for _ in 0..4 {
+            match self.chars.next().and_then(|(_, c)| c.to_digit(16)) {
+                None => return self.err("invalid unicode escape"),
+                Some(d) => code = code * 16 + d,
+            }
+        }
+        Ok(code)
+    }
+
+    // whether the next characters are `\u`, the start of the second half of
+    // a surrogate pair
+    fn low_surrogate_follows(&self) -> bool {
+        let mut rest = self.chars.clone();
+        matches!((rest.next(), rest.next()), (Some((_, '\\')), Some((_, 'u'))))
+    }
+
+    fn nested(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return self.err(&format!("JSON is nested deeper than {} levels", MAX_DEPTH));
+        }
+        Ok(())
+    }
+
+    fn number(&mut self) -> Result<f64, String> {
+        let mut s = String::new();
+        while let Some((_, c)) = self.chars.peek() {
+            if !(c.is_ascii_digit() || "+-.eE".contains(*c)) {
+                break;
+            }
+            s.push(*c);
+            self.chars.next();
+        }
+        match s.parse::<f64>() {
+            Ok(v) => Ok(v),
+            Err(_) => self.err("invalid number"),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_ws();
+        let c = match self.chars.peek() {
+            None => return self.err("expected value"),
+            Some((_, c)) => *c,
+        };
+        match c {
+            '"' => Ok(Value::Str(self.string()?)),
+            't' => self.expect("true").map(|_| Value::Bool(true)),
+            'f' => self.expect("false").map(|_| Value::Bool(false)),
+            'n' => self.expect("null").map(|_| Value::Null),
+            '[' => {
+                self.nested()?;
+                self.chars.next();
+                let mut arr = Vec::new();
+                self.skip_ws();
+                if let Some((_, ']')) = self.chars.peek() {
+                    self.chars.next();
+                    self.depth -= 1;
+                    return Ok(Value::Array(arr));
+                }
+                loop {
+                    arr.push(self.value()?);
+                    self.skip_ws();
+                    match self.chars.next() {
+                        Some((_, ',')) => {},
+                        Some((_, ']')) => {
+                            self.depth -= 1;
+                            return Ok(Value::Array(arr));
+                        }
+                        _ => return self.err("expected ',' or ']'"),
+                    }
+                }
+            }
+            '{' => {
+                self.nested()?;
+                self.chars.next();
+                let mut obj = HashMap::new();
+                self.skip_ws();
+                if let Some((_, '}')) = self.chars.peek() {
+                    self.chars.next();
+                    self.depth -= 1;
+                    return Ok(Value::Object(obj));
+                }
+                loop {
+                    self.skip_ws();
+                    if !matches!(self.chars.peek(), Some((_, '"'))) {
+                        return self.err("expected key");
+                    }
+                    let key = self.string()?;
+                    self.skip_ws();
+                    self.expect(":")?;
+                    let v = self.value()?;
+                    obj.insert(key, v);
+                    self.skip_ws();
+                    match self.chars.next() {
+                        Some((_, ',')) => {},
+                        Some((_, '}')) => {
+                            self.depth -= 1;
+                            return Ok(Value::Object(obj));
+                        }
+                        _ => return self.err("expected ',' or '}'"),
+                    }
+                }
+            }
+            _ => Ok(Value::Number(self.number()?)),
+        }
+    }
+}
+
+pub fn parse(s: &str) -> Result<Value, String> {
+    let mut p = Parser { chars: s.char_indices().peekable(), depth: 0 };
+    let v = p.value()?;
+    p.skip_ws();
+    if p.chars.peek().is_some() {
+        return p.err("unexpected data");
+    }
+    Ok(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Value {
+        Value::Str(s.to_string())
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(parse(" null "), Ok(Value::Null));
+        assert_eq!(parse("true"), Ok(Value::Bool(true)));
+        assert_eq!(parse("-1.5e2"), Ok(Value::Number(-150.0)));
+        assert_eq!(parse("[1, \"a\", []]"), Ok(Value::Array(vec![Value::Number(1.0), string("a"), Value::Array(Vec::new())])));
+        let obj = parse("{\"zones\": [\"p\", \"trn\"], \"strict\": false, \"empty\": {}}").unwrap();
+        assert_eq!(obj.get("zones").and_then(|v| v.as_array()).map(|v| v.len()), Some(2));
+        assert_eq!(obj.get("zones").unwrap().as_array().unwrap()[1].as_str(), Some("trn"));
+        assert_eq!(obj.get("strict"), Some(&Value::Bool(false)));
+        assert_eq!(obj.get("empty"), Some(&Value::Object(HashMap::new())));
+        assert_eq!(obj.get("missing"), None);
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(parse(r#""a\"b\\c\/d\n\t\r\b\f""#), Ok(string("a\"b\\c/d\n\t\r\u{8}\u{c}")));
+        assert_eq!(parse(r#""\u0041\u00e9\u041A""#), Ok(string("AéК")));
+        assert_eq!(parse(r#""\x""#), Err(String::from("invalid escape at position 3")));
+        assert!(parse(r#""\u12""#).is_err());
+        assert!(parse(r#""\u12g4""#).is_err());
+    }
+
+    #[test]
+    fn surrogates() {
+        assert_eq!(parse(r#""\ud83d\ude00""#), Ok(string("\u{1f600}")));
+        assert_eq!(parse(r#""\ud834\udd1e!""#), Ok(string("\u{1d11e}!")));
+        // lone surrogates are replaced like broken UTF-16 of a dictionary
+        assert_eq!(parse(r#""\ud83d""#), Ok(string("\u{fffd}")));
+        assert_eq!(parse(r#""\ude00x""#), Ok(string("\u{fffd}x")));
+        assert_eq!(parse(r#""\ud83dA""#), Ok(string("\u{fffd}A")));
+        assert_eq!(parse(r#""\ud83d\n""#), Ok(string("\u{fffd}\n")));
+    }
+
+    #[test]
+    fn malformed() {
+        for s in ["", "   ", "[", "[1,", "[1 2]", "{\"a\" 1}", "{\"a\": 1,}", "{1: 2}", "\"abc", "tru", "nul", "-", "1.2.3", "[1] 2", "{\"a\": }", "]"] {
+            assert!(parse(s).is_err(), "{:?}", s);
+        }
+        assert_eq!(parse("[1 2]"), Err(String::from("expected ',' or ']' at position 4")));
+        assert_eq!(parse("\"abc"), Err(String::from("unterminated string at the end of JSON")));
+    }
+
+    #[test]
+    fn depth_is_limited() {
+        let ok = "[".repeat(MAX_DEPTH) + &"]".repeat(MAX_DEPTH);
+        assert!(parse(&ok).is_ok());
+        let objects = "{\"a\":".repeat(MAX_DEPTH) + "1" + &"}".repeat(MAX_DEPTH);
+        assert!(parse(&objects).is_ok());
+        let deep = "[".repeat(MAX_DEPTH + 1) + &"]".repeat(MAX_DEPTH + 1);
+        assert_eq!(parse(&deep), Err(format!("JSON is nested deeper than {} levels at position {}", MAX_DEPTH, MAX_DEPTH)));
+        // far too deep for the stack without the limit
+        assert!(parse(&"[".repeat(1_000_000)).is_err());
+        // siblings do not add up
+        let wide = format!("[{}]", vec!["[[]]"; 1000].join(","));
+        assert!(parse(&wide).is_ok());
+    }
+}
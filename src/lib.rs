@@ -0,0 +1,2075 @@
+pub mod card;
+pub mod checker;
+pub mod digest;
+pub mod gzip;
+pub mod html;
+pub mod json;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+pub mod schema;
+pub mod script;
+
+use std::fmt;
+use std::ops::Range;
+
+#[derive(Debug,PartialEq,Copy,Clone)]
+pub enum DState {
+    Begin,
+    Name,
+    Index,
+    Lang,
+    Include,
+    // `## note` or `; note` line that Lingvo ignores
+    EditorComment,
+    EmptyLine,
+    Key,
+    Comment,
+    Text,
+    M1,
+    M2,
+    RomanNumber,
+
+    Invalid,
+}
+
+/// Tags the validator knows. A closing tag is the same kind with a flag,
+/// see `lookup_tag`
+#[derive(Debug,PartialEq,Eq,Copy,Clone)]
+pub enum TagKind {
+    Comment,
+    M1,
+    M2,
+    P,
+    I,
+    C,
+    B,
+    Ex,
+    LangID,
+    Br,
+    Trn,
+    Sound,
+    Video,
+    Url,
+    // `[*]...[/*]`: the part of a card shown only in the full view
+    Optional,
+}
+
+impl TagKind {
+    /// name of the tag as it is written in a dictionary, e.g. `m1` for `[m1]`
+    pub fn name(self) -> &'static str {
+        TAGS.iter().find(|t| t.kind == self).map(|t| t.name).unwrap_or_default()
+    }
+
+    /// margins `[mN]` share the closing tag `[/m]`
+    pub fn is_margin(self) -> bool {
+        matches!(self, TagKind::M1 | TagKind::M2)
+    }
+}
+
+pub fn roman_to_u8(s: &str) -> u8 {
+    match s.trim() {
+        "I" => 1,
+        "II" => 2,
+        "III" => 3,
+        "IV" => 4,
+        "V" => 5,
+        "VI" => 6,
+        "VII" => 7,
+        "VIII" => 8,
+        "IX" => 9,
+        "X" => 10,
+        _ => 0,
+    }
+}
+
+#[derive(Debug,PartialEq,Copy,Clone)]
+pub struct TagInfo {
+    pub name: &'static str,
+    pub kind: TagKind,
+    pub self_closing: bool,
+    pub takes_attributes: bool,
+    // contents is a file name, not DSL text
+    pub opaque: bool,
+}
+
+const fn paired(name: &'static str, kind: TagKind) -> TagInfo {
+    TagInfo { name, kind, self_closing: false, takes_attributes: false, opaque: false }
+}
+
+// All tags the validator knows. A paired tag is closed with `[/name]`,
+// margins `[mN]` are closed with `[/m]`
+pub static TAGS: &[TagInfo] = &[
+    paired("com", TagKind::Comment),
+    paired("m1", TagKind::M1),
+    paired("m2", TagKind::M2),
+    paired("p", TagKind::P),
+    paired("i", TagKind::I),
+    paired("ex", TagKind::Ex),
+    paired("c", TagKind::C),
+    paired("b", TagKind::B),
+    paired("trn", TagKind::Trn),
+    TagInfo { takes_attributes: true, ..paired("lang", TagKind::LangID) },
+    TagInfo { self_closing: true, ..paired("br", TagKind::Br) },
+    TagInfo { opaque: true, ..paired("s", TagKind::Sound) },
+    TagInfo { opaque: true, ..paired("video", TagKind::Video) },
+    paired("url", TagKind::Url),
+    paired("*", TagKind::Optional),
+];
+
+fn is_opaque(name: &str) -> bool {
+    TAGS.iter().any(|t| t.opaque && t.name == name)
+}
+
+// contents of all `[name]...[/name]` tags of a line
+pub fn tag_contents(s: &str, name: &str) -> Vec<String> {
+    raw_tag_contents(s, name).iter().map(|c| c.trim().to_string()).collect()
+}
+
+// contents of all `[name]...[/name]` tags of a line as they are written,
+// with tags and spaces
+pub fn raw_tag_contents<'a>(s: &'a str, name: &str) -> Vec<&'a str> {
+    let mut res = Vec::new();
+    let open = format!("[{}]", name);
+    let close = format!("[/{}]", closing_name(name));
+    let mut rest = s;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        let end = match rest.find(&close) {
+            None => break,
+            Some(e) => e,
+        };
+        res.push(&rest[..end]);
+        rest = &rest[end + close.len()..];
+    }
+    res
+}
+
+// file names referenced by media tags like `[s]sound.wav[/s]`
+pub fn media_files(s: &str) -> Vec<String> {
+    TAGS.iter().filter(|t| t.opaque).flat_map(|t| tag_contents(s, t.name)).collect()
+}
+
+pub fn closing_name(name: &str) -> &str {
+    name.trim_end_matches(|c: char| c.is_ascii_digit())
+}
+
+// looks up a tag like `[b]`, `[/b]`, or `[lang id=1]` in the tag table.
+// Returns the tag description and whether it is a closing tag
+pub fn lookup_tag(s: &str) -> Option<(&'static TagInfo, bool)> {
+    let inner = s.strip_prefix('[')?.strip_suffix(']')?;
+    if let Some(name) = inner.strip_prefix('/') {
+        return TAGS.iter()
+            .find(|t| !t.self_closing && closing_name(t.name) == name)
+            .map(|t| (t, true));
+    }
+    let (name, attrs) = match inner.find(' ') {
+        None => (inner, false),
+        Some(pos) => (&inner[..pos], true),
+    };
+    TAGS.iter()
+        .find(|t| t.name == name && (t.takes_attributes || !attrs))
+        .map(|t| (t, false))
+}
+
+/// Looks up a tag by its name, e.g. `b`, `m1`, or `lang`. Attributes and the
+/// closing slash are ignored, so `lang id=1` and `/m` are found as well
+pub fn tag_info(name: &str) -> Option<TagInfo> {
+    let name = name.trim().trim_start_matches('[').trim_end_matches(']');
+    let base = name.split(' ').next().unwrap_or("");
+    if let Some(closed) = base.strip_prefix('/') {
+        return TAGS.iter().find(|t| !t.self_closing && closing_name(t.name) == closed).copied();
+    }
+    TAGS.iter().find(|t| t.name == base).copied()
+}
+
+pub fn is_known_tag(name: &str) -> bool {
+    tag_info(name).is_some()
+}
+
+// `[/br]` and alike
+fn is_self_closing_end(s: &str) -> bool {
+    match s.strip_prefix("[/").and_then(|t| t.strip_suffix(']')) {
+        None => false,
+        Some(name) => TAGS.iter().any(|t| t.self_closing && t.name == name),
+    }
+}
+
+// kind of a tag like `[b]` and whether it is a closing tag
+pub fn tag_type(s: &str) -> Option<(TagKind, bool)> {
+    lookup_tag(s).map(|(info, closing)| (info.kind, closing))
+}
+
+/// Lines of a text with their line ends, like `split_inclusive('\n')`, but a
+/// bare `\r` of old Mac files ends a line as well as `\n` and `\r\n`
+pub fn split_lines(s: &str) -> SplitLines<'_> {
+    SplitLines { rest: s }
+}
+
+pub struct SplitLines<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Iterator for SplitLines<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        let end = match self.rest.find(['\r', '\n']) {
+            None => self.rest.len(),
+            Some(p) if self.rest[p..].starts_with("\r\n") => p + 2,
+            Some(p) => p + 1,
+        };
+        let (line, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        Some(line)
+    }
+}
+
+/// Lines of a text without their line ends, like `lines()`, but split the
+/// way `split_lines` does, so line numbers agree with the checker
+pub fn text_lines(s: &str) -> TextLines<'_> {
+    TextLines { lines: split_lines(s) }
+}
+
+pub struct TextLines<'a> {
+    lines: SplitLines<'a>,
+}
+
+impl<'a> Iterator for TextLines<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        self.lines.next().map(|l| l.strip_suffix("\r\n").or_else(|| l.strip_suffix(['\r', '\n'])).unwrap_or(l))
+    }
+}
+
+pub fn line_type(s: &str) -> DState {
+    if s.is_empty() {
+        return DState::EmptyLine;
+    }
+    if s.starts_with("#NAME ") {
+        return DState::Name;
+    }
+    if s.starts_with("#INDEX_LANGUAGE ") {
+        return DState::Index;
+    }
+    if s.starts_with("#CONTENTS_LANGUAGE ") {
+        return DState::Lang;
+    }
+    if s.starts_with("#INCLUDE ") {
+        return DState::Include;
+    }
+    if s.starts_with("##") || s.starts_with(';') {
+        return DState::EditorComment;
+    }
+    if !s.starts_with('\t') {
+        return DState::Key;
+    }
+    let tr = s.trim();
+    // a line of TABs only is an empty paragraph inside a card body, e.g.
+    // between two [m1] paragraphs of one sense, unlike a really empty line
+    // that ends the card
+    if tr.is_empty() {
+        return DState::Text;
+    }
+    if roman_to_u8(tr) != 0 {
+        return DState::RomanNumber;
+    }
+    if !s.starts_with("\t[") {
+        return DState::Text;
+    }
+    if s.starts_with("\t[m1]") {
+        return DState::M1;
+    }
+    if s.starts_with("\t[m2]") {
+        return DState::M2;
+    }
+    if s.contains("[com]") {
+        return DState::Comment;
+    }
+    DState::Invalid
+}
+
+/// Kind of a dictionary line, see `classify_line`
+#[derive(Debug,PartialEq,Eq,Copy,Clone)]
+pub enum LineKind {
+    /// `#NAME "..."`
+    Name,
+    /// `#INDEX_LANGUAGE "..."`
+    IndexLanguage,
+    /// `#CONTENTS_LANGUAGE "..."`
+    ContentsLanguage,
+    /// `#INCLUDE "..."`
+    Include,
+    /// `## note` or `; note`, ignored by Lingvo
+    EditorComment,
+    /// a line without any characters, it ends a card
+    Empty,
+    /// a headword, the first line of a card or its variant
+    Headword,
+    /// body line with a roman number of a sense, like `\tII`
+    RomanNumber,
+    /// body line starting with a margin tag `[mN]`, N is given
+    Margin(u8),
+    /// body line with a comment `[com]` and no margin
+    Comment,
+    /// body line of plain text, or a line of TABs only
+    Text,
+    /// body line starting with a tag that cannot start a line
+    Invalid,
+}
+
+/// Classifies a line the way the checker sees it. Unlike `line_type`, the
+/// result has only line kinds and no tag kinds
+pub fn classify_line(line: &str) -> LineKind {
+    match line_type(line) {
+        DState::Name => LineKind::Name,
+        DState::Index => LineKind::IndexLanguage,
+        DState::Lang => LineKind::ContentsLanguage,
+        DState::Include => LineKind::Include,
+        DState::EditorComment => LineKind::EditorComment,
+        DState::EmptyLine => LineKind::Empty,
+        DState::Key => LineKind::Headword,
+        DState::RomanNumber => LineKind::RomanNumber,
+        DState::M1 => LineKind::Margin(1),
+        DState::M2 => LineKind::Margin(2),
+        DState::Comment => LineKind::Comment,
+        DState::Text => LineKind::Text,
+        _ => LineKind::Invalid,
+    }
+}
+
+pub fn can_follow(prev: DState, curr: DState) -> bool {
+    match prev {
+        DState::Begin => curr == DState::Name,
+        DState::Name => curr == DState::Index,
+        DState::Index => curr == DState::Lang,
+        DState::Lang => curr == DState::EmptyLine || curr == DState::Include,
+        // cards of another file are inserted in place of #INCLUDE
+        DState::Include => curr == DState::EmptyLine || curr == DState::Include,
+        DState::EmptyLine => curr == DState::Key || curr == DState::EmptyLine || curr == DState::Include,
+        DState::Key => curr == DState::Comment || curr == DState::Text || curr == DState::M1 || curr == DState::RomanNumber,
+        DState::Comment => curr == DState::Text || curr == DState::M1 || curr == DState::Comment || curr == DState::RomanNumber || curr == DState::Key,
+        DState::Text => curr == DState::Comment || curr == DState::Key || curr == DState::M1 || curr == DState::M2 || curr == DState::Text || curr == DState::RomanNumber,
+        DState::M1 => curr == DState::Comment || curr == DState::Key || curr == DState::M1 || curr == DState::M2 || curr == DState::RomanNumber || curr == DState::Text,
+        DState::M2 => curr == DState::Comment || curr == DState::Key || curr == DState::M1 || curr == DState::M2 || curr == DState::RomanNumber || curr == DState::Text,
+        DState::RomanNumber => curr == DState::Comment || curr == DState::M1 || curr == DState::Text,
+        _ => false,
+    }
+}
+
+// body lines of a card
+pub fn is_body(tp: DState) -> bool {
+    matches!(tp, DState::Comment | DState::Text | DState::M1 | DState::M2 | DState::RomanNumber)
+}
+
+// the loose order of --lenient: a body line needs a headword somewhere
+// before it, the rest may go in any order
+pub fn can_follow_lenient(seen_key: bool, curr: DState) -> bool {
+    seen_key || !(is_body(curr) || curr == DState::Invalid)
+}
+
+// whether a file can end after a line of the given type: a headword must be
+// followed by its body, and a header by an empty line and cards
+pub fn can_end(last: DState) -> bool {
+    matches!(last, DState::EmptyLine | DState::Comment | DState::Text | DState::M1 | DState::M2)
+}
+
+#[derive(Debug,PartialEq,Clone)]
+pub enum TagError {
+    BracketInsideTag(String),
+    OrphanClosingBracket,
+    UnknownTag(String),
+    SuperfluousClosingTag(String),
+    // closing tag of a self-closing tag like `[/br]`
+    UnexpectedClosingTag(String),
+    // `[/m]` while no margin `[mN]` is open
+    MarginCloseWithoutOpen,
+    // tag opened inside `[com]` and not closed before `[/com]`
+    TagCrossesCommentBoundary(String),
+    // opened tag, the closing tag, and the 1-based column of the opened
+    // tag; None if it is opened on a previous line
+    MismatchedTag(TagKind, TagKind, Option<(usize, String)>),
+    UnfinishedTag(String),
+    UnclosedTags(Vec<TagKind>),
+}
+
+impl TagError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TagError::BracketInsideTag(_) => "bracket-inside-tag",
+            TagError::OrphanClosingBracket => "orphan-bracket",
+            TagError::UnknownTag(_) => "unknown-tag",
+            TagError::SuperfluousClosingTag(_) => "superfluous-closing-tag",
+            TagError::UnexpectedClosingTag(_) => "unexpected-closing-tag",
+            TagError::MarginCloseWithoutOpen => "margin-close-without-open",
+            TagError::TagCrossesCommentBoundary(_) => "tag-crosses-comment",
+            TagError::MismatchedTag(_, _, _) => "mismatched-tag",
+            TagError::UnfinishedTag(_) => "unfinished-tag",
+            TagError::UnclosedTags(_) => "unclosed-tag",
+        }
+    }
+}
+
+impl fmt::Display for TagError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TagError::BracketInsideTag(tag) => write!(f, "opening bracket inside tag: '{}['", tag),
+            TagError::OrphanClosingBracket => write!(f, "orphan closing bracket"),
+            TagError::UnknownTag(tag) => write!(f, "unknown tag '{}'", tag),
+            TagError::SuperfluousClosingTag(tag) => write!(f, "superfluos closing tag '{}'", tag),
+            TagError::UnexpectedClosingTag(tag) => write!(f, "closing tag '{}' of a self-closing tag", tag),
+            TagError::MarginCloseWithoutOpen => write!(f, "margin closing tag '[/m]' without an opening '[mN]'"),
+            TagError::TagCrossesCommentBoundary(tag) => write!(f, "tag '{}' opened inside comment is not closed before '[/com]'", tag),
+            TagError::MismatchedTag(open, close, at) => {
+                let at = match at {
+                    Some((col, tag)) => format!("'{}' at column {}", tag, col),
+                    None => format!("'[{}]' opened on a previous line", open.name()),
+                };
+                write!(f, "expected '[/{}]' for {}, found '[/{}]'", closing_name(open.name()), at, closing_name(close.name()))
+            }
+            TagError::UnfinishedTag(tag) => write!(f, "unfinished tag '{}'", tag),
+            TagError::UnclosedTags(stack) => write!(f, "unclosed tags: {:?}", stack),
+        }
+    }
+}
+
+pub fn check_line(s: &str) -> Result<(), TagError> {
+    check_line_span(s).map_err(|(e, _)| e)
+}
+
+/// The same as `check_line`, but besides the error returns the byte range of
+/// the offending text in the line `s`
+pub fn check_line_span(s: &str) -> Result<(), (TagError, Range<usize>)> {
+    check_line_open(s, &mut Vec::new(), &[])
+}
+
+/// The same as `check_line_span` for tags that may span lines of a card:
+/// `open` holds the tags the previous lines left open and gets the tags left
+/// open by this line. Only tags from `multiline` may stay open at the end of
+/// a line. On error `open` is not changed
+pub fn check_line_open(s: &str, open: &mut Vec<TagKind>, multiline: &[TagKind]) -> Result<(), (TagError, Range<usize>)> {
+    let line = s;
+    let shift = s.len() - s.trim_start().len();
+    let s = s.trim();
+    let at = |r: Range<usize>| r.start + shift..r.end + shift;
+    let mut in_sq = false;
+    let mut tag = String::new();
+    let mut tag_start = 0;
+    let mut stack: Vec<TagKind> = open.clone();
+    // where the opening tags from the stack are, tags of the previous lines
+    // get an empty range
+    let mut opened: Vec<Range<usize>> = vec![0..0; stack.len()];
+    // the previous character is a backslash that escapes the current one
+    let mut escaped = false;
+    // contents of media tags is not parsed
+    let mut skip_until = 0;
+    for (pos, c) in s.char_indices() {
+        if pos < skip_until {
+            continue;
+        }
+        match c {
+            '[' => {
+                    if escaped {
+                        escaped = false;
+                        continue;
+                    }
+                    if in_sq {
+                        return Err((TagError::BracketInsideTag(tag), at(tag_start..pos + 1)));
+                    }
+                    tag.clear();
+                    tag.push('[');
+                    tag_start = pos;
+                    in_sq = true;
+            }
+            ']' => {
+                if escaped {
+                    if in_sq {
+                        tag.push(c);
+                    }
+                    escaped = false;
+                    continue;
+                }
+                if !in_sq {
+                    return Err((TagError::OrphanClosingBracket, at(pos..pos + 1)));
+                }
+                tag.push(c);
+                in_sq = false;
+                let span = tag_start..pos + 1;
+                let (kind, closing) = match lookup_tag(&tag) {
+                    None if is_self_closing_end(&tag) => return Err((TagError::UnexpectedClosingTag(tag), at(span))),
+                    None => return Err((TagError::UnknownTag(tag), at(span))),
+                    Some(v) => v,
+                };
+                if kind.self_closing {
+                    tag.clear();
+                } else if !closing {
+                    tag.clear();
+                    stack.push(kind.kind);
+                    opened.push(span.clone());
+                    if kind.opaque {
+                        let close = format!("[/{}]", kind.name);
+                        match s[pos + 1..].find(&close) {
+                            None => return Err((TagError::UnclosedTags(stack), at(span))),
+                            Some(end) => skip_until = pos + 1 + end,
+                        }
+                    }
+                } else {
+                    if kind.kind.is_margin() && !stack.iter().any(|k| k.is_margin()) {
+                        return Err((TagError::MarginCloseWithoutOpen, at(span)));
+                    }
+                    if stack.is_empty() {
+                        return Err((TagError::SuperfluousClosingTag(tag), at(span)));
+                    }
+                    let last = *stack.last().unwrap();
+                    if kind.kind == TagKind::Comment && last != TagKind::Comment && stack.contains(&TagKind::Comment) {
+                        let open = opened.last().cloned().unwrap_or_default();
+                        return Err((TagError::TagCrossesCommentBoundary(s[open.clone()].to_string()), at(open)));
+                    }
+                    stack.pop();
+                    let open = opened.pop().unwrap_or_default();
+                    if last != kind.kind && !(last.is_margin() && kind.kind.is_margin()) {
+                        // the opening tag as it is written, e.g. `[lang id=2]`
+                        // among nested [lang] blocks of different languages
+                        let col = if open.is_empty() { None } else { Some((line[..at(open.clone()).start].chars().count() + 1, s[open].to_string())) };
+                        return Err((TagError::MismatchedTag(last, kind.kind, col), at(span)));
+                    }
+                    tag.clear();
+                }
+            },
+            _ => if in_sq {
+                tag.push(c);
+            },
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    if in_sq {
+        return Err((TagError::UnfinishedTag(tag), at(tag_start..s.len())));
+    }
+    if let Some(i) = stack.iter().rposition(|k| !multiline.contains(k)) {
+        return Err((TagError::UnclosedTags(stack), at(opened[i].clone())));
+    }
+    *open = stack;
+    Ok(())
+}
+
+/// Tags that enclose the line `line` (0-based) of a text: opened on the
+/// previous lines of its card and not closed yet, the innermost last. Any tag
+/// may span lines here, and lines with tag errors do not change the tags.
+/// A headword or a header line has no enclosing tags
+pub fn enclosing_tags(content: &str, line: usize) -> Vec<TagKind> {
+    let all: Vec<TagKind> = TAGS.iter().map(|t| t.kind).collect();
+    let mut open: Vec<TagKind> = Vec::new();
+    for l in text_lines(content).take(line) {
+        match line_type(l) {
+            DState::EditorComment => {},
+            _ if l.starts_with('\t') => {
+                let _ = check_line_open(l, &mut open, &all);
+            }
+            _ => open.clear(),
+        }
+    }
+    match text_lines(content).nth(line) {
+        Some(l) if l.starts_with('\t') => open,
+        _ => Vec::new(),
+    }
+}
+
+/// Renders a line with the range `span` underlined with carets, like:
+///
+/// ```text
+///   12 | \t[x]word
+///      |  ^^^
+/// ```
+pub fn render_snippet(line: &str, line_no: usize, span: &Range<usize>) -> String {
+    let start = span.start.min(line.len());
+    let end = span.end.clamp(start, line.len());
+    let num = line_no.to_string();
+    // keep TABs in the padding so the carets stay under the text
+    let pad: String = line[..start].chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+    let carets = "^".repeat(line[start..end].chars().count().max(1));
+    let gutter = " ".repeat(num.len());
+    format!("{} | {}\n{} | {}{}", num, line, gutter, pad, carets)
+}
+
+// the same as check_line, but returns an error message or an empty string
+pub fn parse_line(s: &str) -> String {
+    match check_line(s) {
+        Ok(()) => String::new(),
+        Err(e) => e.to_string(),
+    }
+}
+
+/// Splits the part-of-speech shorthand off a headword line. Some
+/// dictionaries end the line with a label `[p]...[/p]` separated from the
+/// headword with spaces, like `bank [p]n[/p]`; the label must not be empty
+/// and must not contain brackets. Returns the headword and the label
+pub fn split_pos(s: &str) -> (&str, Option<&str>) {
+    let line = s.trim_end();
+    let inner = match line.strip_suffix("[/p]") {
+        None => return (s, None),
+        Some(l) => l,
+    };
+    let start = match inner.rfind("[p]") {
+        None => return (s, None),
+        Some(st) => st,
+    };
+    let (head, pos) = (&inner[..start], inner[start + 3..].trim());
+    if !head.ends_with([' ', '\t']) || head.trim().is_empty() || pos.is_empty() || pos.contains(['[', ']']) {
+        return (s, None);
+    }
+    (head.trim_end(), Some(pos))
+}
+
+// returns 1-based columns of special characters in a headword that are not
+// escaped and are not a part of a valid unsorted part `{...}` or optional
+// part `(...)`
+pub fn headword_specials(s: &str) -> Vec<(usize, char)> {
+    let mut res: Vec<(usize, char)> = Vec::new();
+    let mut escaped = false;
+    let mut open_brace: Option<usize> = None;
+    let mut open_paren: Option<usize> = None;
+    for (idx, c) in s.chars().enumerate() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            '[' | ']' => res.push((idx + 1, c)),
+            '{' => if open_brace.is_some() {
+                res.push((idx + 1, c));
+            } else {
+                open_brace = Some(idx + 1);
+            },
+            '}' => if open_brace.is_none() {
+                res.push((idx + 1, c));
+            } else {
+                open_brace = None;
+            },
+            '(' => if open_paren.is_some() {
+                res.push((idx + 1, c));
+            } else {
+                open_paren = Some(idx + 1);
+            },
+            ')' => if open_paren.is_none() {
+                res.push((idx + 1, c));
+            } else {
+                open_paren = None;
+            },
+            _ => {},
+        }
+    }
+    if let Some(col) = open_brace {
+        res.push((col, '{'));
+    }
+    if let Some(col) = open_paren {
+        res.push((col, '('));
+    }
+    res.sort();
+    res
+}
+
+// index forms of a headword: with optional parts `(...)` expanded and with
+// them removed. Unsorted parts `{...}` are not indexed at all:
+// `{to} colo(u)r` -> ("colour", "color")
+pub fn index_forms(s: &str) -> (String, String) {
+    let mut full = String::with_capacity(s.len());
+    let mut stripped = String::with_capacity(s.len());
+    let mut escaped = false;
+    let mut in_brace = false;
+    let mut in_paren = false;
+    for c in s.chars() {
+        if !escaped {
+            match c {
+                '\\' => {
+                    escaped = true;
+                    continue;
+                }
+                '{' => in_brace = true,
+                '}' => in_brace = false,
+                '(' => in_paren = true,
+                ')' => in_paren = false,
+                _ => {},
+            }
+            if "{}()".contains(c) {
+                continue;
+            }
+        }
+        escaped = false;
+        if in_brace {
+            continue;
+        }
+        full.push(c);
+        if !in_paren {
+            stripped.push(c);
+        }
+    }
+    let squash = |s: String| s.split_whitespace().collect::<Vec<&str>>().join(" ");
+    (squash(full), squash(stripped))
+}
+
+// case folding of characters whose folded form is not their lowercase
+fn fold_special(c: char) -> Option<&'static str> {
+    let res = match c {
+        'ß' | 'ẞ' => "ss",
+        'ſ' => "s",
+        // Turkish capital dotted I matches a regular i
+        'İ' => "i",
+        'ŉ' => "ʼn",
+        'ς' => "σ",
+        '\u{345}' | '\u{1fbe}' => "\u{3b9}",
+        'ϐ' => "β",
+        'ϑ' => "θ",
+        'ϕ' => "φ",
+        'ϖ' => "π",
+        'ϰ' => "κ",
+        'ϱ' => "ρ",
+        'ϵ' => "ε",
+        'ẛ' => "ṡ",
+        'ﬀ' => "ff",
+        'ﬁ' => "fi",
+        'ﬂ' => "fl",
+        'ﬃ' => "ffi",
+        'ﬄ' => "ffl",
+        'ﬅ' | 'ﬆ' => "st",
+        _ => return None,
+    };
+    Some(res)
+}
+
+/// Case-insensitive index key of a headword: its indexed form (see
+/// `index_forms`) with Unicode case folding, so `Straße` and `STRASSE` get
+/// the same key. Dotless `ı` stays a separate letter, while `İ` is `i`
+pub fn index_key(headword: &str) -> String {
+    fold_case(&index_forms(headword).0)
+}
+
+/// Unicode case folding of a text, see `index_key`
+pub fn fold_case(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match fold_special(c) {
+            Some(f) => res.push_str(f),
+            None => res.extend(c.to_lowercase()),
+        }
+    }
+    res
+}
+
+// words of a plain text: runs of letters and digits with at least one letter.
+// An apostrophe or a hyphen between letters is a part of the word: `don't`,
+// `well-known`
+pub fn text_words(s: &str) -> Vec<&str> {
+    let mut res = Vec::new();
+    let chars: Vec<(usize, char)> = s.char_indices().collect();
+    let mut start: Option<usize> = None;
+    for (i, &(pos, c)) in chars.iter().enumerate() {
+        let joiner = matches!(c, '\'' | '’' | '-')
+            && i > 0 && chars[i - 1].1.is_alphanumeric()
+            && chars.get(i + 1).is_some_and(|(_, n)| n.is_alphanumeric());
+        if c.is_alphanumeric() || (joiner && start.is_some()) {
+            start.get_or_insert(pos);
+            continue;
+        }
+        if let Some(st) = start.take() {
+            res.push(&s[st..pos]);
+        }
+    }
+    if let Some(st) = start {
+        res.push(&s[st..]);
+    }
+    res.retain(|w| w.chars().any(char::is_alphabetic));
+    res
+}
+
+pub fn escape_headword(s: &str) -> String {
+    let specials = headword_specials(s);
+    let mut res = String::with_capacity(s.len() + specials.len());
+    // columns are sorted, walk them along with the string
+    let mut next = specials.iter().map(|(col, _)| *col).peekable();
+    for (idx, c) in s.chars().enumerate() {
+        if next.peek() == Some(&(idx + 1)) {
+            next.next();
+            res.push('\\');
+        }
+        res.push(c);
+    }
+    res
+}
+
+// value of a header directive without quotes: #NAME "Dict" -> Dict
+pub fn header_value(s: &str) -> &str {
+    match s.find(' ') {
+        None => "",
+        Some(pos) => s[pos..].trim().trim_matches('"'),
+    }
+}
+
+// text of a line without tags and without the contents of `skip` tags
+pub fn visible_text(s: &str, skip: &[&str]) -> String {
+    let mut res = String::with_capacity(s.len());
+    let mut depth = 0;
+    let mut in_sq = false;
+    let mut tag = String::new();
+    let mut escaped = false;
+    for c in s.chars() {
+        match c {
+            '[' if !escaped => {
+                in_sq = true;
+                tag.clear();
+            }
+            ']' if in_sq && !escaped => {
+                in_sq = false;
+                if skip.contains(&tag.as_str()) || is_opaque(&tag) {
+                    depth += 1;
+                } else if tag.starts_with('/') && (skip.contains(&&tag[1..]) || is_opaque(&tag[1..])) {
+                    depth = std::cmp::max(depth, 1) - 1;
+                }
+            }
+            '\\' if !escaped => {},
+            _ if in_sq => tag.push(c),
+            _ if depth == 0 => res.push(c),
+            _ => {},
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    res
+}
+
+// Lingvo language ids: the short ids of old Lingvo versions and Windows language ids
+const LANG_IDS: &[(u16, &str)] = &[
+    (1, "English"), (2, "Russian"), (3, "German"), (4, "French"),
+    (1025, "Arabic"), (1026, "Bulgarian"), (1027, "Catalan"), (1028, "ChineseTaiwan"), (1029, "Czech"),
+    (1030, "Danish"), (1031, "German"), (1032, "GreekModern"), (1033, "English"), (1034, "Spanish"),
+    (1035, "Finnish"), (1036, "French"), (1037, "Hebrew"), (1038, "Hungarian"), (1039, "Icelandic"),
+    (1040, "Italian"), (1041, "Japanese"), (1042, "Korean"), (1043, "Dutch"), (1044, "Norwegian"),
+    (1045, "Polish"), (1046, "Portuguese"), (1048, "Romanian"), (1049, "Russian"), (1050, "Croatian"),
+    (1051, "Slovak"), (1052, "Albanian"), (1053, "Swedish"), (1054, "Thai"), (1055, "Turkish"),
+    (1057, "Indonesian"), (1058, "Ukrainian"), (1059, "Belarusian"), (1060, "Slovenian"), (1061, "Estonian"),
+    (1062, "Latvian"), (1063, "Lithuanian"), (1065, "Persian"), (1066, "Vietnamese"), (1067, "Armenian"),
+    (1068, "Azeri"), (1069, "Basque"), (1071, "Macedonian"), (1078, "Afrikaans"), (1079, "Georgian"),
+    (1081, "Hindi"), (1086, "Malay"), (1087, "Kazakh"), (1088, "Kyrgyz"), (1089, "Swahili"),
+    (1090, "Turkmen"), (1091, "Uzbek"), (1092, "Tatar"), (1104, "Mongolian"), (1133, "Bashkir"),
+    (1142, "Latin"), (2052, "ChinesePRC"), (2070, "PortugueseStandard"), (3098, "Serbian"),
+];
+
+/// Name of a Lingvo language by the id used in `[lang id=N]`, as it is
+/// written in `#INDEX_LANGUAGE`, e.g. 1033 is "English"
+pub fn lang_name(id: u16) -> Option<&'static str> {
+    LANG_IDS.iter().find(|(i, _)| *i == id).map(|(_, n)| *n)
+}
+
+// values of `id` attributes of [lang] tags in a line as they are written
+pub fn lang_ids(s: &str) -> Vec<String> {
+    let mut res = Vec::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("[lang ") {
+        rest = &rest[start + 6..];
+        let end = match rest.find(']') {
+            None => break,
+            Some(e) => e,
+        };
+        for attr in rest[..end].split_whitespace() {
+            if let Some(v) = attr.strip_prefix("id=") {
+                res.push(v.trim_matches('"').to_string());
+            }
+        }
+        rest = &rest[end..];
+    }
+    res
+}
+
+// whether the attributes of a [lang] tag, like `id=1033` or `name="English"`,
+// select the language `id`
+fn is_lang(attrs: &str, id: u16) -> bool {
+    attrs.split_whitespace().any(|a| match a.split_once('=') {
+        Some(("id", v)) => v.trim_matches('"').parse::<u16>().ok() == Some(id),
+        Some(("name", v)) => lang_name(id).is_some_and(|n| n.eq_ignore_ascii_case(v.trim_matches('"'))),
+        _ => false,
+    })
+}
+
+/// Removes `[lang ...]...[/lang]` blocks of all languages except `id`.
+/// Returns the line and whether it has a block of the language `id`
+pub fn keep_lang(s: &str, id: u16) -> (String, bool) {
+    let mut res = String::with_capacity(s.len());
+    let mut found = false;
+    let mut rest = s;
+    while let Some(start) = rest.find("[lang ") {
+        let after = &rest[start..];
+        let (attrs_end, close) = match (after.find(']'), after.find("[/lang]")) {
+            (Some(a), Some(c)) if a < c => (a, c),
+            _ => break,
+        };
+        if is_lang(&after[6..attrs_end], id) {
+            found = true;
+            res.push_str(&rest[..start + close + 7]);
+        } else {
+            res.push_str(rest[..start].trim_end_matches(' '));
+        }
+        rest = &after[close + 7..];
+    }
+    res.push_str(rest);
+    (res, found)
+}
+
+/// Whether the first line of a text decoded as UTF-16LE looks like UTF-16BE:
+/// most of its characters are ASCII with swapped bytes, like `U+2300` for `#`
+pub fn byte_swapped(s: &str) -> bool {
+    let line = text_lines(s.trim_start_matches('\u{feff}')).next().unwrap_or("");
+    let mut total = 0;
+    let mut swapped = 0;
+    for c in line.chars().take(200) {
+        let code = c as u32;
+        total += 1;
+        if code & 0xff == 0 && (0x09..0x7f).contains(&(code >> 8)) {
+            swapped += 1;
+        }
+    }
+    total != 0 && swapped * 2 > total
+}
+
+/// Lone UTF-16 surrogates of a file that a decoder replaces with U+FFFD.
+/// Returns 0-based lines, offsets of the line starts in bytes, and 1-based
+/// columns in characters. A BOM at the start of `bytes` is skipped. Lines
+/// are split the way `split_lines` does
+pub fn lone_surrogates(bytes: &[u8], big_endian: bool) -> Vec<(usize, usize, usize)> {
+    let mut res = Vec::new();
+    let (mut line, mut line_offset, mut col) = (0, 0, 0);
+    // a high surrogate waiting for its low one
+    let mut high = false;
+    // the previous unit is `\r`, so a `\n` does not start one more line
+    let mut cr = false;
+    for (i, b) in bytes.chunks_exact(2).enumerate() {
+        let unit = if big_endian { u16::from_be_bytes([b[0], b[1]]) } else { u16::from_le_bytes([b[0], b[1]]) };
+        if i == 0 && unit == 0xfeff {
+            continue;
+        }
+        let is_low = (0xdc00..=0xdfff).contains(&unit);
+        if high && !is_low {
+            col += 1;
+            res.push((line, line_offset, col));
+        }
+        match unit {
+            0xd800..=0xdbff => high = true,
+            0xdc00..=0xdfff => {
+                col += 1;
+                if !high {
+                    res.push((line, line_offset, col));
+                }
+                high = false;
+            }
+            _ => {
+                high = false;
+                col += 1;
+                if unit == u16::from(b'\n') || unit == u16::from(b'\r') {
+                    if !(cr && unit == u16::from(b'\n')) {
+                        line += 1;
+                    }
+                    line_offset = i * 2 + 2;
+                    col = 0;
+                }
+            }
+        }
+        cr = unit == u16::from(b'\r');
+    }
+    if high {
+        res.push((line, line_offset, col + 1));
+    }
+    res
+}
+
+// whether a line contains any known tag that is not escaped
+pub fn has_tags(s: &str) -> bool {
+    let mut rest = s;
+    while let Some(start) = rest.find('[') {
+        let escaped = rest[..start].ends_with('\\');
+        rest = &rest[start..];
+        let end = match rest.find(']') {
+            None => return false,
+            Some(e) => e + 1,
+        };
+        if !escaped && lookup_tag(&rest[..end]).is_some() {
+            return true;
+        }
+        rest = &rest[1..];
+    }
+    false
+}
+
+// the deepest nesting of `[lang]` blocks of a line and the 1-based column of
+// the first `[lang]` at that depth. Escaped tags are skipped
+pub fn lang_depth(s: &str) -> (usize, usize) {
+    let (mut depth, mut max, mut col) = (0, 0, 0);
+    let mut escaped = false;
+    for (idx, (pos, c)) in s.char_indices().enumerate() {
+        if c == '[' && !escaped {
+            let tag = s[pos..].find(']').map(|e| &s[pos..=pos + e]);
+            match tag.and_then(lookup_tag) {
+                Some((info, false)) if info.kind == TagKind::LangID => {
+                    depth += 1;
+                    if depth > max {
+                        max = depth;
+                        col = idx + 1;
+                    }
+                }
+                Some((info, true)) if info.kind == TagKind::LangID => depth = std::cmp::max(depth, 1) - 1,
+                _ => {},
+            }
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    (max, col)
+}
+
+// numbers of escaped brackets `\\[` and `\\]` of a line and of literal brackets
+// that are not parts of tags, the ones `fix_up_line` escapes
+pub fn bracket_counts(s: &str) -> (usize, usize) {
+    let (mut escaped_count, mut literal) = (0, 0);
+    let mut escaped = false;
+    let mut pos = 0;
+    while let Some(c) = s[pos..].chars().next() {
+        let start = pos;
+        pos += c.len_utf8();
+        match c {
+            '[' | ']' if escaped => escaped_count += 1,
+            '[' => {
+                // a tag ends at the first bracket after its start, `[a [b]`
+                // is a stray bracket followed by a tag
+                match s[pos..].find(['[', ']']).map(|e| pos + e) {
+                    Some(end) if &s[end..=end] == "]" && tag_type(&s[start..=end]).is_some() => pos = end + 1,
+                    _ => literal += 1,
+                }
+            }
+            ']' => literal += 1,
+            _ => {},
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    (escaped_count, literal)
+}
+
+// names of tags of a line the validator does not know, like `x` for `[x]`
+// and `[/x]`. Escaped brackets and file names of media tags are skipped
+pub fn unknown_tags(s: &str) -> Vec<&str> {
+    let mut res = Vec::new();
+    let mut escaped = false;
+    let mut pos = 0;
+    while let Some(c) = s[pos..].chars().next() {
+        let end = if c == '[' && !escaped { s[pos..].find(']') } else { None };
+        escaped = c == '\\' && !escaped;
+        let end = match end {
+            None => {
+                pos += c.len_utf8();
+                continue;
+            }
+            Some(e) => pos + e,
+        };
+        let tag = &s[pos..=end];
+        let name = tag[1..tag.len() - 1].trim_start_matches('/').split(' ').next().unwrap_or("");
+        pos = end + 1;
+        match lookup_tag(tag) {
+            Some((info, false)) if info.opaque => {
+                if let Some(e) = s[pos..].find(&format!("[/{}]", info.name)) {
+                    pos += e;
+                }
+            }
+            None if !is_known_tag(name) => res.push(name),
+            _ => {},
+        }
+    }
+    res
+}
+
+// returns 1-based columns of opening tags `[name]` and `[name ...]` of a line,
+// escaped tags are skipped
+pub fn tag_columns(s: &str, name: &str) -> Vec<usize> {
+    let mut res = Vec::new();
+    let mut escaped = false;
+    for (col, (pos, c)) in s.char_indices().enumerate() {
+        if c == '[' && !escaped {
+            let inner = s[pos + 1..].split(']').next().unwrap_or("");
+            if s[pos + 1..].contains(']') && inner.split(' ').next() == Some(name) {
+                res.push(col + 1);
+            }
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    res
+}
+
+// returns 1-based columns of TABs inside inline tags, e.g. `[trn]a<TAB>b[/trn]`.
+// The leading indentation and margins `[mN]` that span the whole line are
+// not checked
+pub fn tabs_inside_tags(s: &str) -> Vec<usize> {
+    let mut res = Vec::new();
+    let mut depth: usize = 0;
+    let mut in_sq = false;
+    let mut tag = String::new();
+    let mut escaped = false;
+    let indent = s.chars().take_while(|&c| c == '\t').count();
+    for (idx, c) in s.chars().enumerate().skip(indent) {
+        match c {
+            '[' if !escaped => {
+                in_sq = true;
+                tag.clear();
+                tag.push(c);
+            }
+            ']' if in_sq && !escaped => {
+                in_sq = false;
+                tag.push(c);
+                match lookup_tag(&tag) {
+                    Some((info, _)) if info.self_closing || info.kind.is_margin() => {},
+                    Some((_, false)) => depth += 1,
+                    Some((_, true)) => depth = depth.saturating_sub(1),
+                    _ => {},
+                }
+            }
+            '\t' if depth > 0 => res.push(idx + 1),
+            _ if in_sq => tag.push(c),
+            _ => {},
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    res
+}
+
+// replaces TABs found by tabs_inside_tags with spaces
+pub fn replace_tag_tabs(s: &str) -> String {
+    let cols = tabs_inside_tags(s);
+    if cols.is_empty() {
+        return s.to_string();
+    }
+    s.chars().enumerate().map(|(idx, c)| if cols.binary_search(&(idx + 1)).is_ok() { ' ' } else { c }).collect()
+}
+
+// byte ranges of margin tags that repeat the margin right before them, like
+// the second `[m1]` of `[m1][m1]word`
+fn margin_repeats(s: &str) -> Vec<Range<usize>> {
+    let mut res = Vec::new();
+    for t in TAGS.iter().filter(|t| t.kind.is_margin()) {
+        let tag = format!("[{}]", t.name);
+        let twice = tag.repeat(2);
+        let mut from = 0;
+        while let Some(pos) = s[from..].find(&twice).map(|p| p + from) {
+            let backslashes = s[..pos].chars().rev().take_while(|&c| c == '\\').count();
+            if backslashes % 2 == 0 {
+                res.push(pos + tag.len()..pos + twice.len());
+            }
+            from = pos + tag.len();
+        }
+    }
+    res.sort_by_key(|r| r.start);
+    res
+}
+
+// 1-based columns of repeated margins, see margin_repeats
+pub fn redundant_margins(s: &str) -> Vec<usize> {
+    margin_repeats(s).iter().map(|r| s[..r.start].chars().count() + 1).collect()
+}
+
+// removes repeated margins together with as many `[/m]` at the end of the
+// line, if the line has them: `[m1][m1]a[/m][/m]` becomes `[m1]a[/m]`
+pub fn collapse_margins(s: &str) -> String {
+    let repeats = margin_repeats(s);
+    let mut res = String::with_capacity(s.len());
+    let mut last = 0;
+    for r in repeats.iter() {
+        res.push_str(&s[last..r.start]);
+        last = r.end;
+    }
+    res.push_str(&s[last..]);
+    let mut extra = repeats.len();
+    while extra > 0 && res.ends_with("[/m][/m]") {
+        res.truncate(res.len() - 4);
+        extra -= 1;
+    }
+    res
+}
+
+// paired tags of a line without visible text inside, like `[b][/b]` or
+// `[i] [/i]`: byte ranges of the opening and the closing tags. Margins are
+// not checked. The scan stops at the first tag error
+fn empty_tags(s: &str) -> Vec<(Range<usize>, Range<usize>)> {
+    let mut res = Vec::new();
+    // open tags: the kind, the opening tag, and whether there is text inside
+    let mut stack: Vec<(TagKind, Range<usize>, bool)> = Vec::new();
+    let mut escaped = false;
+    let mut pos = 0;
+    while let Some(c) = s[pos..].chars().next() {
+        let start = pos;
+        let tag = if c == '[' && !escaped { s[pos..].find(']').and_then(|e| lookup_tag(&s[pos..=pos + e]).map(|t| (t, e + 1))) } else { None };
+        escaped = c == '\\' && !escaped;
+        let ((info, closing), len) = match tag {
+            None => {
+                if !c.is_whitespace() && !escaped {
+                    stack.iter_mut().for_each(|t| t.2 = true);
+                }
+                pos += c.len_utf8();
+                continue;
+            }
+            Some(t) => t,
+        };
+        pos += len;
+        if info.self_closing {
+            continue;
+        }
+        if !closing {
+            stack.push((info.kind, start..pos, false));
+            if info.opaque {
+                // a file name is the text of a media tag
+                let end = s[pos..].find(&format!("[/{}]", info.name)).map_or(s.len(), |e| pos + e);
+                if !s[pos..end].trim().is_empty() {
+                    stack.iter_mut().for_each(|t| t.2 = true);
+                }
+                pos = end;
+            }
+            continue;
+        }
+        match stack.pop() {
+            Some((kind, open, text)) if kind == info.kind || (kind.is_margin() && info.kind.is_margin()) => {
+                if !text && !kind.is_margin() {
+                    res.push((open, start..pos));
+                }
+            }
+            _ => break,
+        }
+    }
+    res.sort_by_key(|(open, _)| open.start);
+    res
+}
+
+// 1-based columns of paired tags without visible text inside, see `empty_tags`
+pub fn empty_formatting(s: &str) -> Vec<usize> {
+    empty_tags(s).iter().map(|(open, _)| s[..open.start].chars().count() + 1).collect()
+}
+
+// removes paired tags without visible text inside, keeping the spaces
+// between them: `a[i] [/i]b` becomes `a b`
+pub fn remove_empty_formatting(s: &str) -> String {
+    let mut cut: Vec<Range<usize>> = empty_tags(s).into_iter().flat_map(|(open, close)| [open, close]).collect();
+    cut.sort_by_key(|r| r.start);
+    let mut res = String::with_capacity(s.len());
+    let mut last = 0;
+    for r in cut.iter() {
+        res.push_str(&s[last..r.start]);
+        last = r.end;
+    }
+    res.push_str(&s[last..]);
+    res
+}
+
+// level of the margin tag `[mN]` a body line starts with
+pub fn margin_level(s: &str) -> Option<u8> {
+    let rest = s.trim_start_matches(['\t', ' ']).strip_prefix("[m")?;
+    let end = rest.find(']')?;
+    rest[..end].parse::<u8>().ok()
+}
+
+// sets the leading TABs of a body line to its margin level, `tabs_per_level`
+// TABs per level: with one TAB per level `[m2]` is indented with two TABs.
+// Lines without margins are not changed
+pub fn fix_indent(s: &str, tabs_per_level: usize) -> String {
+    let text = s.trim_start_matches(['\t', ' ']);
+    match margin_level(text) {
+        None => s.to_string(),
+        Some(level) => "\t".repeat(std::cmp::max(level as usize * tabs_per_level, 1)) + text,
+    }
+}
+
+/// number of cards `detect_tabs_per_level` looks at by default
+pub const INDENT_SAMPLE_CARDS: usize = 100;
+
+// guesses how many leading TABs the dictionary uses per margin level from
+// the first `cards` cards. A few badly indented lines are fine, but None is
+// returned if there are no margins or no convention is used by 90% of lines
+pub fn detect_tabs_per_level(content: &str, cards: usize) -> Option<usize> {
+    let mut counts: Vec<(usize, usize)> = Vec::new();
+    let mut total = 0;
+    let mut seen = 0;
+    for l in text_lines(content) {
+        if line_type(l) == DState::Key {
+            seen += 1;
+            if seen > cards {
+                break;
+            }
+            continue;
+        }
+        let tabs = l.chars().take_while(|&c| c == '\t').count();
+        let level = match margin_level(l) {
+            Some(v) if v > 0 && tabs > 0 => v as usize,
+            _ => continue,
+        };
+        total += 1;
+        if tabs % level != 0 {
+            continue;
+        }
+        match counts.iter_mut().find(|(r, _)| *r == tabs / level) {
+            Some((_, n)) => *n += 1,
+            None => counts.push((tabs / level, 1)),
+        }
+    }
+    let (ratio, n) = counts.into_iter().max_by_key(|(_, n)| *n)?;
+    if n * 10 >= total * 9 {
+        Some(ratio)
+    } else {
+        None
+    }
+}
+
+// the first http:// or https:// link that is not wrapped in [url]
+pub fn bare_url(s: &str) -> Option<String> {
+    let text = visible_text(s, &["url"]);
+    let start = match (text.find("http://"), text.find("https://")) {
+        (Some(a), Some(b)) => a.min(b),
+        (a, b) => a.or(b)?,
+    };
+    let url = &text[start..];
+    let end = url.find(char::is_whitespace).unwrap_or(url.len());
+    Some(url[..end].to_string())
+}
+
+/// Texts of examples `[ex]...[/ex]` of a line without tags. With `lang` only
+/// examples of the language are returned: the ones inside a `[lang]` block
+/// of the language or containing such a block
+pub fn examples(s: &str, lang: Option<u16>) -> Vec<String> {
+    let mut res = Vec::new();
+    let mut pos = 0;
+    while let Some(start) = s[pos..].find("[ex]").map(|p| pos + p) {
+        pos = start + 4;
+        if s[..start].ends_with('\\') {
+            continue;
+        }
+        let end = s[pos..].find("[/ex]").map_or(s.len(), |e| pos + e);
+        let body = &s[pos..end];
+        pos = end;
+        if let Some(id) = lang {
+            // the innermost [lang] block the example is inside of, if any
+            let outer = s[..start].rfind("[lang ").filter(|&o| !s[o..start].contains("[/lang]"));
+            let in_lang = outer.is_some_and(|o| s[o + 6..].split(']').next().is_some_and(|a| is_lang(a, id)));
+            if !in_lang && !keep_lang(body, id).1 {
+                continue;
+            }
+        }
+        let text = visible_text(body, &[]).trim().replace('\t', " ");
+        if !text.is_empty() {
+            res.push(text);
+        }
+    }
+    res
+}
+
+// texts of examples `[ex]...[/ex]` that have no translation: a nested [trn]
+// or [lang] tag, or a dash between the phrase and its translation
+pub fn untranslated_examples(s: &str) -> Vec<String> {
+    let mut res = Vec::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("[ex]") {
+        let escaped = rest[..start].ends_with('\\');
+        rest = &rest[start + 4..];
+        if escaped {
+            continue;
+        }
+        let end = rest.find("[/ex]").unwrap_or(rest.len());
+        let body = &rest[..end];
+        let translated = body.contains("[trn]") || body.contains("[lang")
+            || [" \u{2014} ", " \u{2013} ", " - "].iter().any(|d| body.contains(d));
+        if !translated {
+            res.push(visible_text(body, &[]).trim().to_string());
+        }
+        rest = &rest[end..];
+    }
+    res
+}
+
+// a body line has a translation if it contains [trn] or any visible text
+// outside comments and examples
+pub fn has_translation(s: &str) -> bool {
+    s.contains("[trn]") || !visible_text(s, &["com", "ex"]).trim().is_empty()
+}
+
+// Levenshtein distance that counts swapping two adjacent letters as a
+// single edit: `recieve` is one typo away from `receive`
+pub fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut prev2: Vec<usize> = vec![0; b.len() + 1];
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut row: Vec<usize> = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        row[0] = i;
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            row[j] = (prev[j] + 1).min(row[j - 1] + 1).min(prev[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(prev2[j - 2] + 1);
+            }
+        }
+        std::mem::swap(&mut prev2, &mut prev);
+        std::mem::swap(&mut prev, &mut row);
+    }
+    prev[b.len()]
+}
+
+pub fn fix_up_line(s: &str) -> String {
+    fix_up_line_replacing(s).0
+}
+
+// escapes unknown tags and stray brackets like fix_up_line, returns the line
+// and pairs of unknown tags and their escaped replacements
+pub fn fix_up_line_replacing(s: &str) -> (String, Vec<(String, String)>) {
+    let mut replaced = Vec::new();
+    let mut in_sq = false;
+    let mut tag = String::new();
+    let mut escaped = false;
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '[' => {
+                    if escaped {
+                        escaped = false;
+                        if in_sq {
+                            tag.push(c);
+                        } else {
+                            res.push(c);
+                        }
+                        continue;
+                    }
+                    // the bracket opened before is a stray one
+                    if in_sq {
+                        res.push('\\');
+                        res.push_str(&tag);
+                    }
+                    tag.clear();
+                    tag.push('[');
+                    in_sq = true;
+            }
+            ']' => {
+                if escaped {
+                    if in_sq {
+                        tag.push(c);
+                    } else {
+                        res.push(c);
+                    }
+                    escaped = false;
+                    continue;
+                }
+                if !in_sq {
+                    res.push('\\');
+                    res.push(c);
+                    continue;
+                }
+                tag.push(c);
+                in_sq = false;
+                if tag_type(&tag).is_none() {
+                    let t = tag.strip_suffix(']').unwrap_or(&tag);
+                    let tg = format!("\\{}\\]", t);
+                    res.push_str(&tg);
+                    replaced.push((tag.clone(), tg));
+                } else {
+                    res.push_str(&tag);
+                }
+                tag.clear();
+            },
+            _ => if in_sq {
+                tag.push(c);
+            } else {
+                res.push(c);
+            },
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    // unfinished tag at the end of the line
+    if !tag.is_empty() {
+        res.push('\\');
+        res.push_str(&tag);
+    }
+    (res, replaced)
+}
+
+// renames tag `old` to `new` keeping attributes, e.g. `[p]` to `[pos]` and
+// `[/p]` to `[/pos]`, or `[m1]...[/m]` to `[b]...[/b]` without touching
+// `[m2]...[/m]`. Escaped brackets are not tags. Returns the new line
+// and the number of renamed opening tags
+pub fn rename_tag(s: &str, old: &str, new: &str) -> (String, usize) {
+    let mut res = String::with_capacity(s.len());
+    let mut count = 0;
+    // tags with the same closing tag, e.g. `[m1]` and `[m2]` share `[/m]`:
+    // whether the opening tag was renamed
+    let mut opened: Vec<bool> = Vec::new();
+    let mut escaped = false;
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        let end = if c == '[' && !escaped { rest.find(']') } else { None };
+        escaped = c == '\\' && !escaped;
+        let end = match end {
+            None => {
+                res.push(c);
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+            Some(e) => e,
+        };
+        let inner = &rest[1..end];
+        let (slash, tail) = match inner.strip_prefix('/') {
+            Some(t) => ("/", t),
+            None => ("", inner),
+        };
+        let name_len = tail.find(' ').unwrap_or(tail.len());
+        let name = &tail[..name_len];
+        if slash.is_empty() && closing_name(name) == closing_name(old) {
+            opened.push(name == old);
+        }
+        let renamed = if slash.is_empty() { name == old } else { name == closing_name(old) && opened.pop().unwrap_or(false) };
+        if !renamed {
+            res.push_str(&rest[..=end]);
+        } else if slash.is_empty() {
+            count += 1;
+            res.push_str(&format!("[{}{}]", new, &tail[name_len..]));
+        } else {
+            res.push_str(&format!("[/{}]", closing_name(new)));
+        }
+        rest = &rest[end + 1..];
+    }
+    (res, count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn br_is_self_closing() {
+        assert_eq!(tag_type("[br]"), Some((TagKind::Br, false)));
+        assert_eq!(parse_line("\t[m1]line1[br]line2[/m]"), "");
+        assert_eq!(parse_line("\t[m1][b]line1[br]line2[/b][/m]"), "");
+    }
+
+    #[test]
+    fn headword_special_chars() {
+        assert_eq!(headword_specials("{to }go"), Vec::new());
+        assert_eq!(headword_specials("a \\[b\\]"), Vec::new());
+        assert_eq!(headword_specials("a [b]"), vec![(3, '['), (5, ']')]);
+        assert_eq!(headword_specials("a}b{c"), vec![(2, '}'), (4, '{')]);
+        assert_eq!(escape_headword("a [b]"), "a \\[b\\]");
+        assert_eq!(escape_headword("{to }go"), "{to }go");
+        assert_eq!(headword_specials(&escape_headword("a}b{c")), Vec::new());
+    }
+
+    #[test]
+    fn edit_distances() {
+        let dist = |a: &str, b: &str| edit_distance(&a.chars().collect::<Vec<_>>(), &b.chars().collect::<Vec<_>>());
+        assert_eq!(dist("receive", "receive"), 0);
+        assert_eq!(dist("recieve", "receive"), 1);
+        assert_eq!(dist("cat", "cats"), 1);
+        assert_eq!(dist("cat", "dog"), 3);
+        assert_eq!(dist("", "dog"), 3);
+    }
+
+    #[test]
+    fn all_tags_validate() {
+        for t in TAGS.iter() {
+            assert_eq!(t.kind.name(), t.name);
+            assert_eq!(tag_type(&format!("[{}]", t.name)), Some((t.kind, false)), "{}", t.name);
+            if t.self_closing {
+                assert_eq!(parse_line(&format!("\t[m1]a[{}]b[/m]", t.name)), "", "{}", t.name);
+                continue;
+            }
+            let close = format!("[/{}]", closing_name(t.name));
+            if t.kind.is_margin() {
+                // all margins share `[/m]`
+                assert!(tag_type(&close).is_some_and(|(k, closing)| k.is_margin() && closing));
+                assert_eq!(parse_line(&format!("\t[{}]a{}", t.name, close)), "", "{}", t.name);
+                continue;
+            }
+            assert_eq!(tag_type(&close), Some((t.kind, true)), "{}", t.name);
+            assert_eq!(parse_line(&format!("\t[m1][{}]a{}[/m]", t.name, close)), "", "{}", t.name);
+        }
+        assert_eq!(tag_type("[lang id=1]"), Some((TagKind::LangID, false)));
+        assert_eq!(tag_type("[b id=1]"), None);
+        assert_eq!(tag_type("[m3]"), None);
+        assert_eq!(parse_line("\t[m2][trn][lang id=1]a[/lang][/trn][/m]"), "");
+    }
+
+    #[test]
+    fn optional_parts_of_headwords() {
+        assert_eq!(headword_specials("color(u)r"), Vec::new());
+        assert_eq!(index_forms("colo(u)r"), (String::from("colour"), String::from("color")));
+        assert_eq!(index_forms("{to} go"), (String::from("go"), String::from("go")));
+        assert_eq!(headword_specials("word(s"), vec![(5, '(')]);
+        assert_eq!(headword_specials("word)s"), vec![(5, ')')]);
+        assert_eq!(headword_specials("a {b"), vec![(3, '{')]);
+    }
+
+    // a quadratic walk over a line of megabytes would not finish in time
+    #[test]
+    fn huge_lines() {
+        let body = format!("\t[m1]{}[/m]", "[b]word[/b] ".repeat(200_000));
+        assert_eq!(parse_line(&body), "");
+        assert_eq!(fix_up_line(&body), body);
+        let headword = "{a} ".repeat(100_000) + &"}".repeat(100_000);
+        assert_eq!(headword_specials(&headword).len(), 100_000);
+        assert_eq!(escape_headword(&headword).len(), headword.len() + 100_000);
+    }
+
+    #[test]
+    fn media_tags() {
+        let line = "\t[m1][s]cat.wav[/s] [video] cat.mp4 [/video] [i]cat.png[/i][/m]";
+        assert_eq!(media_files(line), vec!["cat.wav", "cat.mp4"]);
+        for name in ["s", "video"] {
+            assert!(tag_info(name).is_some_and(|t| t.opaque && !t.self_closing), "{}", name);
+        }
+        // file names are neither DSL text nor visible text
+        assert_eq!(parse_line("\t[m1][s]a[b].wav[/s][/m]"), "");
+        assert_eq!(visible_text("a [s]cat.wav[/s]", &[]), "a ");
+    }
+
+    #[test]
+    fn known_tags() {
+        for name in ["b", "[b]", "/b", "m1", "/m", "com", "*", "br"] {
+            assert!(is_known_tag(name), "{}", name);
+        }
+        for name in ["x", "m3", "/br", "", "bb"] {
+            assert!(!is_known_tag(name), "{}", name);
+        }
+        // attributes are ignored
+        assert_eq!(tag_info("lang id=1").map(|t| t.kind), Some(TagKind::LangID));
+        assert_eq!(tag_info("[lang name=\"German\"]").map(|t| t.kind), Some(TagKind::LangID));
+        assert_eq!(tag_info("/m").map(|t| t.name), Some("m1"));
+        assert_eq!(tag_info("br").map(|t| t.self_closing), Some(true));
+    }
+
+    #[test]
+    fn error_spans() {
+        let line = "\t[m1]a [xyz]word[/xyz][/m]";
+        let (err, span) = check_line_span(line).unwrap_err();
+        assert!(matches!(err, TagError::UnknownTag(_)), "{:?}", err);
+        assert_eq!(&line[span], "[xyz]");
+        // byte offsets, not character ones
+        let line = "\t[m1]кот [b]word[/m]";
+        let (_, span) = check_line_span(line).unwrap_err();
+        assert_eq!(&line[span], "[/m]");
+        assert_eq!(render_snippet("\t[x]word", 12, &(1..4)), "12 | \t[x]word\n   | \t^^^");
+        assert_eq!(render_snippet("ab", 3, &(5..9)), "3 | ab\n  |   ^");
+    }
+
+    #[test]
+    fn tabs_inside_tag_bodies() {
+        assert_eq!(tabs_inside_tags("\t[m1][com]a\tb[/com][/m]"), vec![12]);
+        assert_eq!(tabs_inside_tags("\t\t[m2]a\tb[/m]"), Vec::new());
+        assert_eq!(tabs_inside_tags("\t[m1][b]a[/b]\t[i]b[br]\t[/i][/m]"), vec![23]);
+        assert_eq!(replace_tag_tabs("\t[m1][com]a\tb[/com][/m]"), "\t[m1][com]a b[/com][/m]");
+        assert_eq!(replace_tag_tabs("\t[m1]a\tb[/m]"), "\t[m1]a\tb[/m]");
+    }
+
+    #[test]
+    fn bare_urls() {
+        assert_eq!(bare_url("\t[m1][trn]see https://example.com/cat here[/trn][/m]"), Some(String::from("https://example.com/cat")));
+        assert_eq!(bare_url("\t[m1]http://a.b and https://c.d[/m]"), Some(String::from("http://a.b")));
+        assert_eq!(bare_url("\t[m1]see [url]https://example.com[/url][/m]"), None);
+        assert_eq!(bare_url("\t[m1]see example.com[/m]"), None);
+    }
+
+    #[test]
+    fn fix_indents() {
+        // over- and under-indented lines
+        assert_eq!(fix_indent("\t\t\t[m1]a[/m]", 1), "\t[m1]a[/m]");
+        assert_eq!(fix_indent("\t[m2]a[/m]", 1), "\t\t[m2]a[/m]");
+        assert_eq!(fix_indent("\t [m2]a[/m]", 2), "\t\t\t\t[m2]a[/m]");
+        // `[m0]` still needs one TAB of a body line
+        assert_eq!(fix_indent("\t\t[m0]a[/m]", 1), "\t[m0]a[/m]");
+        assert_eq!(fix_indent("\tplain text", 1), "\tplain text");
+    }
+
+    #[test]
+    fn examples_without_translation() {
+        assert_eq!(untranslated_examples("\t[m2][ex]a black cat [trn]чёрная кошка[/trn][/ex][/m]"), Vec::<String>::new());
+        assert_eq!(untranslated_examples("\t[m2][ex][lang id=1]eine Katze[/lang] a cat[/ex][/m]"), Vec::<String>::new());
+        assert_eq!(untranslated_examples("\t[m2][ex]a black cat \u{2014} чёрная кошка[/ex][/m]"), Vec::<String>::new());
+        assert_eq!(untranslated_examples("\t[m2][ex]a [b]black[/b] cat[/ex]; [ex]a cat - кошка[/ex][/m]"), vec!["a black cat"]);
+        assert_eq!(untranslated_examples("\t[m2]\\[ex]a cat[/m]"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn fix_up_line_is_idempotent() {
+        let lines = [
+            "\t[m1][xyz]word[/xyz][/m]",
+            "\t[m1]a [b]cat[/m]",
+            "\t[m1]a ] cat [ and \\[escaped\\] [/m]",
+            "\t[m1]\\[xyz\\]word\\[/xyz\\][/m]",
+            "\t[m1]a \\\\[b]bold[/b] cat[/m]",
+            "\t[m1][com]a [i]note[/com][/m]",
+            "\t[m1]plain[/m]",
+        ];
+        for l in lines.iter() {
+            let once = fix_up_line(l);
+            assert_eq!(fix_up_line(&once), once, "{:?}", l);
+        }
+        assert_eq!(fix_up_line("\t[m1][xyz]word[/xyz][/m]"), "\t[m1]\\[xyz\\]word\\[/xyz\\][/m]");
+        assert_eq!(fix_up_line("\t[m1]\\[xyz\\]word[/m]"), "\t[m1]\\[xyz\\]word[/m]");
+        assert_eq!(fix_up_line("\t[m1]a ] cat [ and \\[escaped\\] [/m]"), "\t[m1]a \\] cat \\[ and \\[escaped\\] [/m]");
+        // an escaped backslash does not escape the bracket after it
+        assert_eq!(fix_up_line("\t[m1]a \\\\[b]bold[/b][/m]"), "\t[m1]a \\\\[b]bold[/b][/m]");
+    }
+
+    #[test]
+    fn tabs_per_level() {
+        let two = "cat\r\n\t\t[m1]кошка[/m]\r\n\t\t\t\t[m2]a pet[/m]\r\ndog\r\n\t\t[m1]собака[/m]\r\n";
+        assert_eq!(detect_tabs_per_level(two, 100), Some(2));
+        assert_eq!(detect_tabs_per_level(&two.replace("\t\t", "\t"), 100), Some(1));
+        // one line of ten does not break the convention, half of them do
+        let mostly = "cat\r\n".to_string() + &"\t\t[m1]a[/m]\r\n".repeat(9) + "\t[m1]b[/m]\r\n";
+        assert_eq!(detect_tabs_per_level(&mostly, 100), Some(2));
+        assert_eq!(detect_tabs_per_level("cat\r\n\t\t[m1]a[/m]\r\n\t[m1]b[/m]\r\n", 100), None);
+        assert_eq!(detect_tabs_per_level("cat\r\n\tnot a margin\r\n", 100), None);
+        // only the first cards are sampled
+        assert_eq!(detect_tabs_per_level("cat\r\n\t\t[m1]a[/m]\r\ndog\r\n\t[m1]b[/m]\r\n\t[m1]c[/m]\r\n", 1), Some(2));
+    }
+
+    #[test]
+    fn closing_self_closing_tag() {
+        assert_eq!(check_line("\t[m1]a[br][/br]b[/m]"), Err(TagError::UnexpectedClosingTag(String::from("[/br]"))));
+        let (err, span) = check_line_span("\t[m1]a[br][/br]b[/m]").unwrap_err();
+        assert_eq!((err.kind(), span), ("unexpected-closing-tag", 10..15));
+        assert_eq!(tag_type("[/br]"), None);
+    }
+
+    #[test]
+    fn lang_names() {
+        assert_eq!(lang_name(1033), Some("English"));
+        assert_eq!(lang_name(1049), Some("Russian"));
+        assert_eq!(lang_name(1), Some("English"));
+        assert_eq!(lang_name(9999), None);
+        assert_eq!(lang_ids("\t[m1][lang id=1033]cat[/lang], [lang name=\"German\"]Katze[/lang] [lang id=\"9999\"]x[/lang][/m]"),
+            vec!["1033", "9999"]);
+    }
+
+    #[test]
+    fn margin_close_without_open() {
+        let line = "\t[/m]cat";
+        let (err, span) = check_line_span(line).unwrap_err();
+        assert_eq!(err, TagError::MarginCloseWithoutOpen);
+        assert_eq!(&line[span], "[/m]");
+        assert_eq!(err.to_string(), "margin closing tag '[/m]' without an opening '[mN]'");
+        // other closing tags without an opening one are superfluous
+        assert!(matches!(check_line("\tcat[/b]"), Err(TagError::SuperfluousClosingTag(_))));
+        assert!(matches!(check_line("\t[m1]cat[/m][/m]"), Err(TagError::MarginCloseWithoutOpen)));
+        // a margin opened on a previous line of the card
+        let mut open = vec![TagKind::M1];
+        assert_eq!(check_line_open("\tcat[/m]", &mut open, &[TagKind::M1]), Ok(()));
+        assert!(open.is_empty());
+    }
+
+    #[test]
+    fn replacements_are_returned() {
+        let (line, replaced) = fix_up_line_replacing("\t[m1][x]a[/x][/m]");
+        assert_eq!(line, fix_up_line("\t[m1][x]a[/x][/m]"));
+        assert_eq!(replaced, [(String::from("[x]"), String::from("\\[x\\]")), (String::from("[/x]"), String::from("\\[/x\\]"))]);
+        assert_eq!(fix_up_line_replacing("\t[m1]a[/m]").1, Vec::new());
+    }
+
+    #[test]
+    fn escaped_brackets() {
+        // an escaped bracket is text
+        assert_eq!(check_line("\t[m1]a \\] b[/m]"), Ok(()));
+        // an escaped backslash does not escape the bracket after it
+        assert_eq!(check_line("\t[m1]a \\\\] b[/m]"), Err(TagError::OrphanClosingBracket));
+        assert_eq!(check_line("\t[m1]a \\\\\\] b[/m]"), Ok(()));
+        assert_eq!(check_line("\t[m1]a \\\\\\\\] b[/m]"), Err(TagError::OrphanClosingBracket));
+        assert_eq!(check_line("\t[m1]a \\\\[b]b[/b][/m]"), Ok(()));
+        assert_eq!(visible_text("\t[m1]a \\\\ \\] b[/m]", &[]).trim(), "a \\ ] b");
+    }
+
+    #[test]
+    fn tag_crosses_comment_boundary() {
+        let line = "\t[m1][com]a [b]note[/com] b[/b][/m]";
+        let (err, span) = check_line_span(line).unwrap_err();
+        assert_eq!(err, TagError::TagCrossesCommentBoundary(String::from("[b]")));
+        assert_eq!(&line[span], "[b]");
+        assert_eq!(err.to_string(), "tag '[b]' opened inside comment is not closed before '[/com]'");
+        assert_eq!(check_line("\t[m1][com]a [b]note[/b][/com] b[/m]"), Ok(()));
+        assert_eq!(check_line("\t[m1][b]a [com]note[/com] b[/b][/m]"), Ok(()));
+        // a comment opened on a previous line of the card
+        let mut open = vec![TagKind::Comment];
+        let res = check_line_open("\t[b]note[/com][/b]", &mut open, &[TagKind::Comment]);
+        assert!(matches!(res, Err((TagError::TagCrossesCommentBoundary(_), _))), "{:?}", res);
+    }
+
+    #[test]
+    fn index_keys() {
+        assert_eq!(index_key("Straße"), "strasse");
+        assert_eq!(index_key("STRASSE"), index_key("straße"));
+        assert_eq!(index_key("ẞ"), "ss");
+        // Turkish: İ is a dotted i, ı is another letter than i
+        assert_eq!(index_key("İstanbul"), "istanbul");
+        assert_eq!(index_key("ISTANBUL"), "istanbul");
+        assert_eq!(index_key("ılık"), "ılık");
+        assert_ne!(index_key("ılık"), index_key("ILIK"));
+        assert_eq!(index_key("ΣΟΦΟΣ"), index_key("σοφος"));
+        assert_eq!(index_key("colo(u)r"), "colour");
+    }
+
+    #[test]
+    fn nested_optional_zones() {
+        assert_eq!(check_line("\t[m1][*][*]x[/*][/*][/m]"), Ok(()));
+        assert_eq!(check_line("\t[m1][*]a [*][b]x[/b][/*] b[/*][/m]"), Ok(()));
+        let line = "\t[m1][*][*]x[/*]";
+        let (err, span) = check_line_span(line).unwrap_err();
+        assert_eq!(err, TagError::UnclosedTags(vec![TagKind::M1, TagKind::Optional]));
+        // the outer zone is not closed
+        assert_eq!(span, 5..8);
+        // the innermost tag is reported first
+        let line = "\t[m1][*][b][*]x[/*][/*][/m]";
+        let (err, span) = check_line_span(line).unwrap_err();
+        assert!(matches!(err, TagError::MismatchedTag(TagKind::B, TagKind::Optional, _)), "{:?}", err);
+        assert_eq!(&line[span], "[/*]");
+    }
+
+    #[test]
+    fn byte_swapped_text() {
+        let text = "#NAME \"Test\"\r\n#INDEX_LANGUAGE \"English\"\r\n";
+        let bytes: Vec<u8> = text.encode_utf16().flat_map(|c| c.to_be_bytes()).collect();
+        let (swapped, _, _) = encoding_rs::UTF_16LE.decode(&bytes);
+        assert!(swapped.starts_with('\u{2300}'), "{:?}", swapped);
+        assert!(byte_swapped(&swapped));
+        assert!(!byte_swapped(text));
+        assert!(!byte_swapped("\u{feff}кошка\r\n"));
+        assert!(!byte_swapped(""));
+    }
+
+    #[test]
+    fn keep_one_language() {
+        let line = "\t[m1][lang id=1033]cat[/lang] [lang id=1031]Katze[/lang][/m]";
+        assert_eq!(keep_lang(line, 1031), (String::from("\t[m1] [lang id=1031]Katze[/lang][/m]"), true));
+        assert_eq!(keep_lang(line, 1033), (String::from("\t[m1][lang id=1033]cat[/lang][/m]"), true));
+        assert_eq!(keep_lang(line, 1036), (String::from("\t[m1][/m]"), false));
+        // a language by its name
+        assert!(keep_lang("\t[m1][lang name=\"German\"]Katze[/lang][/m]", 1031).1);
+        assert_eq!(keep_lang("\t[m1]cat[/m]", 1031), (String::from("\t[m1]cat[/m]"), false));
+    }
+
+    #[test]
+    fn words_of_a_text() {
+        assert_eq!(text_words("a cat, the cat's toy; well-known 2 cats 3d"), ["a", "cat", "the", "cat's", "toy", "well-known", "cats", "3d"]);
+        assert_eq!(text_words("кошка — 'pet' -x-"), ["кошка", "pet", "x"]);
+        assert_eq!(text_words(""), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn rename_tags() {
+        assert_eq!(rename_tag("\t[m1][p]n[/p] [b]cat[/b] [p]pl[/p][/m]", "p", "pos"), (String::from("\t[m1][pos]n[/pos] [b]cat[/b] [pos]pl[/pos][/m]"), 2));
+        // attributes are kept, escaped brackets are text
+        assert_eq!(rename_tag("\t[m1][lang id=1033]cat[/lang] \\[lang][/m]", "lang", "l"), (String::from("\t[m1][l id=1033]cat[/l] \\[lang][/m]"), 1));
+        // only the closing tag of a renamed margin changes
+        assert_eq!(rename_tag("\t[m1]a[/m] [m2]b[/m]", "m2", "m3"), (String::from("\t[m1]a[/m] [m3]b[/m]"), 1));
+        assert_eq!(rename_tag("\t[m1]cat[/m]", "p", "pos"), (String::from("\t[m1]cat[/m]"), 0));
+    }
+
+    #[test]
+    fn line_kinds() {
+        let lines = [
+            ("#NAME \"Test\"", LineKind::Name),
+            ("#INDEX_LANGUAGE \"English\"", LineKind::IndexLanguage),
+            ("#CONTENTS_LANGUAGE \"Russian\"", LineKind::ContentsLanguage),
+            ("#INCLUDE \"other.dsl\"", LineKind::Include),
+            ("## TODO", LineKind::EditorComment),
+            ("; TODO", LineKind::EditorComment),
+            ("", LineKind::Empty),
+            ("cat", LineKind::Headword),
+            ("\tII", LineKind::RomanNumber),
+            ("\t[m1]кошка[/m]", LineKind::Margin(1)),
+            ("\t[m2][ex]a cat[/ex][/m]", LineKind::Margin(2)),
+            ("\t[com]note[/com]", LineKind::Comment),
+            ("\tкошка", LineKind::Text),
+            ("\t", LineKind::Text),
+            ("\t[b]кошка[/b]", LineKind::Invalid),
+        ];
+        for (line, kind) in lines.iter() {
+            assert_eq!(classify_line(line), *kind, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn line_order() {
+        use DState::*;
+        let accepted = [
+            (Begin, Name), (Name, Index), (Index, Lang), (Lang, EmptyLine), (Lang, Include), (Include, EmptyLine),
+            (EmptyLine, Key), (EmptyLine, EmptyLine), (Key, M1), (Key, Comment), (Key, Text), (Key, RomanNumber),
+            (M1, M2), (M2, M1), (M1, M1), (M2, Key), (Text, M2), (Comment, Key), (RomanNumber, M1),
+        ];
+        for (prev, curr) in accepted.iter() {
+            assert!(can_follow(*prev, *curr), "{:?} -> {:?}", prev, curr);
+        }
+        let rejected = [
+            (Begin, Key), (Name, Lang), (Lang, Key), (EmptyLine, M1), (Key, Key), (Key, EmptyLine), (Key, M2),
+            (M1, EmptyLine), (M2, EmptyLine), (RomanNumber, M2), (M1, Invalid), (Invalid, Key),
+        ];
+        for (prev, curr) in rejected.iter() {
+            assert!(!can_follow(*prev, *curr), "{:?} -> {:?}", prev, curr);
+        }
+        assert!(can_end(M1) && can_end(Text) && can_end(EmptyLine));
+        assert!(!can_end(Key) && !can_end(Lang) && !can_end(RomanNumber));
+        // the same line kinds cover tags: a margin line and its tag
+        assert_eq!(line_type("\t[m2]a[/m]"), M2);
+        assert_eq!(tag_type("[m2]"), Some((TagKind::M2, false)));
+        assert_eq!(tag_type("[/m]").map(|(_, closing)| closing), Some(true));
+    }
+
+    #[test]
+    fn tag_open_at_line_end() {
+        // a closed opening tag is unclosed, not unfinished
+        assert_eq!(check_line_span("\t[m1][b]x"), Err((TagError::UnclosedTags(vec![TagKind::M1, TagKind::B]), 5..8)));
+        assert_eq!(check_line_span("\t[m1]x[b]"), Err((TagError::UnclosedTags(vec![TagKind::M1, TagKind::B]), 6..9)));
+        assert_eq!(check_line_span("\t[m1]x[b"), Err((TagError::UnfinishedTag(String::from("[b")), 6..8)));
+    }
+
+    #[test]
+    fn unknown_tag_ending_with_brackets() {
+        // only the bracket that ends the tag is replaced
+        let fixed = fix_up_line("\t[m1][\\]][/m]");
+        assert_eq!(fixed, "\t[m1]\\[\\]\\][/m]");
+        assert_eq!(check_line(&fixed), Ok(()));
+    }
+
+    // the properties of the fuzz targets on their seed corpus, so a change
+    // that breaks them fails without cargo fuzz
+    #[test]
+    fn fuzz_seeds() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/corpus/fix_up_line");
+        let mut seeds = 0;
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let s = String::from_utf8(std::fs::read(entry.unwrap().path()).unwrap()).unwrap();
+            let _ = check_line(&s);
+            let fixed = fix_up_line(&s);
+            let res = check_line(&fixed);
+            assert!(!matches!(res, Err(TagError::BracketInsideTag(_)) | Err(TagError::OrphanClosingBracket) | Err(TagError::UnknownTag(_))
+                | Err(TagError::UnexpectedClosingTag(_)) | Err(TagError::UnfinishedTag(_))), "{:?} is fixed as {:?}: {:?}", s, fixed, res);
+            seeds += 1;
+        }
+        assert!(seeds > 0);
+    }
+
+    #[test]
+    fn mismatched_tag_message() {
+        let err = check_line("\t[m1]a [b]cat[/i][/m]").unwrap_err();
+        assert_eq!(err, TagError::MismatchedTag(TagKind::B, TagKind::I, Some((8, String::from("[b]")))));
+        assert_eq!(err.to_string(), "expected '[/b]' for '[b]' at column 8, found '[/i]'");
+        // the opening tag as it is written, with its attributes
+        let err = check_line("\t[m1]кот [lang id=2]a[/trn][/m]").unwrap_err();
+        assert_eq!(err.to_string(), "expected '[/lang]' for '[lang id=2]' at column 10, found '[/trn]'");
+        let err = check_line("\t[m2][b]a[/m]").unwrap_err();
+        assert_eq!(err.to_string(), "expected '[/b]' for '[b]' at column 6, found '[/m]'");
+        let mut open = vec![TagKind::M1, TagKind::Comment];
+        let (err, _) = check_line_open("\tnote[/i]", &mut open, &[TagKind::M1, TagKind::Comment]).unwrap_err();
+        assert_eq!(err.to_string(), "expected '[/com]' for '[com]' opened on a previous line, found '[/i]'");
+    }
+
+    #[test]
+    fn unknown_tag_names() {
+        assert_eq!(unknown_tags("\t[m1][x]a[/x] [b]b[/b] [sup]2[/sup] [y id=1][/m]"), ["x", "x", "sup", "sup", "y"]);
+        assert_eq!(unknown_tags("\t[m1]\\[x\\] [s]a[x].wav[/s][/m]"), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn enclosing_tags_mid_card() {
+        let text = "#NAME \"t\"\r\n\r\ncat\r\n\t[m1][com]a [i]long\r\n## note\r\n\tnote[/i]\r\n\tgoes on[/com][/m]\r\ndog\r\n\t[m1]собака[/m]\r\n";
+        assert_eq!(enclosing_tags(text, 3), Vec::new());
+        assert_eq!(enclosing_tags(text, 5), vec![TagKind::M1, TagKind::Comment, TagKind::I]);
+        assert_eq!(enclosing_tags(text, 6), vec![TagKind::M1, TagKind::Comment]);
+        // a headword starts a new card
+        assert_eq!(enclosing_tags(text, 2), Vec::new());
+        assert_eq!(enclosing_tags(text, 7), Vec::new());
+        assert_eq!(enclosing_tags(text, 8), Vec::new());
+        // lines with errors do not change the tags
+        let text = "cat\r\n\t[m1][b]one\r\n\ttwo[/i]\r\n\tthree[/b][/m]\r\n";
+        assert_eq!(enclosing_tags(text, 3), vec![TagKind::M1, TagKind::B]);
+        assert_eq!(enclosing_tags(text, 100), Vec::new());
+    }
+
+    #[test]
+    fn repeated_margins() {
+        assert_eq!(redundant_margins("\t[m1][m1]word[/m][/m]"), vec![6]);
+        assert_eq!(redundant_margins("\t[m1][m1][m1]word[/m][/m][/m]"), vec![6, 10]);
+        assert_eq!(redundant_margins("\t[m1]word [m1]more[/m][/m]"), Vec::new());
+        assert_eq!(redundant_margins("\t[m1]\\[m1][m1]word[/m]"), Vec::new());
+        assert_eq!(collapse_margins("\t[m1][m1]word[/m][/m]"), "\t[m1]word[/m]");
+        assert_eq!(collapse_margins("\t[m1][m1][m1]word[/m][/m][/m]"), "\t[m1]word[/m]");
+        // a line without the extra closing tags keeps the one it has
+        assert_eq!(collapse_margins("\t[m2][m2]word[/m]"), "\t[m2]word[/m]");
+        assert_eq!(check_line(&collapse_margins("\t[m1][m1]word[/m][/m]")), Ok(()));
+    }
+
+    #[test]
+    fn text_lines_split_like_split_lines() {
+        let text = "a\r\nb\rc\nd";
+        assert_eq!(text_lines(text).collect::<Vec<_>>(), vec!["a", "b", "c", "d"]);
+        assert_eq!(text_lines(text).count(), split_lines(text).count());
+        assert_eq!(text_lines("a\r\r\nb\n\n").collect::<Vec<_>>(), vec!["a", "", "b", ""]);
+    }
+
+    #[test]
+    fn lone_surrogates_count_bare_cr_lines() {
+        let mut bytes: Vec<u8> = "\u{feff}a\rb\r\nc".encode_utf16().flat_map(|c| c.to_le_bytes()).collect();
+        bytes.extend_from_slice(&0xd800u16.to_le_bytes());
+        assert_eq!(lone_surrogates(&bytes, false), vec![(2, 12, 2)]);
+    }
+
+    #[test]
+    fn enclosing_tags_count_bare_cr_lines() {
+        let text = "cat\r\t[m1][b]one\r\ttwo[/b][/m]\r";
+        assert_eq!(enclosing_tags(text, 2), vec![TagKind::M1, TagKind::B]);
+    }
+
+    #[test]
+    fn example_texts() {
+        let line = "\t[m2][ex][b]a[/b] cat — кошка[/ex]; [ex][lang id=1031]eine Katze[/lang][/ex] \\[ex]no[/m]";
+        assert_eq!(examples(line, None), ["a cat — кошка", "eine Katze"]);
+        assert_eq!(examples(line, Some(1031)), ["eine Katze"]);
+        assert_eq!(examples("\t[m2][lang id=1033][ex]a cat[/ex][/lang] [ex]кошка[/ex][/m]", Some(1033)), ["a cat"]);
+        assert_eq!(examples("\t[m2][ex] [/ex][/m]", None), Vec::<String>::new());
+    }
+
+    #[test]
+    fn bracket_audit() {
+        assert_eq!(bracket_counts("\t[m1][b]cat[/b] \\[1\\][/m]"), (2, 0));
+        assert_eq!(bracket_counts("\t[m1]a [x] b] [c [i]d[/i][/m]"), (0, 4));
+        assert_eq!(bracket_counts("\t[m1]\\\\[b]a[/b][/m]"), (0, 0));
+        // fix_up_line escapes exactly the literal brackets
+        let line = "\t[m1]a [x] b] [c [i]d[/i][/m]";
+        assert_eq!(bracket_counts(&fix_up_line(line)), (4, 0));
+    }
+
+    #[test]
+    fn nested_lang_blocks() {
+        assert_eq!(check_line("\t[m1][lang id=1]a [lang id=2]b[/lang] c[/lang][/m]"), Ok(()));
+        let line = "\t[m1][lang id=1]a [i]b [lang id=2]c[/i][/lang][/lang][/m]";
+        let err = check_line(line).unwrap_err();
+        assert_eq!(err.to_string(), "expected '[/lang]' for '[lang id=2]' at column 24, found '[/i]'");
+        assert_eq!(lang_depth("\t[m1][lang id=1]a [lang id=2]b[/lang] [lang id=3]c[/lang][/lang][/m]"), (2, 19));
+        assert_eq!(lang_depth("\t[m1][lang id=1]a[/lang] [lang id=2]b[/lang][/m]"), (1, 6));
+        assert_eq!(lang_depth("\t[m1]\\[lang id=1]a[/m]"), (0, 0));
+    }
+
+    #[test]
+    fn empty_formatting_tags() {
+        assert_eq!(empty_formatting("\t[m1]a[b][/b] [i] [/i]b[/m]"), vec![7, 15]);
+        assert_eq!(empty_formatting("\t[m1][b][i][/i][/b][/m]"), vec![6, 9]);
+        // text, an escaped character, and a file name are not empty
+        assert_eq!(empty_formatting("\t[m1][b]x[/b] [i]\\[[/i] [s]a.wav[/s][/m]"), Vec::<usize>::new());
+        assert_eq!(empty_formatting("\t[m1][/m]"), Vec::<usize>::new());
+        assert_eq!(remove_empty_formatting("\t[m1]a[i] [/i]b[b][/b][/m]"), "\t[m1]a b[/m]");
+        assert_eq!(remove_empty_formatting("\t[m1][b][i][/i][/b]x[/m]"), "\t[m1]x[/m]");
+    }
+
+    #[test]
+    fn part_of_speech() {
+        assert_eq!(split_pos("bank [p]n[/p]"), ("bank", Some("n")));
+        assert_eq!(split_pos("big cat  [p] n [/p] "), ("big cat", Some("n")));
+        assert_eq!(split_pos("run[p]v[/p]"), ("run[p]v[/p]", None));
+        assert_eq!(split_pos("bank [p][/p]"), ("bank [p][/p]", None));
+        assert_eq!(split_pos("bank [p]n[/p] x"), ("bank [p]n[/p] x", None));
+        assert_eq!(split_pos(" [p]n[/p]"), (" [p]n[/p]", None));
+        assert_eq!(split_pos("bank"), ("bank", None));
+    }
+}
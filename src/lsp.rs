@@ -0,0 +1,124 @@
+// Diagnostics in the shape of the Language Server Protocol. Positions are
+// 0-based and columns are counted in UTF-16 code units, as LSP requires
+
+use std::ops::Range;
+
+use crate::card::ParseError;
+use crate::checker::{Diagnostic, Severity};
+
+#[derive(Debug,PartialEq,Copy,Clone)]
+pub struct LspPosition {
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Debug,PartialEq,Copy,Clone)]
+pub struct LspRange {
+    pub start: LspPosition,
+    pub end: LspPosition,
+}
+
+#[derive(Debug,PartialEq,Copy,Clone)]
+pub enum LspSeverity {
+    Error = 1,
+    Warning = 2,
+    Information = 3,
+    Hint = 4,
+}
+
+#[derive(Debug,PartialEq,Clone)]
+pub struct LspDiagnostic {
+    pub range: LspRange,
+    pub severity: LspSeverity,
+    /// problem kind, e.g. `unknown-tag`
+    pub code: String,
+    pub source: &'static str,
+    pub message: String,
+}
+
+// column of the byte offset `pos` of the line in UTF-16 code units
+fn utf16_col(line: &str, pos: usize) -> u32 {
+    let mut pos = pos.min(line.len());
+    while !line.is_char_boundary(pos) {
+        pos -= 1;
+    }
+    line[..pos].encode_utf16().count() as u32
+}
+
+fn line_range(line_no: usize, line: &str, span: &Range<usize>) -> LspRange {
+    let line_no = line_no as u32;
+    LspRange {
+        start: LspPosition { line: line_no, character: utf16_col(line, span.start) },
+        end: LspPosition { line: line_no, character: utf16_col(line, span.end) },
+    }
+}
+
+impl From<Severity> for LspSeverity {
+    fn from(s: Severity) -> LspSeverity {
+        match s {
+            Severity::Error => LspSeverity::Error,
+            Severity::Warning => LspSeverity::Warning,
+            Severity::Info => LspSeverity::Information,
+        }
+    }
+}
+
+impl Diagnostic {
+    /// `line` is the text of the line the diagnostic points to, the whole
+    /// line is highlighted. `Diagnostic::line` is 0-based already and is
+    /// used as is
+    pub fn to_lsp(&self, line: &str) -> LspDiagnostic {
+        LspDiagnostic {
+            range: line_range(self.line, line, &(0..line.len())),
+            severity: self.severity.into(),
+            code: self.kind.to_string(),
+            source: "dsldoc",
+            message: self.message.clone(),
+        }
+    }
+}
+
+impl ParseError {
+    /// `line` is the text of the line the error points to, only the
+    /// offending text is highlighted
+    pub fn to_lsp(&self, line: &str) -> LspDiagnostic {
+        LspDiagnostic {
+            range: line_range(self.line, line, &self.span),
+            severity: LspSeverity::Error,
+            code: String::from("parse-error"),
+            source: "dsldoc",
+            message: self.message.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card::cards;
+
+    #[test]
+    fn diagnostic_line_passes_through() {
+        let line = "\t[m1]кот 😀[/m]";
+        let d = Diagnostic { line: 4, byte_offset: 100, severity: Severity::Warning, kind: "bare-url", message: String::from("m"), data: Vec::new() };
+        let lsp = d.to_lsp(line);
+        // `Diagnostic::line` is 0-based, so it is not changed
+        // the emoji takes two UTF-16 code units
+        assert_eq!(lsp.range, LspRange { start: LspPosition { line: 4, character: 0 }, end: LspPosition { line: 4, character: 15 } });
+        assert_eq!((lsp.severity, lsp.code.as_str(), lsp.source), (LspSeverity::Warning, "bare-url", "dsldoc"));
+        assert_eq!(LspSeverity::from(Severity::Info) as u8, 3);
+    }
+
+    #[test]
+    fn parse_error_to_lsp() {
+        let line = "\t[m1]кот [x]a[/m]";
+        let err = cards(&format!("cat\r\n{}\r\n", line)).next().unwrap().unwrap_err();
+        let lsp = err.to_lsp(line);
+        // 0-based line of the file, the span covers `[x]` only
+        assert_eq!(lsp.range, LspRange { start: LspPosition { line: 1, character: 9 }, end: LspPosition { line: 1, character: 12 } });
+        assert_eq!(lsp.severity, LspSeverity::Error);
+        // a position inside a character is moved to its start
+        assert_eq!(utf16_col("кот", 3), 1);
+        assert_eq!(utf16_col("кот", 100), 3);
+    }
+}
@@ -1,366 +1,2291 @@
 use std::env;
 use std::process::exit;
 use std::fs;
-use std::path::Path;
-use std::io::{self, BufRead, Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::io::{self, IsTerminal, Read, Write};
 use std::collections::HashMap;
 
-use encoding_rs;
+use dsldoc::{bracket_counts, byte_swapped, can_follow, check_line, closing_name, collapse_margins, detect_tabs_per_level, escape_headword, examples, fix_indent, fix_up_line, fix_up_line_replacing, fold_case, header_value, index_forms, index_key, is_known_tag,
+    keep_lang, lone_surrogates, line_type, media_files, rename_tag, remove_empty_formatting, replace_tag_tabs, split_lines, tag_contents, text_lines, text_words, unknown_tags, visible_text, DState, INDENT_SAMPLE_CARDS, TAGS};
+use dsldoc::checker::{limit_errors, Checker, Diagnostic, Severity, TraceEvent};
+use dsldoc::{card, digest, gzip, html, schema};
 
 #[derive(Debug,PartialEq,Copy,Clone)]
-enum DState {
-    Begin,
-    Name,
-    Index,
-    Lang,
-    EmptyLine,
-    Key,
-    Comment,
+enum Format {
     Text,
-    M1,
-    M2,
-    P,
-    I,
-    C,
-    B,
-    Ex,
-    RomanNumber,
-    LangID,
-
-    MClose,
-    IClose,
-    ComClose,
-    PClose,
-    CClose,
-    BClose,
-    ExClose,
-    LangIDClose,
-
-    Invalid,
-}
-
-fn load_utf16_file(filename: &str) -> String {
+    Json,
+}
+
+// hash function of the hash command
+#[derive(Debug,PartialEq,Copy,Clone)]
+enum Algorithm {
+    Sha256,
+    Blake3,
+}
+
+// order of problems in the output of `check`
+#[derive(Debug,PartialEq,Copy,Clone)]
+enum SortBy {
+    Line,
+    Kind,
+}
+
+// problems that make `check` exit with code 1
+#[derive(Debug,PartialEq,Copy,Clone)]
+enum FailOn {
+    Error,
+    Warning,
+    Never,
+}
+
+struct Options {
+    compress: bool,
+    format: Format,
+    fail_on: FailOn,
+    // --severity KIND=LEVEL
+    severities: Vec<(String, Severity)>,
+    algorithm: Algorithm,
+    input_encoding: &'static encoding_rs::Encoding,
+    allow_monolingual: bool,
+    escape_headwords: bool,
+    context: usize,
+    require_translation: bool,
+    near_dup: Option<usize>,
+    dup_ignore_trailing_punct: bool,
+    check_script: bool,
+    check_direction: bool,
+    errors_only: bool,
+    // diagnostic kinds to show and to hide
+    only: Vec<String>,
+    ignore: Vec<String>,
+    no_duplicate_check: bool,
+    hash_headwords: bool,
+    verbose: bool,
+    trace: bool,
+    lenient: bool,
+    allow_tags: Vec<String>,
+    multiline_tags: Vec<String>,
+    forbid_tags: Vec<String>,
+    max_errors: Option<usize>,
+    expand_optional: bool,
+    warn_bare_urls: bool,
+    check_redundant_margins: bool,
+    collapse_margins: bool,
+    flag_empty_formatting: bool,
+    remove_empty_formatting: bool,
+    flag_untranslated: bool,
+    max_line_length: Option<usize>,
+    min_translation_length: Option<usize>,
+    max_lang_depth: Option<usize>,
+    only_card: Option<String>,
+    check_indent: bool,
+    resolve_lang_ids: bool,
+    check_examples: bool,
+    abbrev_consistency: bool,
+    schema: Option<String>,
+    strip_bom: bool,
+    replace_tag_tabs: bool,
+    summary_only: bool,
+    sort_by_severity: bool,
+    sort_by: SortBy,
+    // glob patterns of files and directories to skip when checking a directory
+    exclude: Vec<String>,
+    glob: Option<String>,
+    top: Option<usize>,
+    count: usize,
+    entries_per_page: Option<usize>,
+    report_file: Option<String>,
+    follow_includes: bool,
+    strict_media: bool,
+    report_shared: bool,
+    preserve_comments: bool,
+    lang_id: Option<u16>,
+    min_count: usize,
+    ignore_case: bool,
+    // tag renames of retag, names without brackets
+    tag_maps: Vec<(String, String)>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options { compress: false, format: Format::Text, fail_on: FailOn::Error, severities: Vec::new(), algorithm: Algorithm::Sha256, input_encoding: encoding_rs::UTF_16LE, allow_monolingual: false,
+            escape_headwords: false, context: 0,
+            require_translation: false, near_dup: None, dup_ignore_trailing_punct: false,
+            check_script: false, check_direction: false, errors_only: false, only: Vec::new(), ignore: Vec::new(), no_duplicate_check: false, hash_headwords: false, verbose: false, trace: false, lenient: false,
+            allow_tags: Vec::new(), multiline_tags: Vec::new(), forbid_tags: Vec::new(), max_errors: None,
+            expand_optional: false, warn_bare_urls: false, check_redundant_margins: false, collapse_margins: false, flag_empty_formatting: false, remove_empty_formatting: false, flag_untranslated: false, max_line_length: None, min_translation_length: None, max_lang_depth: None, only_card: None, check_indent: false, resolve_lang_ids: false, check_examples: false, abbrev_consistency: false, schema: None,
+            strip_bom: false, replace_tag_tabs: false, summary_only: false, sort_by_severity: false, sort_by: SortBy::Line,
+            exclude: Vec::new(), glob: None, top: None, count: 5, entries_per_page: None,
+            report_file: None, follow_includes: false, strict_media: false, report_shared: false, preserve_comments: false, lang_id: None, min_count: 1, ignore_case: false, tag_maps: Vec::new() }
+    }
+}
+
+// reads the whole file and unpacks it if it is gzip-compressed
+fn read_dict_file(filename: &str) -> Vec<u8> {
     let path = Path::new(filename);
     let bytes = match fs::read(path) {
         Err(e) => {
             println!("{}", e);
             exit(1);
         }
-        Ok(v) => v,
-    };
-    let (res, _enc, _used) = encoding_rs::UTF_16LE.decode(&bytes);
-    res.to_string()
-}
-
-fn roman_to_u8(s: &str) -> u8 {
-    match s.trim() {
-        "I" => 1,
-        "II" => 2,
-        "III" => 3,
-        "IV" => 4,
-        "V" => 5,
-        "VI" => 6,
-        "VII" => 7,
-        "VIII" => 8,
-        "IX" => 9,
-        "X" => 10,
-        _ => 0,
-    }
-}
-
-fn tag_type(s: &str) -> DState {
-    if s.starts_with("[lang id=") {
-        return DState::LangID;
-    }
-    match s {
-        "[com]" => DState::Comment,
-        "[/com]" => DState::ComClose,
-        "[m1]" => DState::M1,
-        "[m2]" => DState::M2,
-        "[/m]" => DState::MClose,
-        "[p]" => DState::P,
-        "[/p]" => DState::PClose,
-        "[i]" => DState::I,
-        "[/i]" => DState::IClose,
-        "[ex]" => DState::Ex,
-        "[/ex]" => DState::ExClose,
-        "[c]" => DState::C,
-        "[/c]" => DState::CClose,
-        "[b]" => DState::B,
-        "[/b]" => DState::BClose,
-        "[/lang]" => DState::LangIDClose,
-        _ => DState::Invalid,
-    }
-}
-
-fn line_type(s: &str) -> DState {
-    if s.is_empty() {
-        return DState::EmptyLine;
-    }
-    if s.starts_with("#NAME ") {
-        return DState::Name;
-    }
-    if s.starts_with("#INDEX_LANGUAGE ") {
-        return DState::Index;
-    }
-    if s.starts_with("#CONTENTS_LANGUAGE ") {
-        return DState::Lang;
-    }
-    if !s.starts_with('\t') {
-        return DState::Key;
-    }
-    let tr = s.trim();
-    if tr == "I" || tr == "II" || tr == "III" || tr == "IV" || tr == "V" || tr == "VI" || tr == "VII" || tr == "VIII" || tr == "IX" || tr == "X" {
-        return DState::RomanNumber;
-    }
-    if !s.starts_with("\t[") {
-        return DState::Text;
-    }
-    if s.starts_with("\t[m1]") {
-        return DState::M1;
-    }
-    if s.starts_with("\t[m2]") {
-        return DState::M2;
-    }
-    if s.contains("[com]") {
-        return DState::Comment;
-    }
-    DState::Invalid
-}
-
-fn can_follow(prev: DState, curr: DState) -> bool {
-    match prev {
-        DState::Begin => curr == DState::Name,
-        DState::Name => curr == DState::Index,
-        DState::Index => curr == DState::Lang,
-        DState::Lang => curr == DState::EmptyLine,
-        DState::EmptyLine => curr == DState::Key || curr == DState::EmptyLine,
-        DState::Key => curr == DState::Comment || curr == DState::Text || curr == DState::M1 || curr == DState::RomanNumber,
-        DState::Comment => curr == DState::Text || curr == DState::M1 || curr == DState::Comment || curr == DState::RomanNumber || curr == DState::Key,
-        DState::Text => curr == DState::Comment || curr == DState::Key || curr == DState::M1 || curr == DState::M2 || curr == DState::Text || curr == DState::RomanNumber,
-        DState::M1 => curr == DState::Comment || curr == DState::Key || curr == DState::M1 || curr == DState::M2 || curr == DState::RomanNumber || curr == DState::Text,
-        DState::M2 => curr == DState::Comment || curr == DState::Key || curr == DState::M1 || curr == DState::M2 || curr == DState::RomanNumber || curr == DState::Text,
-        DState::RomanNumber => curr == DState::Comment || curr == DState::M1 || curr == DState::Text,
-        _ => false,
-    }
-}
-
-fn parse_line(s: &str) -> String {
-    let s = s.trim();
-    let mut in_sq = false;
-    let mut tag = String::new();
-    let mut stack: Vec<DState> = Vec::new();
-    let mut last_c = ' ';
-    for c in s.chars() {
-        match c {
-            '[' => {
-                    if last_c == '\\' {
-                        last_c = c;
-                        continue;
-                    }
-                    if in_sq {
-                        return format!("opening bracket inside tag: '{}['", tag);
-                    }
-                    tag = String::from("[");
-                    in_sq = true;
+        Ok(v) => v,
+    };
+    if filename.ends_with(".dz") || gzip::is_gzip(&bytes) {
+        match gzip::decompress(&bytes) {
+            Err(e) => {
+                println!("{}: {}", filename, e);
+                exit(1);
+            }
+            Ok(v) => v,
+        }
+    } else {
+        bytes
+    }
+}
+
+fn bom_len(bytes: &[u8]) -> usize {
+    if bytes.starts_with(&[0xff, 0xfe]) || bytes.starts_with(&[0xfe, 0xff]) {
+        2
+    } else {
+        0
+    }
+}
+
+// `enc` is UTF-16LE or UTF-16BE, a BOM overrides it
+fn load_utf16_file(filename: &str, enc: &'static encoding_rs::Encoding) -> String {
+    let bytes = read_dict_file(filename);
+    let (res, _enc, had_errors) = enc.decode(&bytes);
+    // stderr keeps CSV and JSON output intact
+    if had_errors {
+        eprintln!("warning: {} is not valid UTF-16, broken characters are replaced with U+FFFD", filename);
+    }
+    // decode removes the BOM, but a converter may leave a second one that
+    // hides the #NAME header
+    res.strip_prefix('\u{feff}').unwrap_or(&res).to_string()
+}
+
+// the decoder replaces broken characters with U+FFFD silently: tells where
+// they are, or at least that there are some, e.g. an odd byte at the end
+fn decode_errors(bytes: &[u8], big_endian: bool, cont: &str) -> Vec<Diagnostic> {
+    let lines: Vec<&str> = text_lines(cont).collect();
+    let mut res: Vec<Diagnostic> = lone_surrogates(bytes, big_endian).into_iter().map(|(line, byte_offset, col)| Diagnostic {
+        line, byte_offset, severity: Severity::Error, kind: "invalid-surrogate",
+        message: format!("invalid UTF-16 surrogate at column {} is replaced with U+FFFD ==> {}", col, lines.get(line).unwrap_or(&"")),
+        data: vec![("column", col)] }).collect();
+    if res.is_empty() {
+        res.push(Diagnostic { line: 0, byte_offset: 0, severity: Severity::Error, kind: "invalid-utf16",
+            message: String::from("the file is not valid UTF-16, broken characters are replaced with U+FFFD"), data: Vec::new() });
+    }
+    res
+}
+
+// decodes a file in any encoding the tool reads or writes: the encoding is
+// taken from the BOM; without a BOM a text with zero high bytes of ASCII
+// characters is UTF-16LE, the rest is UTF-8
+fn decode_any(bytes: &[u8]) -> String {
+    if let Some((enc, _)) = encoding_rs::Encoding::for_bom(bytes) {
+        return enc.decode(bytes).0.to_string();
+    }
+    let zeros = bytes.iter().skip(1).step_by(2).filter(|&&b| b == 0).count();
+    let enc = if bytes.len() >= 2 && zeros * 4 >= bytes.len() / 2 { encoding_rs::UTF_16LE } else { encoding_rs::UTF_8 };
+    enc.decode(bytes).0.to_string()
+}
+
+// hash of the dictionary text that does not depend on the encoding, the
+// compression, and line endings of the file
+fn content_hash(filename: &str, algorithm: Algorithm) -> String {
+    let text = decode_any(&read_dict_file(filename));
+    let text = text.strip_prefix('\u{feff}').unwrap_or(&text);
+    let mut norm = String::with_capacity(text.len());
+    for l in text_lines(text) {
+        norm.push_str(l);
+        norm.push('\n');
+    }
+    let hash = match algorithm {
+        Algorithm::Sha256 => digest::sha256(norm.as_bytes()),
+        Algorithm::Blake3 => digest::blake3(norm.as_bytes()),
+    };
+    digest::to_hex(&hash)
+}
+
+// compares the text of two files in different encodings or compression,
+// line endings are ignored. Returns true if they differ
+fn verify_encoding(first: &str, second: &str) -> bool {
+    let a = decode_any(&read_dict_file(first));
+    let b = decode_any(&read_dict_file(second));
+    let mut la = text_lines(&a);
+    let mut lb = text_lines(&b);
+    let mut idx = 0;
+    loop {
+        match (la.next(), lb.next()) {
+            (None, None) => {
+                println!("the texts are identical");
+                return false;
+            }
+            (Some(x), Some(y)) if x == y => {},
+            (x, y) => {
+                let (x, y) = (x.unwrap_or_default(), y.unwrap_or_default());
+                let col = x.chars().zip(y.chars()).take_while(|(c1, c2)| c1 == c2).count();
+                println!("{:4}.texts differ at column {}:\n    {}\n    {}", idx, col + 1, x, y);
+                return true;
+            }
+        }
+        idx += 1;
+    }
+}
+
+fn json_escape(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+fn prefix(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Warning => "warning: ",
+        Severity::Error | Severity::Info => "",
+    }
+}
+
+fn write_diagnostics(out: &mut dyn Write, diags: &[Diagnostic], format: Format) -> std::io::Result<()> {
+    match format {
+        Format::Text => {
+            for d in diags.iter() {
+                writeln!(out, "{:4}.{}{}", d.line, prefix(d.severity), d.message)?;
+            }
+        }
+        Format::Json => {
+            writeln!(out, "[")?;
+            for (i, d) in diags.iter().enumerate() {
+                let sep = if i + 1 == diags.len() { "" } else { "," };
+                let data: String = d.data.iter().map(|(k, v)| format!(", \"{}\": {}", k, v)).collect();
+                writeln!(out, "  {{\"line\": {}, \"byte_offset\": {}, \"severity\": \"{}\", \"kind\": \"{}\", \"message\": \"{}\"{}}}{}",
+                    d.line, d.byte_offset, d.severity.name(), d.kind, json_escape(&d.message), data, sep)?;
+            }
+            writeln!(out, "]")?;
+        }
+    }
+    Ok(())
+}
+
+fn print_diagnostics(diags: &[Diagnostic], format: Format) {
+    let _ = write_diagnostics(&mut std::io::stdout(), diags, format);
+}
+
+// appends diagnostics to the report file, main creates it empty
+fn save_report(filename: &str, diags: &[Diagnostic], format: Format) {
+    let res = fs::OpenOptions::new().append(true).create(true).open(filename)
+        .and_then(|mut f| write_diagnostics(&mut f, diags, format).and_then(|_| f.flush()));
+    if let Err(e) = res {
+        println!("{}: {}", filename, e);
+        exit(1);
+    }
+}
+
+// prints diagnostics surrounded by `n` lines of context, like `grep -C`:
+// overlapping and adjacent contexts are merged into one group
+fn print_with_context(diags: &[Diagnostic], lines: &[&str], n: usize) {
+    let mut last_printed: Option<usize> = None;
+    let mut di = 0;
+    while di < diags.len() {
+        let start = diags[di].line.saturating_sub(n);
+        let start = match last_printed {
+            Some(l) if l >= start => l + 1,
+            _ => start,
+        };
+        if last_printed.is_some() && last_printed != Some(start - 1) {
+            println!("--");
+        }
+        let mut end = (diags[di].line + n).min(lines.len().saturating_sub(1));
+        let mut idx = start;
+        while idx <= end {
+            if di < diags.len() && diags[di].line == idx {
+                while di < diags.len() && diags[di].line == idx {
+                    println!("{:4}.{}{}", idx, prefix(diags[di].severity), diags[di].message);
+                    di += 1;
+                }
+            } else {
+                println!("{:4}-{}", idx, lines[idx]);
+            }
+            if di < diags.len() && diags[di].line <= end + n + 1 {
+                end = (diags[di].line + n).min(lines.len().saturating_sub(1));
+            }
+            idx += 1;
+        }
+        last_printed = Some(end);
+    }
+}
+
+fn load_schema(filename: &str) -> schema::Schema {
+    let text = match fs::read_to_string(filename) {
+        Err(e) => {
+            println!("{}: {}", filename, e);
+            exit(1);
+        }
+        Ok(t) => t,
+    };
+    match schema::Schema::parse(&text) {
+        Err(e) => {
+            println!("{}: {}", filename, e);
+            exit(1);
+        }
+        Ok(s) => s,
+    }
+}
+
+struct CardSummary<'a> {
+    line: Option<usize>,
+    errors: usize,
+    warnings: usize,
+    // problem kinds in order of appearance and their counts
+    kinds: Vec<(&'a str, usize)>,
+}
+
+// prints one line per card with problems (the header is a card without headword)
+fn print_summary(diags: &[Diagnostic], lines: &[&str], heads: &[Option<usize>], opts: &Options) {
+    let mut cards: Vec<CardSummary> = Vec::new();
+    for d in diags.iter().filter(|d| d.severity != Severity::Info) {
+        let line = heads.get(d.line).copied().flatten();
+        if cards.last().map(|c| c.line) != Some(line) {
+            cards.push(CardSummary { line, errors: 0, warnings: 0, kinds: Vec::new() });
+        }
+        let card = cards.last_mut().unwrap();
+        if d.severity == Severity::Error {
+            card.errors += 1;
+        } else {
+            card.warnings += 1;
+        }
+        match card.kinds.iter_mut().find(|(k, _)| *k == d.kind) {
+            Some((_, n)) => *n += 1,
+            None => card.kinds.push((d.kind, 1)),
+        }
+    }
+    if opts.sort_by_severity {
+        cards.sort_by_key(|c| std::cmp::Reverse((c.errors, c.errors + c.warnings)));
+    }
+    let headword = |c: &CardSummary| match c.line {
+        None => String::from("<header>"),
+        Some(l) => lines[l].to_string(),
+    };
+    match opts.format {
+        Format::Text => {
+            for c in cards.iter() {
+                let kinds: Vec<String> = c.kinds.iter().map(|(k, n)| format!("{}: {}", k, n)).collect();
+                println!("{:4}.{}: {} errors, {} warnings ({})", c.line.unwrap_or(0), headword(c), c.errors, c.warnings, kinds.join(", "));
+            }
+        }
+        Format::Json => {
+            println!("[");
+            for (i, c) in cards.iter().enumerate() {
+                let sep = if i + 1 == cards.len() { "" } else { "," };
+                let kinds: Vec<String> = c.kinds.iter().map(|(k, n)| format!("\"{}\": {}", k, n)).collect();
+                println!("  {{\"line\": {}, \"headword\": \"{}\", \"errors\": {}, \"warnings\": {}, \"kinds\": {{{}}}}}{}",
+                    c.line.unwrap_or(0), json_escape(&headword(c)), c.errors, c.warnings, kinds.join(", "), sep);
+            }
+            println!("]");
+        }
+    }
+}
+
+// the headword line of the card every line belongs to
+fn card_heads(lines: &[&str]) -> Vec<Option<usize>> {
+    let mut heads: Vec<Option<usize>> = Vec::with_capacity(lines.len());
+    for (idx, l) in lines.iter().enumerate() {
+        let head = if line_type(l) == DState::Key { Some(idx) } else { heads.last().copied().flatten() };
+        heads.push(head);
+    }
+    heads
+}
+
+// whether every line belongs to a card with the headword `headword`: the
+// comparison is exact after case folding, any variant of a card matches
+fn card_lines(lines: &[&str], headword: &str) -> Vec<bool> {
+    let wanted = fold_case(headword.trim());
+    let mut res = vec![false; lines.len()];
+    let mut matched = false;
+    let mut idx = 0;
+    while idx < lines.len() {
+        if line_type(lines[idx]) == DState::Key {
+            // consecutive headwords are variants of one card
+            let start = idx;
+            while idx < lines.len() && line_type(lines[idx]) == DState::Key {
+                idx += 1;
+            }
+            matched = lines[start..idx].iter().any(|l| fold_case(l.trim()) == wanted);
+            res[start..idx].fill(matched);
+            continue;
+        }
+        res[idx] = matched;
+        idx += 1;
+    }
+    res
+}
+
+fn make_checker(opts: &Options) -> Checker {
+    let mut checker = Checker::new()
+        .allow_monolingual(opts.allow_monolingual)
+        .duplicates(!opts.no_duplicate_check)
+        .hash_headwords(opts.hash_headwords)
+        .verbose(opts.verbose)
+        .lenient(opts.lenient)
+        .near_dup(opts.near_dup)
+        .dup_ignore_trailing_punct(opts.dup_ignore_trailing_punct)
+        .require_translation(opts.require_translation)
+        .check_script(opts.check_script)
+        .check_direction(opts.check_direction)
+        .expand_optional(opts.expand_optional)
+        .warn_bare_urls(opts.warn_bare_urls)
+        .check_redundant_margins(opts.check_redundant_margins)
+        .flag_untranslated(opts.flag_untranslated)
+        .flag_empty_formatting(opts.flag_empty_formatting)
+        .max_line_length(opts.max_line_length)
+        .min_translation_length(opts.min_translation_length)
+        .max_lang_depth(opts.max_lang_depth)
+        .check_indent(opts.check_indent)
+        .resolve_lang_ids(opts.resolve_lang_ids)
+        .check_examples(opts.check_examples)
+        .abbrev_consistency(opts.abbrev_consistency)
+        .schema(opts.schema.as_ref().map(|f| load_schema(f)));
+    for t in opts.allow_tags.iter() {
+        checker = checker.allow_tag(t);
+    }
+    for t in opts.multiline_tags.iter() {
+        checker = checker.multiline_tag(t);
+    }
+    for t in opts.forbid_tags.iter() {
+        checker = checker.forbid_tag(t);
+    }
+    for (kind, sev) in opts.severities.iter() {
+        checker = checker.severity(kind, *sev);
+    }
+    checker
+}
+
+// adds headwords of files included with #INCLUDE to the checker. Paths are
+// relative to the dictionary directory; includes of included files are not
+// followed
+fn follow_includes(filename: &str, content: &str, start_offset: usize, mut checker: Checker, diags: &mut Vec<Diagnostic>,
+    enc: &'static encoding_rs::Encoding) -> Checker {
+    let dir = Path::new(filename).parent().map(|p| p.to_path_buf()).unwrap_or_default();
+    let mut offset = start_offset;
+    for (idx, raw) in split_lines(content).enumerate() {
+        let line_offset = offset;
+        offset += raw.encode_utf16().count() * 2;
+        let l = raw.trim_end_matches(['\r', '\n']);
+        if line_type(l) != DState::Include {
+            continue;
+        }
+        let name = header_value(l);
+        let path = dir.join(name);
+        if !path.is_file() {
+            diags.push(Diagnostic { line: idx, byte_offset: line_offset, severity: Severity::Error, kind: "missing-include",
+                message: format!("included file '{}' not found ==> {}", name, l), data: Vec::new() });
+            continue;
+        }
+        let included = load_utf16_file(&path.to_string_lossy(), enc);
+        let headwords: Vec<String> = card::cards(&included).filter_map(Result::ok).flat_map(|c| c.headwords).collect();
+        checker = checker.include(name, &headwords);
+    }
+    checker
+}
+
+// line types for --trace go to stderr, so they do not mix with the report
+fn print_trace(ev: &TraceEvent) {
+    match ev.prev {
+        None => eprintln!("{:4}.{:?}, skipped", ev.line, ev.state),
+        Some(prev) => eprintln!("{:4}.{:?} after {:?}, {}", ev.line, ev.state, prev, if ev.accepted { "accepted" } else { "rejected" }),
+    }
+}
+
+// returns true if any problem was reported
+fn check_grammar(filename: &str, opts: &Options) -> bool {
+    let mut checker = make_checker(opts);
+    let bytes = read_dict_file(filename);
+    let (cont, enc, had_errors) = opts.input_encoding.decode(&bytes);
+    let mut missing = Vec::new();
+    if opts.follow_includes {
+        checker = follow_includes(filename, &cont, bom_len(&bytes), checker, &mut missing, opts.input_encoding);
+    }
+    let checker = checker.start_offset(bom_len(&bytes));
+    let mut diags = if opts.input_encoding == encoding_rs::UTF_16LE && bom_len(&bytes) == 0 && byte_swapped(&cont) {
+        // all other problems would be noise
+        vec![Diagnostic { line: 0, byte_offset: 0, severity: Severity::Error, kind: "wrong-byte-order",
+            message: String::from("the file looks like UTF-16BE without a BOM, pass --input-encoding utf16be"), data: Vec::new() }]
+    } else {
+        let mut trace = |ev: &TraceEvent| if opts.trace {
+            print_trace(ev);
+        };
+        let mut diags = checker.check_traced(&cont, &mut trace);
+        if had_errors {
+            diags.append(&mut decode_errors(&bytes, enc == encoding_rs::UTF_16BE, &cont));
+            diags.sort_by_key(|d| d.line);
+        }
+        diags
+    };
+    if !missing.is_empty() {
+        diags.append(&mut missing);
+        diags.sort_by_key(|d| d.line);
+    }
+    checker.adjust_severity(&mut diags);
+    if opts.errors_only {
+        diags.retain(|d| d.severity == Severity::Error);
+    }
+    if !opts.only.is_empty() {
+        diags.retain(|d| opts.only.iter().any(|k| k == d.kind));
+    }
+    diags.retain(|d| !opts.ignore.iter().any(|k| k == d.kind));
+    let lines: Vec<&str> = text_lines(&cont).collect();
+    if let Some(h) = &opts.only_card {
+        // the whole file is checked, so duplicates of the card are found too
+        let matched = card_lines(&lines, h);
+        if !matched.contains(&true) {
+            println!("card '{}' not found", h);
+            return true;
+        }
+        diags.retain(|d| matched.get(d.line) == Some(&true));
+    }
+    // the limit applies to the problems that are shown
+    if let Some(max) = opts.max_errors {
+        limit_errors(&mut diags, max);
+    }
+    if let Some(f) = &opts.report_file {
+        save_report(f, &diags, opts.format);
+    }
+    if opts.sort_by == SortBy::Kind {
+        diags.sort_by(|a, b| a.kind.cmp(b.kind).then(a.line.cmp(&b.line)));
+    }
+    if opts.summary_only {
+        print_summary(&diags, &lines, &card_heads(&lines), opts);
+    } else if opts.context != 0 && opts.format == Format::Text {
+        // context is printed for every kind separately to keep lines in order
+        for (i, group) in diags.chunk_by(|a, b| a.kind == b.kind || opts.sort_by == SortBy::Line).enumerate() {
+            if i != 0 {
+                println!("--");
+            }
+            print_with_context(group, &lines, opts.context);
+        }
+    } else {
+        print_diagnostics(&diags, opts.format);
+    }
+    match opts.fail_on {
+        FailOn::Error => diags.iter().any(|d| d.severity == Severity::Error),
+        FailOn::Warning => diags.iter().any(|d| d.severity != Severity::Info),
+        FailOn::Never => false,
+    }
+}
+
+// the bytes of a dictionary as save_file writes them: UTF-16LE with a BOM
+// and CRLF line ends
+fn encode_lines(lines: &[String]) -> Vec<u8> {
+    let mut bytes: Vec<u8> = vec![0xff, 0xfe];
+    for s in lines.iter() {
+        for c in s.encode_utf16().chain("\r\n".encode_utf16()) {
+            bytes.extend_from_slice(&c.to_le_bytes());
+        }
+    }
+    bytes
+}
+
+// output is saved as UTF-16LE with a BOM and CRLF line ends, compressed or
+// not, to be readable by Lingvo and by load_utf16_file
+fn save_file(outfile: &str, lines: &[String], compress: bool) -> io::Result<()> {
+    let mut bytes = encode_lines(lines);
+    if compress || outfile.ends_with(".dz") {
+        bytes = gzip::compress(&bytes);
+    }
+    fs::write(Path::new(outfile), bytes)
+}
+
+fn fix_invalid_tags(infile: &str, outfile: &str, opts: &Options) {
+    let cont = load_utf16_file(infile, opts.input_encoding);
+    let mut rvec: Vec<String> = Vec::new();
+    // bare CR line ends are replaced as well
+    for l in text_lines(&cont).map(|l| l.to_string()) {
+        let l = if opts.strip_bom { l.replace('\u{feff}', "") } else { l };
+        let l = if opts.replace_tag_tabs && line_type(&l) != DState::Key { replace_tag_tabs(&l) } else { l };
+        let l = if opts.collapse_margins && l.starts_with('\t') { collapse_margins(&l) } else { l };
+        let l = if opts.remove_empty_formatting && l.starts_with('\t') { remove_empty_formatting(&l) } else { l };
+        if opts.escape_headwords && line_type(&l) == DState::Key {
+            rvec.push(escape_headword(&l));
+            continue;
+        }
+        if !l.contains('[') {
+            rvec.push(l.to_string());
+            continue;
+        }
+        let (prs, replaced) = fix_up_line_replacing(&l);
+        for (tag, tg) in replaced.iter() {
+            eprintln!("replacing '{}' with '{}'", tag, tg);
+        }
+        rvec.push(prs);
+    }
+
+    if let Err(e) = save_file(outfile, &rvec, opts.compress) {
+        println!("{}: {}", outfile, e);
+        exit(1);
+    }
+}
+
+// the lines of a decoded dictionary as normalize saves them
+fn normalize_lines(cont: &str) -> Vec<String> {
+    text_lines(cont).map(|l| l.to_string()).collect()
+}
+
+// re-saves a dictionary as UTF-16LE with CRLF line ends, see save_file
+fn normalize_file(infile: &str, outfile: &str, opts: &Options) {
+    let cont = load_utf16_file(infile, opts.input_encoding);
+    let rvec = normalize_lines(&cont);
+    if let Err(e) = save_file(outfile, &rvec, opts.compress) {
+        println!("{}: {}", outfile, e);
+        exit(1);
+    }
+}
+
+fn fix_indents(infile: &str, outfile: &str, opts: &Options) {
+    let cont = load_utf16_file(infile, opts.input_encoding);
+    let tabs = match detect_tabs_per_level(&cont, INDENT_SAMPLE_CARDS) {
+        Some(n) => {
+            println!("detected {} TAB(s) per margin level", n);
+            n
+        }
+        None => {
+            println!("no consistent TABs per margin level found, using 1");
+            1
+        }
+    };
+    let rvec: Vec<String> = text_lines(&cont).map(|l| fix_indent(l, tabs)).collect();
+    if let Err(e) = save_file(outfile, &rvec, opts.compress) {
+        println!("{}: {}", outfile, e);
+        exit(1);
+    }
+}
+
+// safe repairs of fix-all in the order they are applied
+const FIXES: &[&str] = &["byte order marks", "control characters", "trailing whitespace", "unknown tags", "indentation"];
+
+fn apply_fix(fix: usize, s: &str, tabs: usize) -> String {
+    match fix {
+        0 => s.replace('\u{feff}', ""),
+        1 => s.chars().filter(|c| *c == '\t' || !c.is_control()).collect(),
+        // a line of TABs only is an empty paragraph, not an empty line
+        2 if s.starts_with('\t') && s.trim().is_empty() => s.chars().filter(|&c| c == '\t').collect(),
+        2 => s.trim_end().to_string(),
+        3 if s.contains('[') => fix_up_line(s),
+        4 => fix_indent(s, tabs),
+        _ => s.to_string(),
+    }
+}
+
+// applies all FIXES to every line of a text, returns the lines and the number
+// of lines every fix changed
+fn apply_fixes(cont: &str) -> (Vec<String>, Vec<usize>) {
+    let tabs = detect_tabs_per_level(cont, INDENT_SAMPLE_CARDS).unwrap_or(1);
+    let mut counts = vec![0; FIXES.len()];
+    let mut rvec: Vec<String> = Vec::new();
+    for l in text_lines(cont) {
+        let mut line = l.to_string();
+        for (fix, count) in counts.iter_mut().enumerate() {
+            let fixed = apply_fix(fix, &line, tabs);
+            if fixed != line {
+                *count += 1;
+                line = fixed;
+            }
+        }
+        rvec.push(line);
+    }
+    (rvec, counts)
+}
+
+// the lines of a saved dictionary, the way load_utf16_file reads them
+fn saved_lines(bytes: &[u8]) -> Vec<String> {
+    let (text, _, _) = encoding_rs::UTF_16LE.decode(bytes);
+    normalize_lines(text.strip_prefix('\u{feff}').unwrap_or(&text))
+}
+
+// normalizes a text twice, the second time the saved output of the first
+// run, and returns the bytes of both outputs
+fn normalize_twice(cont: &str) -> (Vec<u8>, Vec<u8>) {
+    let first = encode_lines(&normalize_lines(cont));
+    let second = encode_lines(&saved_lines(&first));
+    (first, second)
+}
+
+// the first line two texts differ in, a line missing in one of them counts
+fn first_difference(a: &[String], b: &[String]) -> Option<usize> {
+    (0..a.len().max(b.len())).find(|&i| a.get(i) != b.get(i))
+}
+
+// normalize must be idempotent: normalizing its own output gives the same
+// bytes. Prints the first line that changes, returns true if there is one
+fn verify_normalize(filename: &str, opts: &Options) -> bool {
+    let cont = load_utf16_file(filename, opts.input_encoding);
+    let (first, second) = normalize_twice(&cont);
+    if first == second {
+        println!("normalizing is stable: its output is not changed by another run");
+        return false;
+    }
+    let (a, b) = (saved_lines(&first), saved_lines(&second));
+    match first_difference(&a, &b) {
+        None => println!("the second run of normalizing changes the bytes, but not the lines"),
+        Some(idx) => {
+            let (a, b) = (a.get(idx).map_or("<no line>", |s| s), b.get(idx).map_or("<no line>", |s| s));
+            println!("{:4}.the second run of normalizing changes the line:\n    {}\n    {}", idx, a, b);
+        }
+    }
+    true
+}
+
+// applies all repairs that cannot break a valid dictionary. Nothing is written
+// if the result still has tag errors; returns true in this case
+fn fix_all(infile: &str, outfile: &str, opts: &Options) -> bool {
+    let cont = load_utf16_file(infile, opts.input_encoding);
+    let (rvec, counts) = apply_fixes(&cont);
+    let mut failed = false;
+    for (idx, line) in rvec.iter().enumerate() {
+        if line.starts_with(['\t', ' ']) {
+            if let Err(e) = check_line(line) {
+                println!("{:4}.{} ==> {}", idx, e, line);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        println!("{} is not written: the errors above cannot be fixed automatically", outfile);
+        return true;
+    }
+    for (name, count) in FIXES.iter().zip(counts.iter()) {
+        println!("{}: {} line(s) fixed", name, count);
+    }
+    if let Err(e) = save_file(outfile, &rvec, opts.compress) {
+        println!("{}: {}", outfile, e);
+        exit(1);
+    }
+    false
+}
+
+// renames tags given by --map. Nothing is written if a new name is not a
+// known or allowed tag, or if renaming adds errors; returns true in this case
+fn retag(infile: &str, outfile: &str, opts: &Options) -> bool {
+    for (_, new) in opts.tag_maps.iter() {
+        if !is_known_tag(new) && !opts.allow_tags.iter().any(|t| t.trim_start_matches('[').trim_end_matches(']') == new) {
+            println!("[{}] is not a known tag, pass --allow-tag {} to use it", new, new);
+            return true;
+        }
+    }
+    let cont = load_utf16_file(infile, opts.input_encoding);
+    let mut counts = vec![0; opts.tag_maps.len()];
+    let mut rvec: Vec<String> = Vec::new();
+    for l in text_lines(&cont) {
+        let mut line = l.to_string();
+        if line_type(l) != DState::Key {
+            for ((old, new), count) in opts.tag_maps.iter().zip(counts.iter_mut()) {
+                let (renamed, n) = rename_tag(&line, old, new);
+                line = renamed;
+                *count += n;
+            }
+        }
+        rvec.push(line);
+    }
+    let checker = opts.allow_tags.iter().fold(Checker::new(), |c, t| c.allow_tag(t));
+    let errors = |text: &str| -> Vec<(usize, &'static str)> {
+        checker.check(text).iter().filter(|d| d.severity == Severity::Error).map(|d| (d.line, d.kind)).collect()
+    };
+    let before = errors(&cont);
+    let after = rvec.join("\n");
+    let added: Vec<(usize, &'static str)> = errors(&after).into_iter().filter(|e| !before.contains(e)).collect();
+    for (idx, kind) in added.iter() {
+        println!("{:4}.{} after renaming ==> {}", idx, kind, rvec[*idx]);
+    }
+    if !added.is_empty() {
+        println!("{} is not written: renaming breaks the lines above", outfile);
+        return true;
+    }
+    for ((old, new), count) in opts.tag_maps.iter().zip(counts.iter()) {
+        println!("[{}] -> [{}]: {} tag(s) renamed", old, new, count);
+    }
+    if let Err(e) = save_file(outfile, &rvec, opts.compress) {
+        println!("{}: {}", outfile, e);
+        exit(1);
+    }
+    false
+}
+
+// video formats Lingvo can play
+const VIDEO_EXTENSIONS: &[&str] = &["avi", "mp4", "mpg", "mpeg", "wmv", "mkv", "webm", "mov"];
+
+// reports media files referenced by the dictionary that do not exist in
+// `media_dir` (the dictionary directory by default)
+fn check_media(filename: &str, media_dir: Option<&str>, opts: &Options) -> bool {
+    let bytes = read_dict_file(filename);
+    let (cont, _enc, _used) = opts.input_encoding.decode(&bytes);
+    let dir = match media_dir {
+        Some(d) => Path::new(d).to_path_buf(),
+        None => Path::new(filename).parent().map(|p| p.to_path_buf()).unwrap_or_default(),
+    };
+    let mut offset = bom_len(&bytes);
+    let mut diags: Vec<Diagnostic> = Vec::new();
+    // first headwords of cards that use a media file, and the line and
+    // offset of the first use by a second card
+    let mut users = HashMap::new();
+    let mut headword = String::new();
+    let mut prev = DState::EmptyLine;
+    for (idx, raw) in split_lines(&cont).enumerate() {
+        let tp = line_type(raw.trim_end_matches(['\r', '\n']));
+        if tp == DState::Key && prev != DState::Key {
+            headword = raw.trim_end_matches(['\r', '\n']).to_string();
+        }
+        prev = tp;
+        for f in media_files(raw) {
+            if opts.report_shared {
+                let (cards, second) = users.entry(f.clone()).or_insert((Vec::new(), None));
+                if !cards.contains(&headword) {
+                    cards.push(headword.clone());
+                    if cards.len() == 2 {
+                        *second = Some((idx, offset));
+                    }
+                }
+            }
+            if !dir.join(&f).is_file() {
+                diags.push(Diagnostic { line: idx, byte_offset: offset, severity: Severity::Error, kind: "missing-media",
+                    message: format!("media file '{}' not found", f), data: Vec::new() });
+            }
+        }
+        if opts.strict_media {
+            for f in tag_contents(raw, "video") {
+                let ext = Path::new(&f).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+                if !VIDEO_EXTENSIONS.contains(&ext.as_str()) {
+                    diags.push(Diagnostic { line: idx, byte_offset: offset, severity: Severity::Warning, kind: "unsupported-video",
+                        message: format!("video file '{}' has unsupported format", f), data: Vec::new() });
+                }
+            }
+        }
+        offset += raw.encode_utf16().count() * 2;
+    }
+    let mut shared: Vec<_> = users.into_iter().filter_map(|(f, (cards, second))| second.map(|s| (s, f, cards))).collect();
+    shared.sort();
+    for ((line, byte_offset), f, cards) in shared {
+        diags.push(Diagnostic { line, byte_offset, severity: Severity::Warning, kind: "shared-media",
+            message: format!("media file '{}' is used by {} cards: {}", f, cards.len(), cards.join(", ")), data: Vec::new() });
+    }
+    diags.sort_by_key(|d| d.line);
+    make_checker(opts).adjust_severity(&mut diags);
+    print_diagnostics(&diags, opts.format);
+    diags.iter().any(|d| d.severity == Severity::Error)
+}
+
+// matches a path against a pattern with wildcards: `?` is any character,
+// `*` is any part of a file name, and `**` is any part of a path
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let n: Vec<char> = name.chars().collect();
+    glob_match_chars(&p, &n)
+}
+
+fn glob_match_chars(p: &[char], n: &[char]) -> bool {
+    match p.first() {
+        None => n.is_empty(),
+        // `**/` matches no directories as well
+        Some('*') if p.get(1) == Some(&'*') => (p.get(2) == Some(&'/') && glob_match_chars(&p[3..], n))
+            || (0..=n.len()).any(|i| glob_match_chars(&p[2..], &n[i..])),
+        Some('*') => {
+            let max = n.iter().position(|&c| c == '/').unwrap_or(n.len());
+            (0..=max).any(|i| glob_match_chars(&p[1..], &n[i..]))
+        }
+        Some('?') => !n.is_empty() && n[0] != '/' && glob_match_chars(&p[1..], &n[1..]),
+        Some(c) => n.first() == Some(c) && glob_match_chars(&p[1..], &n[1..]),
+    }
+}
+
+// a pattern is matched against both the file name and its path relative to
+// the directory being checked
+fn excluded(path: &Path, root: &Path, patterns: &[String]) -> bool {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let rel = path.strip_prefix(root).unwrap_or(path).to_string_lossy().to_string();
+    patterns.iter().any(|p| glob_match(p, &name) || glob_match(p, &rel))
+}
+
+// collects all dictionaries in the directory and its subdirectories
+fn dict_files(dir: &Path, root: &Path, exclude: &[String], res: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Err(e) => {
+            println!("{}: {}", dir.display(), e);
+            exit(1);
+        }
+        Ok(v) => v,
+    };
+    let mut paths: Vec<PathBuf> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+    paths.sort();
+    for path in paths {
+        if excluded(&path, root, exclude) {
+            continue;
+        }
+        if path.is_dir() {
+            dict_files(&path, root, exclude, res);
+            continue;
+        }
+        let name = path.to_string_lossy().to_lowercase();
+        if name.ends_with(".dsl") || name.ends_with(".dsl.dz") {
+            res.push(path);
+        }
+    }
+}
+
+// collects files matching the pattern. The directories above the first
+// wildcard are not scanned
+fn glob_files(pattern: &str, exclude: &[String]) -> Vec<PathBuf> {
+    let comps: Vec<&str> = pattern.split('/').collect();
+    let fixed = comps.iter().take_while(|c| !c.contains(['*', '?'])).count();
+    if fixed == comps.len() {
+        return if Path::new(pattern).is_file() { vec![PathBuf::from(pattern)] } else { Vec::new() };
+    }
+    let prefix: String = comps[..fixed].iter().map(|c| format!("{}/", c)).collect();
+    let root = if prefix.is_empty() { PathBuf::from(".") } else { PathBuf::from(&prefix) };
+    let depth = if pattern.contains("**") { usize::MAX } else { comps.len() - fixed };
+    let mut res = Vec::new();
+    glob_walk(&root, &prefix, depth, pattern, exclude, &mut res);
+    res
+}
+
+fn glob_walk(dir: &Path, prefix: &str, depth: usize, pattern: &str, exclude: &[String], res: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Err(_) => return,
+        Ok(v) => v,
+    };
+    let mut names: Vec<String> = entries.filter_map(|e| e.ok()).map(|e| e.file_name().to_string_lossy().to_string()).collect();
+    names.sort();
+    for name in names {
+        let rel = format!("{}{}", prefix, name);
+        let path = PathBuf::from(&rel);
+        if exclude.iter().any(|p| glob_match(p, &name) || glob_match(p, &rel)) {
+            continue;
+        }
+        if path.is_dir() {
+            if depth > 1 {
+                glob_walk(&path, &format!("{}/", rel), depth - 1, pattern, exclude, res);
+            }
+        } else if glob_match(pattern, &rel) {
+            res.push(path);
+        }
+    }
+}
+
+// checks every file from the list, returns true if any of them has problems
+fn check_files(files: &[PathBuf], opts: &Options) -> bool {
+    let mut failed = 0;
+    for f in files.iter() {
+        let name = f.to_string_lossy();
+        if opts.format == Format::Text {
+            println!("\n=== {}", name);
+        }
+        if check_grammar(&name, opts) {
+            failed += 1;
+        }
+    }
+    if opts.format == Format::Text {
+        println!("\n{} files checked, {} passed, {} failed", files.len(), files.len() - failed, failed);
+    }
+    failed != 0
+}
+
+// checks every dictionary in the directory tree
+fn check_dir(dirname: &str, opts: &Options) -> bool {
+    let root = Path::new(dirname);
+    let mut files = Vec::new();
+    dict_files(root, root, &opts.exclude, &mut files);
+    check_files(&files, opts)
+}
+
+// checks every file matching a pattern like `dicts/*.dsl`
+fn check_glob(pattern: &str, opts: &Options) -> bool {
+    let files = glob_files(pattern, &opts.exclude);
+    if files.is_empty() {
+        println!("no files match '{}'", pattern);
+        exit(1);
+    }
+    check_files(&files, opts)
+}
+
+// sorts cards by the index keys of their first headwords. The header stays
+// on top. Editor comments belong to the card above them; with
+// `preserve_comments` the comments right before a headword move with its card
+fn sort_file(infile: &str, outfile: &str, opts: &Options) {
+    let cont = load_utf16_file(infile, opts.input_encoding);
+    let mut header: Vec<String> = Vec::new();
+    let mut cards: Vec<Vec<String>> = Vec::new();
+    let mut pending: Vec<String> = Vec::new();
+    let mut prev = DState::EmptyLine;
+    // cards are separated with empty lines if the file does so
+    let mut gap = false;
+    for l in text_lines(&cont) {
+        let tp = line_type(l);
+        if tp == DState::EditorComment && opts.preserve_comments {
+            pending.push(l.to_string());
+            continue;
+        }
+        let rest = match cards.last_mut() {
+            _ if tp == DState::Key && prev != DState::Key => {
+                gap |= !cards.is_empty() && prev == DState::EmptyLine;
+                cards.push(Vec::new());
+                cards.last_mut().unwrap()
+            }
+            None => &mut header,
+            Some(c) => c,
+        };
+        rest.append(&mut pending);
+        rest.push(l.to_string());
+        if tp != DState::EditorComment {
+            prev = tp;
+        }
+    }
+    let last = cards.last_mut().unwrap_or(&mut header);
+    last.append(&mut pending);
+    for c in cards.iter_mut() {
+        while c.last().is_some_and(|l| l.trim().is_empty()) {
+            c.pop();
+        }
+    }
+    let key = |c: &Vec<String>| c.iter().find(|l| line_type(l) == DState::Key).map(|h| index_key(h)).unwrap_or_default();
+    cards.sort_by_cached_key(key);
+    let mut rvec = header;
+    for (i, c) in cards.into_iter().enumerate() {
+        if gap && i != 0 {
+            rvec.push(String::new());
+        }
+        rvec.extend(c);
+    }
+    if let Err(e) = save_file(outfile, &rvec, opts.compress) {
+        println!("{}: {}", outfile, e);
+        exit(1);
+    }
+}
+
+// shows how many cards have tag errors and which error is the most common
+fn print_stats(filename: &str, opts: &Options) {
+    let cont = load_utf16_file(filename, opts.input_encoding);
+    let mut cards = 0;
+    let mut broken = 0;
+    let mut kinds: HashMap<&'static str, usize> = HashMap::new();
+    let mut prev = DState::EmptyLine;
+    let mut card_ok = true;
+    for l in text_lines(&cont) {
+        let tp = line_type(l);
+        if tp == DState::Key && prev != DState::Key {
+            cards += 1;
+            if !card_ok {
+                broken += 1;
+            }
+            card_ok = true;
+        }
+        if tp != DState::EditorComment {
+            prev = tp;
+        }
+        if cards == 0 || !l.starts_with('\t') {
+            continue;
+        }
+        if let Err(e) = check_line(l) {
+            card_ok = false;
+            *kinds.entry(e.kind()).or_insert(0) += 1;
+        }
+    }
+    if !card_ok {
+        broken += 1;
+    }
+    let common = kinds.into_iter().max_by(|(k1, n1), (k2, n2)| n1.cmp(n2).then(k2.cmp(k1)));
+    match opts.format {
+        Format::Text => {
+            println!("cards: {}", cards);
+            println!("cards with balanced tags: {}", cards - broken);
+            println!("cards with tag errors: {}", broken);
+            if let Some((kind, n)) = common {
+                println!("most common tag error: {} ({} line(s))", kind, n);
+            }
+        }
+        Format::Json => {
+            let (kind, n) = match common {
+                Some((k, n)) => (format!("\"{}\"", k), n),
+                None => (String::from("null"), 0),
+            };
+            println!("{{\"cards\": {}, \"balanced\": {}, \"unbalanced\": {}, \"most_common_error\": {}, \"most_common_count\": {}}}",
+                cards, cards - broken, broken, kind, n);
+        }
+    }
+}
+
+// makes a dictionary of the cards that have [lang] blocks of the language
+// `id`, with the blocks of other languages removed. Card body lines left
+// without text are removed as well
+fn extract_lang(infile: &str, outfile: &str, id: u16, opts: &Options) {
+    let cont = load_utf16_file(infile, opts.input_encoding);
+    let mut rvec: Vec<String> = Vec::new();
+    let mut card: Vec<String> = Vec::new();
+    let mut found = false;
+    let mut prev = DState::EmptyLine;
+    let mut kept = 0;
+    for l in text_lines(&cont) {
+        let tp = line_type(l);
+        if tp == DState::Key && prev != DState::Key {
+            if found {
+                kept += 1;
+                rvec.append(&mut card);
+            }
+            card.clear();
+            found = false;
+        }
+        if card.is_empty() && tp != DState::Key {
+            // header and lines before the first card
+            rvec.push(l.to_string());
+            continue;
+        }
+        prev = tp;
+        if tp == DState::Key || !l.contains("[lang ") {
+            card.push(l.to_string());
+            continue;
+        }
+        let (line, has) = keep_lang(l, id);
+        found |= has;
+        if line == l || !visible_text(&line, &[]).trim().is_empty() {
+            card.push(line);
+        }
+    }
+    if found {
+        kept += 1;
+        rvec.append(&mut card);
+    }
+    println!("{} card(s) with language {} extracted", kept, id);
+    if let Err(e) = save_file(outfile, &rvec, opts.compress) {
+        println!("{}: {}", outfile, e);
+        exit(1);
+    }
+}
+
+// file name of the page `n` (1-based) of a paginated export to `outfile`:
+// dict.html -> dict-1.html
+fn page_file(outfile: &str, n: usize) -> String {
+    let path = Path::new(outfile);
+    let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    match path.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, n, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, n),
+    }
+}
+
+// the first letter of a headword in upper case, for page ranges of the index
+fn first_letter(headword: &str) -> String {
+    index_forms(headword).0.chars().next().map(|c| c.to_uppercase().collect()).unwrap_or_default()
+}
+
+// exports cards to HTML: to one file, or with --entries-per-page to numbered
+// pages next to `outfile` that becomes the index of the pages. Cards that
+// fail to parse are skipped
+fn export_html(infile: &str, outfile: &str, opts: &Options) {
+    let cont = load_utf16_file(infile, opts.input_encoding);
+    let title = text_lines(&cont).find(|l| l.trim_start_matches('\u{feff}').starts_with("#NAME"))
+        .map(|l| header_value(l).to_string()).unwrap_or_else(|| infile.to_string());
+    let all: Vec<card::Card> = card::cards(&cont).filter_map(Result::ok).filter(|c| !c.headwords.is_empty()).collect();
+    let per_page = opts.entries_per_page.unwrap_or_else(|| all.len().max(1));
+    let pages: Vec<&[card::Card]> = all.chunks(per_page).collect();
+    let file = |n: usize| if opts.entries_per_page.is_some() { page_file(outfile, n + 1) } else { String::new() };
+    // anchors of cards and the pages of the headwords that <<ref>> may
+    // point to, the first card of a headword wins
+    let mut anchors = card::Anchors::new();
+    let mut ids: Vec<Vec<String>> = Vec::new();
+    let mut targets: HashMap<String, (usize, String)> = HashMap::new();
+    for (n, cards) in pages.iter().enumerate() {
+        let mut page_ids = Vec::new();
+        for c in cards.iter() {
+            let id = anchors.anchor(&c.headwords[0]);
+            for h in c.headwords.iter() {
+                targets.entry(index_key(h)).or_insert((n, id.clone()));
+            }
+            page_ids.push(id);
+        }
+        ids.push(page_ids);
+    }
+    let index = Path::new(outfile).file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let dir = Path::new(outfile).parent().map(|p| p.to_path_buf()).unwrap_or_default();
+    let mut ranges: Vec<String> = Vec::new();
+    for (n, cards) in pages.iter().enumerate() {
+        let link = |word: &str| targets.get(&index_key(word)).map(|(p, id)| if *p == n { format!("#{}", id) } else { format!("{}#{}", file(*p), id) });
+        let body: String = cards.iter().zip(ids[n].iter()).map(|(c, id)| html::render_card(c, id, &link)).collect();
+        let mut nav: Vec<String> = Vec::new();
+        if opts.entries_per_page.is_some() {
+            if n > 0 {
+                nav.push(format!("<a href=\"{}\">&larr; previous</a>", html::escape(&file(n - 1))));
+            }
+            nav.push(format!("<a href=\"{}\">index</a>", html::escape(&index)));
+            if n + 1 < pages.len() {
+                nav.push(format!("<a href=\"{}\">next &rarr;</a>", html::escape(&file(n + 1))));
+            }
+        }
+        let (first, last) = (&cards[0].headwords[0], &cards[cards.len() - 1].headwords[0]);
+        let (a, b) = (first_letter(first), first_letter(last));
+        let letters = if a == b { a } else { format!("{} \u{2013} {}", a, b) };
+        ranges.push(format!("<li><a href=\"{}\">{}</a> ({} \u{2013} {})</li>\n", html::escape(&file(n)), html::escape(&letters),
+            html::escape(&index_forms(first).0), html::escape(&index_forms(last).0)));
+        let path = if opts.entries_per_page.is_some() { dir.join(file(n)) } else { Path::new(outfile).to_path_buf() };
+        fs::write(&path, html::page(&title, &nav.join(" | "), &body)).unwrap();
+    }
+    if opts.entries_per_page.is_some() {
+        let body = format!("<h1>{}</h1>\n<ul>\n{}</ul>\n", html::escape(&title), ranges.concat());
+        fs::write(outfile, html::page(&title, "", &body)).unwrap();
+        println!("{} card(s) written to {} page(s)", all.len(), pages.len());
+    } else {
+        if pages.is_empty() {
+            fs::write(outfile, html::page(&title, "", "")).unwrap();
+        }
+        println!("{} card(s) written", all.len());
+    }
+}
+
+// counts how many cards use every pair of tags. Cards that fail to parse
+// are skipped
+fn tag_cooccurrence(filename: &str, opts: &Options) {
+    let cont = load_utf16_file(filename, opts.input_encoding);
+    let mut pairs: HashMap<(String, String), usize> = HashMap::new();
+    for c in card::cards(&cont).filter_map(Result::ok) {
+        let mut tags = schema::card_tags(&c);
+        tags.sort();
+        for (i, a) in tags.iter().enumerate() {
+            for b in tags[i + 1..].iter() {
+                *pairs.entry((a.clone(), b.clone())).or_insert(0) += 1;
             }
-            ']' => {
-                if last_c == '\\' {
-                    if in_sq {
-                        tag.push(c);
-                    }
-                    last_c = c;
-                    continue;
-                }
-                if !in_sq {
-                    return String::from("orphan closing bracket");
-                }
-                tag.push(c);
-                in_sq = false;
-                let tp = tag_type(&tag);
-                if tp == DState::Invalid {
-                    return format!("unknown tag '{}'", tag);
-                }
-                if !tag.starts_with("[/") {
-                    stack.push(tp);
-                } else {
-                    if stack.is_empty() {
-                        return format!("superfluos closing tag '{}'", tag);
-                    }
-                    let last = stack.pop().unwrap();
-                    let matched = match tp {
-                        DState::MClose if last == DState::M1 || last == DState::M2 => true,
-                        DState::IClose if last == DState::I => true,
-                        DState::ComClose if last == DState::Comment => true,
-                        DState::PClose if last == DState::P => true,
-                        DState::CClose if last == DState::C => true,
-                        DState::BClose if last == DState::B => true,
-                        DState::ExClose if last == DState::Ex => true,
-                        DState::LangIDClose if last == DState::LangID => true,
-                        _ => false,
-                    };
-                    if !matched {
-                        return format!("opening tag '{:?}' closing '{:?}'", last, tp);
-                    }
-                    tag = String::new();
-                }
-            },
-            _ => if in_sq {
-                tag.push(c);
-            },
         }
-        last_c = c;
     }
-    if !tag.is_empty() {
-        return format!("unfinished tag '{}'", tag);
+    let mut pairs: Vec<((String, String), usize)> = pairs.into_iter().collect();
+    pairs.sort_by(|(p1, n1), (p2, n2)| n2.cmp(n1).then(p1.cmp(p2)));
+    if let Some(n) = opts.top {
+        pairs.truncate(n);
+    }
+    match opts.format {
+        Format::Text => {
+            println!("tag1,tag2,cards");
+            for ((a, b), n) in pairs.iter() {
+                println!("{},{},{}", a, b, n);
+            }
+        }
+        Format::Json => {
+            println!("[");
+            for (i, ((a, b), n)) in pairs.iter().enumerate() {
+                let sep = if i + 1 == pairs.len() { "" } else { "," };
+                println!("  {{\"tag1\": \"{}\", \"tag2\": \"{}\", \"cards\": {}}}{}", a, b, n, sep);
+            }
+            println!("]");
+        }
     }
-    if !stack.is_empty() {
-        return format!("unclosed tags: {:?}", stack);
+}
+
+// prints examples of card bodies as TSV rows `headword<TAB>example`, the
+// first headword of a card is used. Cards that fail to parse are skipped
+fn list_examples(filename: &str, opts: &Options) {
+    let cont = load_utf16_file(filename, opts.input_encoding);
+    println!("headword\texample");
+    for c in card::cards(&cont).filter_map(Result::ok) {
+        let headword = match c.headwords.first() {
+            None => continue,
+            Some(h) => h.replace('\t', " "),
+        };
+        for l in c.body.iter() {
+            for ex in examples(l, opts.lang_id) {
+                println!("{}\t{}", headword, ex);
+            }
+        }
     }
-    String::new()
 }
 
-fn check_grammar(filename: &str) {
-    let cont = load_utf16_file(filename);
-    let cursor = Cursor::new(cont.as_bytes());
-    let mut prev = DState::Begin;
-    let mut words: HashMap<String, usize> = HashMap::new();
+// counts escaped and unescaped literal brackets of card bodies of every file
+// to decide whether running fix-tags is worth it
+fn escape_audit(files: &[String], opts: &Options) {
+    if opts.format == Format::Json {
+        println!("[");
+    }
+    for (i, f) in files.iter().enumerate() {
+        let cont = load_utf16_file(f, opts.input_encoding);
+        let (mut escaped, mut literal, mut first) = (0, 0, None);
+        for (idx, l) in text_lines(&cont).enumerate().filter(|(_, l)| l.starts_with('\t')) {
+            let (e, n) = bracket_counts(l);
+            escaped += e;
+            literal += n;
+            if n != 0 && first.is_none() {
+                first = Some(idx);
+            }
+        }
+        match opts.format {
+            Format::Text => match first {
+                None => println!("{}: {} escaped bracket(s), no unescaped literal brackets", f, escaped),
+                Some(idx) => println!("{}: {} escaped bracket(s), {} unescaped literal bracket(s), the first at line {}; fix-tags escapes them", f, escaped, literal, idx),
+            },
+            Format::Json => {
+                let sep = if i + 1 == files.len() { "" } else { "," };
+                let first = first.map_or("null".to_string(), |idx| idx.to_string());
+                println!("  {{\"file\": \"{}\", \"escaped\": {}, \"literal\": {}, \"first_literal_line\": {}}}{}", json_escape(f), escaped, literal, first, sep);
+            }
+        }
+    }
+    if opts.format == Format::Json {
+        println!("]");
+    }
+}
+
+// lists every tag the validator does not know with the number of uses and
+// the first line it is used at, most used tags first
+fn list_unknown_tags(filename: &str, opts: &Options) {
+    let cont = load_utf16_file(filename, opts.input_encoding);
+    let mut found: HashMap<&str, (usize, usize)> = HashMap::new();
+    for (idx, l) in text_lines(&cont).enumerate().filter(|(_, l)| l.starts_with('\t')) {
+        for name in unknown_tags(l) {
+            found.entry(name).or_insert((0, idx)).0 += 1;
+        }
+    }
+    let mut tags: Vec<(&str, (usize, usize))> = found.into_iter().collect();
+    tags.sort_by(|(t1, (n1, _)), (t2, (n2, _))| n2.cmp(n1).then(t1.cmp(t2)));
+    match opts.format {
+        Format::Text => {
+            println!("tag,count,first_line");
+            for (t, (n, line)) in tags.iter() {
+                println!("{},{},{}", t, n, line);
+            }
+        }
+        Format::Json => {
+            println!("[");
+            for (i, (t, (n, line))) in tags.iter().enumerate() {
+                let sep = if i + 1 == tags.len() { "" } else { "," };
+                println!("  {{\"tag\": \"{}\", \"count\": {}, \"first_line\": {}}}{}", json_escape(t), n, line, sep);
+            }
+            println!("]");
+        }
+    }
+}
 
-    for (idx, l) in cursor.lines().flatten().enumerate() {
-        let tp = line_type(&l);
-        if tp == DState::Invalid {
-            println!("{:4}.{}", idx, l);
+// counts words of the text of card bodies, most frequent words first
+fn body_freq(filename: &str, opts: &Options) {
+    let cont = load_utf16_file(filename, opts.input_encoding);
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for l in text_lines(&cont).filter(|l| l.starts_with('\t')) {
+        for w in text_words(&visible_text(l, &[])) {
+            let w = if opts.ignore_case { fold_case(w) } else { w.to_string() };
+            *counts.entry(w).or_insert(0) += 1;
+        }
+    }
+    let mut words: Vec<(String, usize)> = counts.into_iter().filter(|(_, n)| *n >= opts.min_count).collect();
+    words.sort_by(|(w1, n1), (w2, n2)| n2.cmp(n1).then(w1.cmp(w2)));
+    if let Some(n) = opts.top {
+        words.truncate(n);
+    }
+    match opts.format {
+        Format::Text => {
+            println!("word,count");
+            for (w, n) in words.iter() {
+                println!("{},{}", w, n);
+            }
         }
-        if tp == DState::Key {
-            let mut exist = false;
-            if let Some(v) = words.get(&l) {
-                exist = true;
-                println!("{} at {} already exists at {}", l, idx, *v);
+        Format::Json => {
+            println!("[");
+            for (i, (w, n)) in words.iter().enumerate() {
+                let sep = if i + 1 == words.len() { "" } else { "," };
+                println!("  {{\"word\": \"{}\", \"count\": {}}}{}", json_escape(w), n, sep);
             }
-            if !exist {
-                words.insert(l.clone(), idx);
+            println!("]");
+        }
+    }
+}
+
+// size of a piece of a file info reads at a time
+const HEADER_CHUNK: usize = 4096;
+
+// reads the start of a dictionary up to the empty line after the header.
+// A compressed file is unpacked completely; returns whether it is compressed
+fn read_header_bytes(filename: &str) -> (Vec<u8>, bool) {
+    let mut f = match fs::File::open(filename) {
+        Err(e) => {
+            println!("{}", e);
+            exit(1);
+        }
+        Ok(f) => f,
+    };
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut chunk = [0u8; HEADER_CHUNK];
+    loop {
+        let n = match f.read(&mut chunk) {
+            Err(e) => {
+                println!("{}: {}", filename, e);
+                exit(1);
             }
+            Ok(n) => n,
+        };
+        // the end of the previous piece is searched again, an empty line may
+        // start there
+        let start = bytes.len().saturating_sub(5);
+        bytes.extend_from_slice(&chunk[..n]);
+        if filename.ends_with(".dz") || gzip::is_gzip(&bytes) {
+            return (read_dict_file(filename), true);
+        }
+        // an empty line in UTF-16 with any line ending and byte order
+        let tail = &bytes[start..];
+        let empty = tail.windows(4).any(|w| w == b"\n\0\n\0" || w == b"\0\n\0\n")
+            || tail.windows(6).any(|w| w == b"\n\0\r\0\n\0" || w == b"\0\n\0\r\0\n");
+        if n == 0 || empty {
+            return (bytes, false);
+        }
+    }
+}
+
+// prints the metadata of the header and checks the order of header lines.
+// Only the header is read, returns true if its lines are in a wrong order
+fn print_info(filename: &str, opts: &Options) -> bool {
+    let (bytes, compressed) = read_header_bytes(filename);
+    let bytes = &bytes[..bytes.len() & !1];
+    let (mut cont, enc, _) = opts.input_encoding.decode(bytes);
+    let encoding = match encoding_rs::Encoding::for_bom(bytes) {
+        Some(_) => enc.name().to_string(),
+        None if opts.input_encoding == encoding_rs::UTF_16LE && byte_swapped(&cont) => {
+            cont = encoding_rs::UTF_16BE.decode(bytes).0;
+            String::from("UTF-16BE without a BOM")
+        }
+        None => format!("{} without a BOM", opts.input_encoding.name()),
+    };
+    let (mut name, mut index_lang, mut contents_lang) = ("", "", "");
+    let mut includes: Vec<&str> = Vec::new();
+    let mut errors: Vec<(usize, String)> = Vec::new();
+    let mut prev = DState::Begin;
+    for (idx, l) in text_lines(&cont).enumerate() {
+        let l = l.strip_prefix('\u{feff}').unwrap_or(l);
+        let tp = line_type(l);
+        if tp == DState::EditorComment {
+            continue;
         }
         if !can_follow(prev, tp) {
-            println!("{:4}.PREV {:?}, CURR: {:?}{}", idx, prev, tp, l);
+            errors.push((idx, format!("PREV {:?}, CURR: {:?}{}", prev, tp, l)));
         }
-        let prs = parse_line(&l);
-        if !prs.is_empty() {
-            println!("{:4}.{} ==> {}", idx, prs, l);
+        match tp {
+            DState::Name => name = header_value(l),
+            DState::Index => index_lang = header_value(l),
+            DState::Lang => contents_lang = header_value(l),
+            DState::Include => includes.push(header_value(l)),
+            // the header ends with an empty line or with the first line that
+            // cannot be a part of it
+            _ => break,
         }
         prev = tp;
     }
+    match opts.format {
+        Format::Text => {
+            println!("name: {}", name);
+            println!("index language: {}", index_lang);
+            println!("contents language: {}", contents_lang);
+            for inc in includes.iter() {
+                println!("include: {}", inc);
+            }
+            println!("encoding: {}{}", encoding, if compressed { ", gzip-compressed" } else { "" });
+            for (idx, msg) in errors.iter() {
+                println!("{:4}.{}", idx, msg);
+            }
+        }
+        Format::Json => {
+            let incs: Vec<String> = includes.iter().map(|i| format!("\"{}\"", json_escape(i))).collect();
+            let errs: Vec<String> = errors.iter().map(|(idx, msg)| format!("{{\"line\": {}, \"message\": \"{}\"}}", idx, json_escape(msg))).collect();
+            println!("{{\"name\": \"{}\", \"index_language\": \"{}\", \"contents_language\": \"{}\", \"includes\": [{}], \"encoding\": \"{}\", \"compressed\": {}, \"errors\": [{}]}}",
+                json_escape(name), json_escape(index_lang), json_escape(contents_lang), incs.join(", "), json_escape(&encoding), compressed, errs.join(", "));
+        }
+    }
+    !errors.is_empty()
 }
 
-fn fix_up_line(s: &str) -> String {
-    let mut in_sq = false;
-    let mut tag = String::new();
-    let mut last_c = ' ';
-    let mut res = String::new();
-    for c in s.chars() {
+// prints the header and the first `count` cards as plain text
+fn print_head(filename: &str, opts: &Options) {
+    let cont = load_utf16_file(filename, opts.input_encoding);
+    for l in text_lines(&cont).take_while(|l| l.starts_with('#')) {
+        println!("{}", l);
+    }
+    for c in card::cards(&cont).take(opts.count) {
+        println!();
         match c {
-            '[' => {
-                    if last_c == '\\' {
-                        last_c = c;
-                        res.push(c);
-                        continue;
-                    }
-                    tag = String::from("[");
-                    in_sq = true;
-            }
-            ']' => {
-                if last_c == '\\' {
-                    if in_sq {
-                        tag.push(c);
-                    } else {
-                        res.push(c);
-                    }
-                    last_c = c;
-                    continue;
-                }
-                if !in_sq {
-                    res.push('\\');
-                    res.push(c);
-                    continue;
-                }
-                tag.push(c);
-                in_sq = false;
-                let tp = tag_type(&tag);
-                if tp == DState::Invalid {
-                    let t = tag.trim_end_matches(|c| c == ']');
-                    let tg = format!("\\{}\\]", t);
-                    res.push_str(&tg);
-                    println!("replacing '{}' with '{}'", tag, tg);
-                } else {
-                    res.push_str(&tag);
-                }
-                tag = String::new();
-            },
-            _ => if in_sq {
-                tag.push(c);
-            } else {
-                res.push(c);
-            },
+            Ok(c) => print!("{}", c.to_text()),
+            Err(e) => println!("error: {}", e),
         }
-        last_c = c;
     }
-    if !tag.is_empty() {
-        res.push_str(&tag);
+}
+
+// cards of a dictionary by case-folded first headwords in the file order:
+// the headword as written and the body lines without leading and trailing
+// spaces. The bodies of cards with the same headword are joined
+fn card_bodies(cont: &str) -> Vec<(String, String, Vec<String>)> {
+    let mut res: Vec<(String, String, Vec<String>)> = Vec::new();
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for c in card::cards(cont).filter_map(Result::ok) {
+        let headword = match c.headwords.first() {
+            None => continue,
+            Some(h) => h.trim().to_string(),
+        };
+        let key = fold_case(&headword);
+        let body = c.body.iter().map(|l| l.trim().to_string()).filter(|l| !l.is_empty());
+        match seen.get(&key) {
+            Some(&i) => res[i].2.extend(body),
+            None => {
+                seen.insert(key.clone(), res.len());
+                res.push((key, headword, body.collect()));
+            }
+        }
     }
     res
 }
 
-// TODO:
-fn fix_invalid_tags(infile: &str, outfile: &str) {
-    let cont = load_utf16_file(infile);
-    let cursor = Cursor::new(cont.as_bytes());
-    let mut rvec: Vec<String> = Vec::new();
-    for l in cursor.lines().flatten() {
-        if !l.contains('[') {
-            rvec.push(l.to_string());
+// compares two dictionaries card by card: prints headwords of removed,
+// changed, and added cards. Returns true if the dictionaries differ
+fn diff_dicts(old_file: &str, new_file: &str, opts: &Options) -> bool {
+    let old = card_bodies(&load_utf16_file(old_file, opts.input_encoding));
+    let new = card_bodies(&load_utf16_file(new_file, opts.input_encoding));
+    let new_keys: HashMap<&str, &Vec<String>> = new.iter().map(|(k, _, b)| (k.as_str(), b)).collect();
+    let old_keys: HashMap<&str, &Vec<String>> = old.iter().map(|(k, _, b)| (k.as_str(), b)).collect();
+    let mut removed: Vec<&str> = Vec::new();
+    let mut changed: Vec<&str> = Vec::new();
+    for (k, h, b) in old.iter() {
+        match new_keys.get(k.as_str()) {
+            None => removed.push(h),
+            Some(nb) if *nb != b => changed.push(h),
+            Some(_) => {},
+        }
+    }
+    let added: Vec<&str> = new.iter().filter(|(k, _, _)| !old_keys.contains_key(k.as_str())).map(|(_, h, _)| h.as_str()).collect();
+    match opts.format {
+        Format::Text => {
+            for h in removed.iter() {
+                println!("- {}", h);
+            }
+            for h in changed.iter() {
+                println!("~ {}", h);
+            }
+            for h in added.iter() {
+                println!("+ {}", h);
+            }
+            println!("{} card(s) removed, {} changed, {} added", removed.len(), changed.len(), added.len());
+        }
+        Format::Json => {
+            let list = |v: &[&str]| v.iter().map(|h| format!("\"{}\"", json_escape(h))).collect::<Vec<String>>().join(", ");
+            println!("{{\"removed\": [{}], \"changed\": [{}], \"added\": [{}]}}", list(&removed), list(&changed), list(&added));
+        }
+    }
+    !(removed.is_empty() && changed.is_empty() && added.is_empty())
+}
+
+// prints cards with the headword, compared like in --only-card, with ANSI
+// styles if the output is a terminal. Returns true if there is no such card
+fn show_card(filename: &str, headword: &str, opts: &Options) -> bool {
+    let cont = load_utf16_file(filename, opts.input_encoding);
+    let wanted = fold_case(headword.trim());
+    let styled = std::io::stdout().is_terminal();
+    let mut found = false;
+    for c in card::cards(&cont).filter_map(Result::ok) {
+        if !c.headwords.iter().any(|h| fold_case(h.trim()) == wanted) {
             continue;
         }
-        let prs = fix_up_line(&l);
-        rvec.push(prs.to_string());
+        if found {
+            println!();
+        }
+        found = true;
+        print!("{}", if styled { c.to_ansi() } else { c.to_text() });
+    }
+    if !found {
+        println!("card '{}' not found", headword);
+    }
+    !found
+}
+
+// prints the case-insensitive index key of every headword
+fn print_index(filename: &str, opts: &Options) {
+    let cont = load_utf16_file(filename, opts.input_encoding);
+    let heads: Vec<&str> = text_lines(&cont).filter(|l| line_type(l) == DState::Key).collect();
+    match opts.format {
+        Format::Text => {
+            for h in heads.iter() {
+                println!("{}\t{}", index_key(h), h);
+            }
+        }
+        Format::Json => {
+            println!("[");
+            for (i, h) in heads.iter().enumerate() {
+                let sep = if i + 1 < heads.len() { "," } else { "" };
+                println!("  {{\"key\": \"{}\", \"headword\": \"{}\"}}{}", json_escape(&index_key(h)), json_escape(h), sep);
+            }
+            println!("]");
+        }
     }
+}
 
-    let mut f = fs::File::create(Path::new(&outfile)).unwrap();
-    for s in rvec.iter() {
-        writeln!(f, "{}", s).unwrap();
+// tags of the table and the custom tags of `--allow-tag`, which are paired
+fn list_tags(format: Format, allowed: &[String]) {
+    let allowed: Vec<&str> = allowed.iter().map(|t| t.trim_start_matches('[').trim_end_matches(']')).collect();
+    match format {
+        Format::Text => {
+            for t in TAGS.iter() {
+                let kind = if t.self_closing {
+                    String::from("self-closing")
+                } else {
+                    format!("paired, closed with [/{}]", closing_name(t.name))
+                };
+                let attrs = if t.takes_attributes { ", takes attributes" } else { "" };
+                let opaque = if t.opaque { ", contains a file name" } else { "" };
+                println!("{:8} {}{}{}", format!("[{}]", t.name), kind, attrs, opaque);
+            }
+            for name in allowed.iter() {
+                println!("{:8} paired, closed with [/{}], allowed with --allow-tag", format!("[{}]", name), name);
+            }
+        }
+        Format::Json => {
+            println!("[");
+            let total = TAGS.len() + allowed.len();
+            for (i, t) in TAGS.iter().enumerate() {
+                let sep = if i + 1 == total { "" } else { "," };
+                println!("  {{\"name\": \"{}\", \"self_closing\": {}, \"takes_attributes\": {}, \"opaque\": {}, \"custom\": false}}{}",
+                    t.name, t.self_closing, t.takes_attributes, t.opaque, sep);
+            }
+            for (i, name) in allowed.iter().enumerate() {
+                let sep = if TAGS.len() + i + 1 == total { "" } else { "," };
+                println!("  {{\"name\": \"{}\", \"self_closing\": false, \"takes_attributes\": false, \"opaque\": false, \"custom\": true}}{}",
+                    json_escape(name), sep);
+            }
+            println!("]");
+        }
     }
 }
 
-// TODO:
-fn sort_file(infile: &str, outfile: &str) {
+fn parse_args(args: Vec<String>) -> (Vec<String>, Options) {
+    let mut opts = Options::default();
+    let mut free: Vec<String> = Vec::new();
+    let mut it = args.into_iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "--compress" => opts.compress = true,
+            "--allow-monolingual" => opts.allow_monolingual = true,
+            "--escape-headwords" => opts.escape_headwords = true,
+            "--strip-bom" => opts.strip_bom = true,
+            "--replace-tag-tabs" => opts.replace_tag_tabs = true,
+            "--require-translation" => opts.require_translation = true,
+            "--check-script" => opts.check_script = true,
+            "--check-direction" => opts.check_direction = true,
+            "--errors-only" => opts.errors_only = true,
+            "--no-duplicate-check" => opts.no_duplicate_check = true,
+            "--hash-headwords" => opts.hash_headwords = true,
+            "--verbose" => opts.verbose = true,
+            "--trace" => opts.trace = true,
+            "--lenient" => opts.lenient = true,
+            "--follow-includes" => opts.follow_includes = true,
+            "--strict-media" => opts.strict_media = true,
+            "--report-shared" => opts.report_shared = true,
+            "--preserve-comments" => opts.preserve_comments = true,
+            "--summary-only" => opts.summary_only = true,
+            "--sort-by-severity" => opts.sort_by_severity = true,
+            "--expand-optional" => opts.expand_optional = true,
+            "--warn-bare-urls" => opts.warn_bare_urls = true,
+            "--check-redundant-margins" => opts.check_redundant_margins = true,
+            "--collapse-margins" => opts.collapse_margins = true,
+            "--flag-empty-formatting" => opts.flag_empty_formatting = true,
+            "--remove-empty-formatting" => opts.remove_empty_formatting = true,
+            "--flag-untranslated" => opts.flag_untranslated = true,
+            "--resolve-lang-ids" => opts.resolve_lang_ids = true,
+            "--check-indent" => opts.check_indent = true,
+            "--check-examples" => opts.check_examples = true,
+            "--abbrev-consistency" => opts.abbrev_consistency = true,
+            "--report-file" => opts.report_file = match it.next() {
+                Some(v) => Some(v),
+                None => {
+                    println!("--report-file requires a file name");
+                    exit(1);
+                }
+            },
+            "--schema" => opts.schema = match it.next() {
+                Some(v) => Some(v),
+                None => {
+                    println!("--schema requires a file name");
+                    exit(1);
+                }
+            },
+            "--only" => match it.next() {
+                Some(v) => opts.only.push(v),
+                None => {
+                    println!("--only requires a problem kind");
+                    exit(1);
+                }
+            },
+            "--ignore" => match it.next() {
+                Some(v) => opts.ignore.push(v),
+                None => {
+                    println!("--ignore requires a problem kind");
+                    exit(1);
+                }
+            },
+            "--allow-tag" => match it.next() {
+                Some(v) => opts.allow_tags.push(v),
+                None => {
+                    println!("--allow-tag requires a tag name");
+                    exit(1);
+                }
+            },
+            "--map" => match it.next().as_deref().and_then(|v| v.split_once('=')) {
+                Some((old, new)) if !old.trim().is_empty() && !new.trim().is_empty() => {
+                    let name = |t: &str| t.trim().trim_start_matches('[').trim_end_matches(']').to_string();
+                    opts.tag_maps.push((name(old), name(new)));
+                }
+                _ => {
+                    println!("--map requires a rename OLD=NEW, e.g. --map \"[p]=[pos]\"");
+                    exit(1);
+                }
+            },
+            "--multiline-tag" => match it.next() {
+                Some(v) if is_known_tag(&v) => opts.multiline_tags.push(v),
+                Some(v) => {
+                    println!("--multiline-tag: unknown tag '{}'", v);
+                    exit(1);
+                }
+                None => {
+                    println!("--multiline-tag requires a tag name");
+                    exit(1);
+                }
+            },
+            "--forbid-tag" => match it.next() {
+                Some(v) => opts.forbid_tags.push(v),
+                None => {
+                    println!("--forbid-tag requires a tag name");
+                    exit(1);
+                }
+            },
+            "--max-errors" => opts.max_errors = match it.next().map(|v| v.parse::<usize>()) {
+                Some(Ok(n)) => Some(n),
+                _ => {
+                    println!("--max-errors requires a number");
+                    exit(1);
+                }
+            },
+            "--max-line-length" => opts.max_line_length = match it.next().map(|v| v.parse::<usize>()) {
+                Some(Ok(n)) => Some(n),
+                _ => {
+                    println!("--max-line-length requires a number");
+                    exit(1);
+                }
+            },
+            "--only-card" => opts.only_card = match it.next() {
+                Some(v) => Some(v),
+                None => {
+                    println!("--only-card requires a headword");
+                    exit(1);
+                }
+            },
+            "--max-lang-depth" => opts.max_lang_depth = match it.next().map(|v| v.parse::<usize>()) {
+                Some(Ok(n)) => Some(n),
+                _ => {
+                    println!("--max-lang-depth requires a number");
+                    exit(1);
+                }
+            },
+            "--min-translation-length" => opts.min_translation_length = match it.next().map(|v| v.parse::<usize>()) {
+                Some(Ok(n)) => Some(n),
+                _ => {
+                    println!("--min-translation-length requires a number");
+                    exit(1);
+                }
+            },
+            "--id" | "--lang" => opts.lang_id = match it.next().map(|v| v.parse::<u16>()) {
+                Some(Ok(n)) => Some(n),
+                _ => {
+                    println!("{} requires a language id", arg);
+                    exit(1);
+                }
+            },
+            "--glob" => opts.glob = match it.next() {
+                Some(v) => Some(v),
+                None => {
+                    println!("--glob requires a pattern");
+                    exit(1);
+                }
+            },
+            "--exclude" => match it.next() {
+                Some(v) => opts.exclude.push(v),
+                None => {
+                    println!("--exclude requires a pattern");
+                    exit(1);
+                }
+            },
+            "--near-dup" => opts.near_dup = Some(opts.near_dup.unwrap_or(1)),
+            "--dup-ignore-trailing-punct" => opts.dup_ignore_trailing_punct = true,
+            "--near-dup-distance" => opts.near_dup = match it.next().map(|v| v.parse::<usize>()) {
+                Some(Ok(n)) => Some(n),
+                _ => {
+                    println!("--near-dup-distance requires a number");
+                    exit(1);
+                }
+            },
+            "--count" => opts.count = match it.next().map(|v| v.parse::<usize>()) {
+                Some(Ok(n)) => n,
+                _ => {
+                    println!("--count requires a number");
+                    exit(1);
+                }
+            },
+            "--entries-per-page" => opts.entries_per_page = match it.next().map(|v| v.parse::<usize>()) {
+                Some(Ok(n)) if n > 0 => Some(n),
+                _ => {
+                    println!("--entries-per-page requires a positive number");
+                    exit(1);
+                }
+            },
+            "--min-count" => opts.min_count = match it.next().map(|v| v.parse::<usize>()) {
+                Some(Ok(n)) => n,
+                _ => {
+                    println!("--min-count requires a number");
+                    exit(1);
+                }
+            },
+            "--ignore-case" => opts.ignore_case = true,
+            "--top" => opts.top = match it.next().map(|v| v.parse::<usize>()) {
+                Some(Ok(n)) => Some(n),
+                _ => {
+                    println!("--top requires a number");
+                    exit(1);
+                }
+            },
+            "--context" => opts.context = match it.next().map(|v| v.parse::<usize>()) {
+                Some(Ok(n)) => n,
+                _ => {
+                    println!("--context requires a number of lines");
+                    exit(1);
+                }
+            },
+            "--format" => opts.format = match it.next().as_deref() {
+                Some("text") => Format::Text,
+                Some("json") => Format::Json,
+                v => {
+                    println!("invalid format: {}", v.unwrap_or_default());
+                    exit(1);
+                }
+            },
+            "--sort-by" => opts.sort_by = match it.next().as_deref() {
+                Some("line") => SortBy::Line,
+                Some("kind") => SortBy::Kind,
+                v => {
+                    println!("invalid --sort-by order: {}", v.unwrap_or_default());
+                    exit(1);
+                }
+            },
+            "--input-encoding" => opts.input_encoding = match it.next().as_deref() {
+                Some("utf16le") => encoding_rs::UTF_16LE,
+                Some("utf16be") => encoding_rs::UTF_16BE,
+                v => {
+                    println!("invalid input encoding: {}", v.unwrap_or_default());
+                    exit(1);
+                }
+            },
+            "--algorithm" => opts.algorithm = match it.next().as_deref() {
+                Some("sha256") => Algorithm::Sha256,
+                Some("blake3") => Algorithm::Blake3,
+                v => {
+                    println!("invalid hash algorithm: {}", v.unwrap_or_default());
+                    exit(1);
+                }
+            },
+            "--severity" => match it.next().as_deref().and_then(|v| v.split_once('=')) {
+                Some((kind, level)) => {
+                    let sev = match level {
+                        "error" => Severity::Error,
+                        "warning" => Severity::Warning,
+                        "info" => Severity::Info,
+                        _ => {
+                            println!("--severity: level must be error, warning, or info");
+                            exit(1);
+                        }
+                    };
+                    opts.severities.push((kind.to_string(), sev));
+                }
+                None => {
+                    println!("--severity requires KIND=LEVEL, e.g. --severity line-too-long=error");
+                    exit(1);
+                }
+            },
+            "--fail-on" => opts.fail_on = match it.next().as_deref() {
+                Some("error") => FailOn::Error,
+                Some("warning") => FailOn::Warning,
+                Some("never") => FailOn::Never,
+                v => {
+                    println!("invalid --fail-on level: {}", v.unwrap_or_default());
+                    exit(1);
+                }
+            },
+            _ if arg.starts_with("--") => {
+                println!("unknown option: {}", arg);
+                exit(1);
+            }
+            _ => free.push(arg),
+        }
+    }
+    (free, opts)
 }
 
+// commands whose text output is data: it must not start with the
+// `cmd --> filename` line
+const NO_BANNER: &[&str] = &["tag-cooccurrence", "body-freq", "unknown-tags", "escape-audit", "examples", "head", "show", "diff", "info", "hash", "index", "stats"];
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let (args, opts) = parse_args(env::args().collect());
+    if let Some(f) = &opts.report_file {
+        if let Err(e) = fs::File::create(f) {
+            println!("{}: {}", f, e);
+            exit(1);
+        }
+    }
     if args.len() == 1 {
         println!("No arguments");
         println!("    check FILENAME\nCheck for valid tag order\n");
+        println!("    check DIRECTORY\nCheck all .dsl and .dsl.dz files in the directory and its subdirectories\n");
+        println!("    check --glob PATTERN\nCheck all files matching PATTERN, e.g. 'dicts/*.dsl' or 'dicts/**/*.dsl'\n");
+        println!("    --exclude GLOB\ncheck DIRECTORY and --glob: skip files and directories matching GLOB (can be repeated)\n");
+        println!("    media FILENAME [MEDIA_DIR]\nCheck that all sound and video files used by the dictionary exist\n");
+        println!("    --strict-media\nmedia: warn about video files in formats other than {}\n", VIDEO_EXTENSIONS.join(", "));
+        println!("    --report-shared\nmedia: list media files used by more than one card\n");
+        println!("    list-tags\nShow all tags the checker recognizes, including the tags of --allow-tag\n");
+        println!("    verify-encoding FILENAME OTHER_FILENAME\nCheck that two files contain the same text in any encoding (e.g. after conversion)\n");
+        println!("    head FILENAME\nShow the header and the first cards as plain text\n");
+        println!("    diff FILENAME NEW_FILENAME\nList cards removed, changed, and added in NEW_FILENAME, as text or JSON with --format json\n");
+        println!("    show FILENAME HEADWORD\nShow the card with the headword, with bold, italic, and dim text in a terminal\n");
+        println!("    info FILENAME\nShow the name, languages, and encoding of a dictionary and check the order of its header, reading only the header\n");
+        println!("    --entries-per-page N\nhtml: split the export into pages of N cards\n");
+        println!("    --count N\nhead: number of cards to show (default 5)\n");
+        println!("    html FILENAME OUT_FILENAME\nExport cards to HTML, with --entries-per-page N to pages of N cards and an index page OUT_FILENAME\n");
+        println!("    sort FILENAME OUT_FILENAME\nSort cards by headwords (editor comments go with the card above them)\n");
+        println!("    --preserve-comments\nsort: keep editor comments right before a headword with its card\n");
+        println!("    extract-lang FILENAME OUT_FILENAME --id N\nKeep only cards with [lang id=N] blocks, and remove blocks of other languages\n");
+        println!("    stats FILENAME\nShow how many cards have balanced tags and the most common tag error\n");
+        println!("    index FILENAME\nShow the case-insensitive index key of every headword\n");
+        println!("    hash FILENAME\nShow the hash of the dictionary text that ignores encoding, compression, and line endings\n");
+        println!("    --algorithm sha256|blake3\nhash: the hash function (default sha256)\n");
+        println!("    tag-cooccurrence FILENAME\nShow how many cards use every pair of tags, as CSV or JSON with --format json\n");
+        println!("    --top N\ntag-cooccurrence, body-freq: show only N most frequent pairs or words\n");
+        println!("    examples FILENAME\nPrint examples [ex]...[/ex] without tags as TSV rows headword<TAB>example, only examples of language N with --lang N\n");
+        println!("    escape-audit FILENAME...\nCount escaped brackets and unescaped literal brackets that fix-tags would escape in every file, as text or JSON with --format json\n");
+        println!("    unknown-tags FILENAME\nList tags the checker does not know with the number of uses and the first line, as CSV or JSON with --format json\n");
+        println!("    body-freq FILENAME\nShow how often every word is used in card bodies, as CSV or JSON with --format json\n");
+        println!("    --min-count N\nbody-freq: show only words used at least N times\n");
+        println!("    --ignore-case\nbody-freq: count words in different case as one word\n");
         println!("    fix-tags FILENAME OUT_FILENAME\nEscape square brackets for unknown tags (use only if check is OK)\n");
+        println!("    normalize FILENAME OUT_FILENAME\nRe-save a dictionary as UTF-16LE with a BOM and CRLF line ends\n");
+        println!("    verify-normalize FILENAME\nMake sure that normalize does not change its own output, i.e. running it twice gives the same bytes\n");
+        println!("    --escape-headwords\nfix-tags: escape stray special characters in headwords\n");
+        println!("    fix-all FILENAME OUT_FILENAME\nApply all safe fixes: byte order marks, control characters, trailing whitespace, unknown tags, indentation\n");
+        println!("    retag FILENAME OUT_FILENAME --map OLD=NEW\nRename tags, e.g. --map \"[p]=[pos]\" (can be repeated)\n");
+        println!("    fix-indent FILENAME OUT_FILENAME\nIndent every line with margin [mN] with N TABs (or N times the TABs per level the file uses)\n");
+        println!("    --replace-tag-tabs\nfix-tags: replace TABs inside tags with spaces\n");
+        println!("    --flag-empty-formatting\nWarn about tags without text inside, like [b][/b] or [i] [/i]\n");
+        println!("    --remove-empty-formatting\nfix-tags: remove tags without text inside, keeping spaces between them\n");
+        println!("    --collapse-margins\nfix-tags: remove margins repeated without text between them, [m1][m1]a[/m][/m] becomes [m1]a[/m]\n");
+        println!("    --strip-bom\nfix-tags: remove byte order marks left inside the text after joining files\n");
+        println!("    --compress\nWrite gzip-compressed UTF-16LE output (always on if OUT_FILENAME ends with .dz)\n");
+        println!("    --format text|json\nOutput format of check results\n");
+        println!("    --report-file FILE\nAlso write the list of problems to FILE (in --format)\n");
+        println!("    --context N\nShow N lines before and after every error\n");
+        println!("    --require-translation\nReport cards that contain only comments and examples\n");
+        println!("    --schema FILE\nCheck that every card follows the house style described in JSON FILE\n");
+        println!("    --expand-optional\nShow how keywords with optional parts are indexed\n");
+        println!("    --summary-only\nShow one line per card with the number and kinds of its problems\n");
+        println!("    --sort-by line|kind\nShow problems in line order (default) or grouped by kind\n");
+        println!("    --sort-by-severity\nSort --summary-only output: cards with most errors go first\n");
+        println!("    --follow-includes\nCheck headwords of files included with #INCLUDE for duplicates\n");
+        println!("    --hash-headwords\nKeep only hashes of keywords for the duplicate check (less memory)\n");
+        println!("    --no-duplicate-check\nDo not look for duplicated keywords (saves memory on huge dictionaries)\n");
+        println!("    --allow-tag NAME\nAccept tag [NAME] the checker does not know (can be repeated)\n");
+        println!("    --forbid-tag NAME\nReport every use of tag [NAME] as an error, e.g. a deprecated tag (can be repeated)\n");
+        println!("    --multiline-tag NAME\nLet tag [NAME] be closed on a later line of the card, e.g. com (can be repeated)\n");
+        println!("    --max-errors N\nStop after N errors\n");
+        println!("    --abbrev-consistency\nWarn about labels [p] written in different ways, like 'n.' and 'n'\n");
+        println!("    --check-examples\nWarn about examples [ex] without a translation\n");
+        println!("    --warn-bare-urls\nWarn about links that are not wrapped in [url]\n");
+        println!("    --check-redundant-margins\nWarn about margins repeated without text between them, like [m1][m1]\n");
+        println!("    --flag-untranslated\nWarn about translations [trn] that repeat the headword of their card\n");
+        println!("    --resolve-lang-ids\nWarn about [lang id=N] with an unknown language id\n");
+        println!("    --check-indent\nWarn about body lines with leading TABs that do not agree with their margins\n");
+        println!("    --max-line-length N\nWarn about body lines longer than N visible characters\n");
+        println!("    --max-lang-depth N\nWarn about [lang] blocks nested more than N deep\n");
+        println!("    --min-translation-length N\nWarn about translations [trn] shorter than N visible characters and empty ones\n");
+        println!("    --only-card HEADWORD\nShow only problems of the card with the headword, the case is ignored\n");
+        println!("    --only KIND\nShow only problems of the given kind, e.g. unknown-tag (can be repeated)\n");
+        println!("    --ignore KIND\nDo not show problems of the given kind (can be repeated)\n");
+        println!("    --verbose\nShow editor comments (lines starting with ## or ;)\n");
+        println!("    --fail-on error|warning|never\nExit with code 1 on errors (default), on errors and warnings, or never\n");
+        println!("    --severity KIND=error|warning|info\nChange the severity of problems of KIND, e.g. --severity line-too-long=error (can be repeated)\n");
+        println!("    --input-encoding utf16le|utf16be\nEncoding of files without a BOM (default utf16le)\n");
+        println!("    --lenient\nAccept lines in any order, except card bodies before the first headword\n");
+        println!("    --trace\nPrint the type of every line and whether it may follow the previous line to stderr\n");
+        println!("    --errors-only\nShow only errors, skip warnings\n");
+        println!("    --check-script\nWarn if card texts are not in the script of #CONTENTS_LANGUAGE\n");
+        println!("    --check-direction\nWarn about cards with the headword in the script of #CONTENTS_LANGUAGE or translations in the script of #INDEX_LANGUAGE\n");
+        println!("    --near-dup\nReport keywords that look like typos of other keywords\n");
+        println!("    --near-dup-distance N\nMaximal number of different letters for --near-dup (default 1)\n");
+        println!("    --dup-ignore-trailing-punct\nWarn about keywords that differ only by trailing . , ; or :, like 'word' and 'word.'\n");
+        println!("    --allow-monolingual\nDo not report the same index and contents languages\n");
+        return;
+    } else if args[1] == "list-tags" {
+        list_tags(opts.format, &opts.allow_tags);
+        return;
+    } else if args[1] == "check" && opts.glob.is_some() {
+        if check_glob(opts.glob.as_deref().unwrap_or_default(), &opts) {
+            exit(1);
+        }
         return;
     } else if args.len() == 2 {
         println!("two arguments expected: [COMMAND] [FILE]");
         return;
     }
+
     let cmd = args[1].as_str();
     let filename = args[2].as_str();
-    println!("{} --> {}", cmd, filename);
+    // CSV and plain text output must not start with the header line
+    if opts.format == Format::Text && !NO_BANNER.contains(&cmd) {
+        println!("{} --> {}", cmd, filename);
+    }
 
     match cmd {
-        "check" => check_grammar(filename),
+        "check" if Path::new(filename).is_dir() => if check_dir(filename, &opts) {
+            exit(1);
+        },
+        "check" => if check_grammar(filename, &opts) {
+            exit(1);
+        },
+        "media" => if check_media(filename, args.get(3).map(|s| s.as_str()), &opts) {
+            exit(1);
+        },
         "fix-tags" => if args.len() < 4 {
             println!("output filename is undefined");
         } else {
-            fix_invalid_tags(filename, &args[3]);
+            fix_invalid_tags(filename, &args[3], &opts);
+        },
+        "stats" => print_stats(filename, &opts),
+        "html" => if args.len() < 4 {
+            println!("output filename is undefined");
+        } else {
+            export_html(filename, &args[3], &opts);
+        },
+        "extract-lang" => match opts.lang_id {
+            _ if args.len() < 4 => println!("output filename is undefined"),
+            None => println!("language id is undefined, pass --id N"),
+            Some(id) => extract_lang(filename, &args[3], id, &opts),
+        },
+        "fix-all" => if args.len() < 4 {
+            println!("output filename is undefined");
+        } else if fix_all(filename, &args[3], &opts) {
+            exit(1);
+        },
+        "retag" => if args.len() < 4 {
+            println!("output filename is undefined");
+        } else if opts.tag_maps.is_empty() {
+            println!("no tags to rename, pass --map OLD=NEW");
+        } else if retag(filename, &args[3], &opts) {
+            exit(1);
+        },
+        "verify-normalize" => if verify_normalize(filename, &opts) {
+            exit(1);
+        },
+        "fix-indent" => if args.len() < 4 {
+            println!("output filename is undefined");
+        } else {
+            fix_indents(filename, &args[3], &opts);
+        },
+        "tag-cooccurrence" => tag_cooccurrence(filename, &opts),
+        "body-freq" => body_freq(filename, &opts),
+        "unknown-tags" => list_unknown_tags(filename, &opts),
+        "escape-audit" => escape_audit(&args[2..], &opts),
+        "examples" => list_examples(filename, &opts),
+        "verify-encoding" => if args.len() < 4 {
+            println!("second filename is undefined");
+        } else if verify_encoding(filename, &args[3]) {
+            exit(1);
+        },
+        "head" => print_head(filename, &opts),
+        "diff" => match args.get(3) {
+            None => println!("second filename is undefined"),
+            Some(f) => if diff_dicts(filename, f, &opts) {
+                exit(1);
+            },
+        },
+        "show" => match args.get(3) {
+            None => println!("headword is undefined"),
+            Some(h) => if show_card(filename, h, &opts) {
+                exit(1);
+            },
+        },
+        "info" => if print_info(filename, &opts) {
+            exit(1);
         },
+        "index" => print_index(filename, &opts),
+        "hash" => println!("{}", content_hash(filename, opts.algorithm)),
         "sort" => if args.len() < 4 {
             println!("output filename is undefined");
         } else {
-            sort_file(filename, &args[3]);
+            sort_file(filename, &args[3], &opts);
+        },
+        "normalize" => if args.len() < 4 {
+            println!("output filename is undefined");
+        } else {
+            normalize_file(filename, &args[3], &opts);
         },
         _ => println!("invalid command: {}", cmd),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a path in the temporary directory unique for the test
+    fn temp_file(name: &str) -> String {
+        std::env::temp_dir().join(format!("dsldoc-{}-{}", std::process::id(), name)).to_string_lossy().to_string()
+    }
+
+    const DICT: &str = "#NAME \"t\"\r\n#INDEX_LANGUAGE \"English\"\r\n#CONTENTS_LANGUAGE \"Russian\"\r\n\r\n";
+
+    fn utf16(s: &str) -> Vec<u8> {
+        s.encode_utf16().flat_map(|c| c.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn fix_all_keeps_tab_only_lines() {
+        assert_eq!(apply_fix(2, "cat ", 1), "cat");
+        assert_eq!(apply_fix(2, "\t[m1][trn]кошка[/trn][/m]  ", 1), "\t[m1][trn]кошка[/trn][/m]");
+        // TABs of an empty paragraph stay, the line must not end the card
+        assert_eq!(apply_fix(2, "\t \t", 1), "\t\t");
+        assert_eq!(apply_fix(2, " \t", 1), "");
+    }
+
+    #[test]
+    fn content_hash_ignores_line_ends_and_encoding() {
+        let crlf = format!("\u{feff}{}cat\r\n\t[m1][trn]кошка[/trn][/m]\r\n", DICT);
+        let lf = crlf.replace("\r\n", "\n");
+        let files = [
+            ("crlf.dsl", utf16(&crlf)),
+            ("lf.dsl", utf16(&lf)),
+            ("cr.dsl", utf16(&crlf.replace("\r\n", "\r"))),
+            ("utf8.dsl", lf.trim_start_matches('\u{feff}').as_bytes().to_vec()),
+            ("crlf.dsl.dz", gzip::compress(&utf16(&crlf))),
+            ("other.dsl", utf16(&lf.replace("кошка", "кот"))),
+        ];
+        for algorithm in [Algorithm::Sha256, Algorithm::Blake3] {
+            let hashes: Vec<String> = files.iter().map(|(name, bytes)| {
+                let path = temp_file(name);
+                fs::write(&path, bytes).unwrap();
+                let hash = content_hash(&path, algorithm);
+                fs::remove_file(&path).unwrap();
+                hash
+            }).collect();
+            assert!(hashes[..5].iter().all(|h| *h == hashes[0]), "{:?}", hashes);
+            assert_ne!(hashes[5], hashes[0]);
+        }
+    }
+
+    #[test]
+    fn first_difference_counts_missing_lines() {
+        let a: Vec<String> = vec!["a".into(), "b".into()];
+        let b: Vec<String> = vec!["a".into(), "b".into(), String::new()];
+        assert_eq!(first_difference(&a, &a), None);
+        assert_eq!(first_difference(&a, &b), Some(2));
+        assert_eq!(first_difference(&b, &a), Some(2));
+        assert_eq!(first_difference(&a, &b[1..]), Some(0));
+    }
+
+    #[test]
+    fn normalizing_is_idempotent() {
+        let fixtures = [
+            format!("{}cat\r\n\t[m1][trn]кошка[/trn][/m]\r\n", DICT),
+            format!("{}cat\n\t[m1][trn]кошка[/trn][/m]\n", DICT),
+            format!("{}cat\r\t[m1][trn]кошка[/trn][/m]\r\rdog\r\n\t[m1]собака[/m]", DICT),
+            format!("{}dog\r\n\t[m1][ex]a dog\u{feff}[/ex][/m]\r\n\t\r\n\r\n\r\n", DICT),
+        ];
+        for cont in fixtures.iter() {
+            let (first, second) = normalize_twice(cont);
+            assert!(first == second, "{:?}", cont);
+            assert_eq!(first_difference(&saved_lines(&first), &normalize_lines(cont)), None, "{:?}", cont);
+        }
+    }
+
+    #[test]
+    fn save_file_round_trip() {
+        let lines: Vec<String> = vec!["#NAME \"t\"".to_string(), String::new(), "кот".to_string(), "\t[m1][trn]猫[/trn][/m]".to_string()];
+        for (name, compress) in [("plain.dsl", false), ("packed.dsl", true), ("packed.dsl.dz", false)] {
+            let path = temp_file(name);
+            save_file(&path, &lines, compress).unwrap();
+            let bytes = fs::read(&path).unwrap();
+            assert_eq!(gzip::is_gzip(&bytes), compress || name.ends_with(".dz"), "{}", name);
+            let cont = load_utf16_file(&path, encoding_rs::UTF_16LE);
+            fs::remove_file(&path).unwrap();
+            assert_eq!(cont, "#NAME \"t\"\r\n\r\nкот\r\n\t[m1][trn]猫[/trn][/m]\r\n", "{}", name);
+        }
+        assert!(save_file(&temp_file("no-such-dir/a.dsl"), &lines, false).is_err());
+    }
+
+    #[test]
+    fn leading_bom_is_skipped() {
+        // a converter may write a second BOM after the one the decoder removes
+        for prefix in ["", "\u{feff}"] {
+            let path = temp_file("bom.dsl");
+            let lines = vec![format!("{}#NAME \"t\"", prefix), String::from("#INDEX_LANGUAGE \"English\"")];
+            save_file(&path, &lines, false).unwrap();
+            let cont = load_utf16_file(&path, encoding_rs::UTF_16LE);
+            fs::remove_file(&path).unwrap();
+            assert!(cont.starts_with("#NAME "), "{:?}", cont);
+            assert_eq!(line_type(cont.lines().next().unwrap()), DState::Name);
+        }
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob_match("*.dsl", "en-ru.dsl"));
+        assert!(!glob_match("*.dsl", "en-ru.dsl.dz"));
+        assert!(!glob_match("*.dsl", "dicts/en-ru.dsl"));
+        assert!(glob_match("dicts/??-ru.dsl", "dicts/en-ru.dsl"));
+        assert!(!glob_match("dicts/?", "dicts/a/b"));
+        assert!(glob_match("dicts/**/*.dsl", "dicts/en-ru.dsl"));
+        assert!(glob_match("dicts/**/*.dsl", "dicts/a/b/en-ru.dsl"));
+        assert!(glob_match("**", "a/b"));
+    }
+
+    #[test]
+    fn verify_encoding_after_conversion() {
+        let text = format!("\u{feff}{}кот\r\n\t[m1][trn]猫 𝄞[/trn][/m]\r\n", DICT);
+        let (utf16_path, utf8_path, back_path, lossy_path) = (temp_file("verify16.dsl"), temp_file("verify8.dsl"),
+            temp_file("verify-back.dsl"), temp_file("verify-lossy.dsl"));
+        fs::write(&utf16_path, utf16(&text)).unwrap();
+        // UTF-16LE to UTF-8 and back
+        let decoded = decode_any(&fs::read(&utf16_path).unwrap());
+        fs::write(&utf8_path, decoded.trim_start_matches('\u{feff}').as_bytes()).unwrap();
+        let decoded = decode_any(&fs::read(&utf8_path).unwrap());
+        fs::write(&back_path, utf16(&decoded)).unwrap();
+        assert!(!verify_encoding(&utf16_path, &utf8_path));
+        assert!(!verify_encoding(&utf16_path, &back_path));
+        // a codepage without the characters replaces them
+        fs::write(&lossy_path, text.replace("猫 𝄞", "? ?").as_bytes()).unwrap();
+        assert!(verify_encoding(&utf16_path, &lossy_path));
+        for path in [utf16_path, utf8_path, back_path, lossy_path] {
+            fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn info_reads_only_the_header() {
+        let path = temp_file("info-header.dsl");
+        let mut bytes = utf16(&format!("\u{feff}{}", DICT));
+        let header_len = bytes.len();
+        // the cards are not valid UTF-16 and would take long to read
+        bytes.extend(std::iter::repeat_n(0xd8, 1 << 20));
+        fs::write(&path, &bytes).unwrap();
+        let (read, compressed) = read_header_bytes(&path);
+        assert!(!compressed);
+        assert!(read.len() >= header_len && read.len() <= HEADER_CHUNK, "{}", read.len());
+        let (_, opts) = parse_args(vec![String::from("dsldoc"), String::from("info"), path.clone()]);
+        assert!(!print_info(&path, &opts));
+        fs::remove_file(&path).unwrap();
+    }
+}
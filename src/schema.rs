@@ -0,0 +1,132 @@
+// House style of cards described in a JSON file:
+//
+// {
+//   "required": ["p", "trn"],
+//   "order": ["p", "trn"]
+// }
+//
+// `required` lists tags every card body must contain. `order` lists tags
+// that, when present, must first appear in the given order
+
+use crate::card::Card;
+use crate::{is_known_tag, json, lookup_tag};
+
+#[derive(Debug,PartialEq,Clone,Default)]
+pub struct Schema {
+    pub required: Vec<String>,
+    pub order: Vec<String>,
+}
+
+fn name_list(v: &json::Value, key: &str) -> Result<Vec<String>, String> {
+    let arr = match v.get(key) {
+        None => return Ok(Vec::new()),
+        Some(a) => match a.as_array() {
+            None => return Err(format!("'{}' must be an array of tag names", key)),
+            Some(a) => a,
+        },
+    };
+    let mut res = Vec::new();
+    for item in arr.iter() {
+        let name = match item.as_str() {
+            None => return Err(format!("'{}' must be an array of tag names", key)),
+            Some(s) => s.trim_start_matches('[').trim_end_matches(']'),
+        };
+        if !is_known_tag(name) {
+            return Err(format!("unknown tag '{}' in '{}'", name, key));
+        }
+        res.push(name.to_string());
+    }
+    Ok(res)
+}
+
+impl Schema {
+    pub fn parse(s: &str) -> Result<Schema, String> {
+        let v = json::parse(s)?;
+        Ok(Schema { required: name_list(&v, "required")?, order: name_list(&v, "order")? })
+    }
+
+    // returns the list of deviations of a card from the schema
+    pub fn validate(&self, card: &Card) -> Vec<String> {
+        let used = card_tags(card);
+        let mut res = Vec::new();
+        for name in self.required.iter() {
+            if !used.contains(name) {
+                res.push(format!("[{}] is required", name));
+            }
+        }
+        let present: Vec<&String> = self.order.iter().filter(|n| used.contains(n)).collect();
+        let actual: Vec<&String> = used.iter().filter(|n| self.order.contains(n)).collect();
+        if present != actual {
+            let names = |v: &[&String]| v.iter().map(|n| format!("[{}]", n)).collect::<Vec<String>>().join(", ");
+            res.push(format!("tags must go in order {}, found {}", names(&present), names(&actual)));
+        }
+        res
+    }
+}
+
+// names of opening tags used in a card body in order of their first appearance
+pub fn card_tags(card: &Card) -> Vec<String> {
+    let mut res: Vec<String> = Vec::new();
+    for l in card.body.iter() {
+        let mut rest = l.as_str();
+        while let Some(start) = rest.find('[') {
+            // `\\[b]` is an escaped backslash followed by a tag
+            let slashes = rest[..start].bytes().rev().take_while(|&b| b == b'\\').count();
+            let escaped = slashes % 2 == 1;
+            let end = match rest[start..].find(']') {
+                None => break,
+                Some(e) => start + e + 1,
+            };
+            if !escaped {
+                if let Some((kind, false)) = lookup_tag(&rest[start..end]) {
+                    if !res.iter().any(|n| n == kind.name) {
+                        res.push(kind.name.to_string());
+                    }
+                }
+            }
+            rest = &rest[end..];
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn card(body: &[&str]) -> Card {
+        Card { line: 0, headwords: vec![String::from("cat")], pos: None, body: body.iter().map(|l| l.to_string()).collect() }
+    }
+
+    #[test]
+    fn parse_schema() {
+        let sch = Schema::parse("{\"required\": [\"[trn]\"], \"order\": [\"p\", \"trn\"]}").unwrap();
+        assert_eq!(sch, Schema { required: vec![String::from("trn")], order: vec![String::from("p"), String::from("trn")] });
+        assert_eq!(Schema::parse("{}"), Ok(Schema::default()));
+        assert_eq!(Schema::parse("{\"required\": [\"xyz\"]}"), Err(String::from("unknown tag 'xyz' in 'required'")));
+        assert_eq!(Schema::parse("{\"order\": \"p\"}"), Err(String::from("'order' must be an array of tag names")));
+        assert!(Schema::parse("{\"order\": [").is_err());
+    }
+
+    #[test]
+    fn validate_cards() {
+        let sch = Schema::parse("{\"required\": [\"p\", \"trn\"], \"order\": [\"p\", \"trn\", \"ex\"]}").unwrap();
+        let good = card(&["\t[m1][p]n[/p] [trn]кошка[/trn][/m]", "\t[m2][ex]a cat[/ex][/m]"]);
+        assert_eq!(sch.validate(&good), Vec::<String>::new());
+        // the order only matters for the tags a card has
+        assert_eq!(sch.validate(&card(&["\t[m1][p]n[/p] [trn]кошка[/trn][/m]"])), Vec::<String>::new());
+        assert_eq!(sch.validate(&card(&["\t[m1][trn]кошка[/trn] [p]n[/p][/m]"])),
+            vec![String::from("tags must go in order [p], [trn], found [trn], [p]")]);
+        assert_eq!(sch.validate(&card(&["\t[m1][ex]a cat[/ex] \\[p\\][/m]"])),
+            vec![String::from("[p] is required"), String::from("[trn] is required")]);
+    }
+
+    #[test]
+    fn escaped_tags() {
+        let tags = |l: &str| card_tags(&card(&[l]));
+        assert_eq!(tags("\t[m1]\\[p\\] [trn]a[/trn][/m]"), vec!["m1", "trn"]);
+        // an escaped backslash does not escape the tag after it
+        assert_eq!(tags("\t[m1]\\\\[p]n[/p][/m]"), vec!["m1", "p"]);
+        assert_eq!(tags("\t[m1]\\\\\\[p\\][/m]"), vec!["m1"]);
+    }
+}
@@ -0,0 +1,98 @@
+// Rough detection of writing systems by Unicode block ranges
+
+#[derive(Debug,PartialEq,Eq,Hash,Copy,Clone)]
+pub enum Script {
+    Latin,
+    Cyrillic,
+    Greek,
+    Armenian,
+    Georgian,
+    Hebrew,
+    Arabic,
+    Devanagari,
+    Thai,
+    Hangul,
+    Kana,
+    Han,
+    Other,
+}
+
+pub fn char_script(c: char) -> Script {
+    match c as u32 {
+        0x41..=0x5a | 0x61..=0x7a | 0xc0..=0x24f | 0x1e00..=0x1eff => Script::Latin,
+        0x370..=0x3ff | 0x1f00..=0x1fff => Script::Greek,
+        0x400..=0x52f | 0x1c80..=0x1c8f | 0x2de0..=0x2dff | 0xa640..=0xa69f => Script::Cyrillic,
+        0x530..=0x58f => Script::Armenian,
+        0x590..=0x5ff => Script::Hebrew,
+        0x600..=0x6ff | 0x750..=0x77f => Script::Arabic,
+        0x900..=0x97f => Script::Devanagari,
+        0xe00..=0xe7f => Script::Thai,
+        0x10a0..=0x10ff => Script::Georgian,
+        0x1100..=0x11ff | 0xac00..=0xd7af => Script::Hangul,
+        0x3040..=0x30ff => Script::Kana,
+        0x3400..=0x4dbf | 0x4e00..=0x9fff => Script::Han,
+        _ => Script::Other,
+    }
+}
+
+// script of the language as it is named in DSL headers
+pub fn language_script(lang: &str) -> Option<Script> {
+    let s = match lang.to_lowercase().as_str() {
+        "russian" | "ukrainian" | "belarusian" | "bulgarian" | "serbian" | "macedonian"
+        | "kazakh" | "kyrgyz" | "tatar" | "mongolian" | "bashkir" | "chuvash" => Script::Cyrillic,
+        "english" | "german" | "french" | "spanish" | "italian" | "portuguese" | "dutch"
+        | "polish" | "czech" | "slovak" | "slovenian" | "croatian" | "danish" | "norwegian"
+        | "swedish" | "finnish" | "estonian" | "latvian" | "lithuanian" | "hungarian"
+        | "romanian" | "turkish" | "latin" | "esperanto" | "indonesian" | "vietnamese" => Script::Latin,
+        "greek" | "greekmodern" => Script::Greek,
+        "armenian" => Script::Armenian,
+        "georgian" => Script::Georgian,
+        "hebrew" => Script::Hebrew,
+        "arabic" | "persian" | "farsi" => Script::Arabic,
+        "hindi" => Script::Devanagari,
+        "thai" => Script::Thai,
+        "korean" => Script::Hangul,
+        "japanese" => Script::Kana,
+        "chinese" | "chineseprc" | "chinesetaiwan" => Script::Han,
+        _ => return None,
+    };
+    Some(s)
+}
+
+// the script of a text if all its letters are in one script, characters of
+// unknown scripts are ignored
+pub fn text_script(s: &str) -> Option<Script> {
+    let mut res = None;
+    for sc in s.chars().filter(|c| c.is_alphabetic()).map(char_script).filter(|&sc| sc != Script::Other) {
+        match res {
+            None => res = Some(sc),
+            Some(r) if r != sc => return None,
+            Some(_) => {},
+        }
+    }
+    res
+}
+
+// Japanese text mixes kana and kanji
+pub fn script_matches(lang: Script, s: Script) -> bool {
+    lang == s || (lang == Script::Kana && s == Script::Han)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripts_of_texts() {
+        assert_eq!(char_script('ж'), Script::Cyrillic);
+        assert_eq!(char_script('é'), Script::Latin);
+        assert_eq!(text_script("кошка, кот"), Some(Script::Cyrillic));
+        assert_eq!(text_script("a cat 1"), Some(Script::Latin));
+        assert_eq!(text_script("cat кот"), None);
+        assert_eq!(text_script("123"), None);
+        assert_eq!(language_script("Russian"), Some(Script::Cyrillic));
+        assert_eq!(language_script("Klingon"), None);
+        assert!(script_matches(Script::Kana, Script::Han));
+        assert!(!script_matches(Script::Han, Script::Kana));
+    }
+}
@@ -0,0 +1,996 @@
+// Tests of the command line tool: they run it on temporary dictionaries and
+// check its output and exit code
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const HEADER: &str = "#NAME \"Test\"\r\n#INDEX_LANGUAGE \"English\"\r\n#CONTENTS_LANGUAGE \"Russian\"\r\n\r\n";
+
+// an empty directory unique for the test
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("dsldoc-cli-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// saves a dictionary the way Lingvo does: UTF-16LE with a BOM
+fn write_dict(path: &Path, text: &str) -> String {
+    let mut bytes = vec![0xff, 0xfe];
+    bytes.extend(text.encode_utf16().flat_map(|c| c.to_le_bytes()));
+    fs::write(path, bytes).unwrap();
+    path.to_string_lossy().to_string()
+}
+
+// text of UTF-16LE bytes that start with a BOM
+fn decode(bytes: &[u8]) -> String {
+    assert_eq!(bytes[..2], [0xff, 0xfe], "no BOM");
+    let units: Vec<u16> = bytes[2..].chunks(2).map(|b| u16::from_le_bytes([b[0], b[1]])).collect();
+    String::from_utf16(&units).unwrap()
+}
+
+fn fixture(name: &str) -> String {
+    format!("{}/tests/data/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+struct Output {
+    stdout: String,
+    stderr: String,
+    code: i32,
+}
+
+fn run(args: &[&str]) -> Output {
+    let out = Command::new(env!("CARGO_BIN_EXE_dsldoc")).args(args).output().unwrap();
+    Output {
+        stdout: String::from_utf8_lossy(&out.stdout).to_string(),
+        stderr: String::from_utf8_lossy(&out.stderr).to_string(),
+        code: out.status.code().unwrap_or(-1),
+    }
+}
+
+#[test]
+fn tab_in_headword() {
+    let dir = temp_dir("tab-in-headword");
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}foo\tbar\r\n\t[m1]x[/m]\r\n", HEADER));
+    let out = run(&["check", &path]);
+    assert_eq!(out.stdout.lines().collect::<Vec<_>>(), ["check --> ".to_string() + &path, "   4.tab inside headword ==> foo\tbar".to_string()]);
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}foo bar\r\n\t[m1]x[/m]\r\n", HEADER));
+    let out = run(&["check", &path]);
+    assert_eq!((out.code, out.stdout.lines().count()), (0, 1));
+}
+
+#[test]
+fn check_compressed_dictionary() {
+    // the same problems as in the unpacked file
+    let report = |path: &str| run(&["check", path]).stdout.lines().skip(1).map(String::from).collect::<Vec<_>>();
+    let expected = report(&fixture("small.dsl"));
+    assert_eq!(report(&fixture("small.dsl.dz")), expected);
+    // the name does not end with .dz, the gzip signature is enough
+    let dir = temp_dir("gzip-signature");
+    let path = dir.join("small.dsl");
+    fs::copy(fixture("small.dsl.dz"), &path).unwrap();
+    assert_eq!(report(path.to_str().unwrap()), expected);
+    // a broken file is reported instead of read as a dictionary
+    let broken = fs::read(fixture("small.dsl.dz")).unwrap();
+    fs::write(&path, &broken[..broken.len() - 20]).unwrap();
+    let out = run(&["check", path.to_str().unwrap()]);
+    assert_eq!(out.code, 1);
+    assert!(out.stdout.contains("unexpected end of compressed data"), "{}", out.stdout);
+}
+
+#[test]
+fn write_compressed_output() {
+    let dir = temp_dir("compressed-output");
+    let expected = format!("{}cat\r\n\t[m1][b]кошка[/b][/m]\r\n\t[m2][ex]a black cat[/ex][/m]\r\n", HEADER);
+    let input = write_dict(&dir.join("dict.dsl"), &expected);
+    let report = run(&["check", &input]).stdout.lines().skip(1).map(String::from).collect::<Vec<_>>();
+    for (cmd, name, flag) in [("normalize", "normal.dsl.dz", None), ("normalize", "normal.dsl", Some("--compress")), ("fix-tags", "fixed.dsl.dz", None),
+        ("sort", "sorted.dsl", Some("--compress"))] {
+        let path = dir.join(name);
+        let path = path.to_str().unwrap();
+        let mut args = vec![cmd, &input, path];
+        args.extend(flag);
+        assert_eq!(run(&args).code, 0, "{}", cmd);
+        assert_eq!(fs::read(path).unwrap()[..2], [0x1f, 0x8b], "{}", cmd);
+        // normalize unpacks it back
+        let unpacked = dir.join("unpacked.dsl");
+        assert_eq!(run(&["normalize", path, unpacked.to_str().unwrap()]).code, 0);
+        assert_eq!(decode(&fs::read(&unpacked).unwrap()), expected, "{}", cmd);
+        let out = run(&["check", path]);
+        assert_eq!(out.stdout.lines().skip(1).collect::<Vec<_>>(), report, "{}", cmd);
+    }
+    // an unwritable path is an error, not a panic
+    let out = run(&["normalize", &input, dir.join("no-such-dir/a.dsl").to_str().unwrap()]);
+    assert_eq!(out.code, 1);
+    assert!(out.stdout.contains("no-such-dir"), "{}", out.stdout);
+}
+
+#[test]
+fn json_byte_offsets() {
+    let dir = temp_dir("byte-offsets");
+    // Cyrillic letters take 2 bytes in UTF-16, the emoji takes 4
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}кот\r\n\t[m1][x]кошка[/m]\r\n😀\r\n\t[m1][y][/m]\r\n", HEADER));
+    let out = run(&["check", &path, "--format", "json"]);
+    // the BOM and 14 + 27 + 30 + 2 + 5 characters of 2 bytes before the line
+    assert!(out.stdout.contains("{\"line\": 5, \"byte_offset\": 158,"), "{}", out.stdout);
+    // 19 characters of line 5 and 😀 with CRLF
+    assert!(out.stdout.contains("{\"line\": 7, \"byte_offset\": 204,"), "{}", out.stdout);
+}
+
+#[test]
+fn same_languages() {
+    let dir = temp_dir("same-languages");
+    let path = write_dict(&dir.join("dict.dsl"), "#NAME \"Test\"\r\n#INDEX_LANGUAGE \"English\"\r\n#CONTENTS_LANGUAGE \"English\"\r\n\r\ncat\r\n\t[m1]a pet[/m]\r\n");
+    let out = run(&["check", &path]);
+    assert_eq!(out.stdout.lines().skip(1).collect::<Vec<_>>(), ["   2.warning: index and contents languages are the same (English) ==> #CONTENTS_LANGUAGE \"English\""]);
+    let out = run(&["check", &path, "--allow-monolingual"]);
+    assert_eq!(out.stdout.lines().count(), 1, "{}", out.stdout);
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}cat\r\n\t[m1]кошка[/m]\r\n", HEADER));
+    assert_eq!(run(&["check", &path]).stdout.lines().count(), 1);
+}
+
+#[test]
+fn escape_headwords() {
+    let dir = temp_dir("escape-headwords");
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}a [b]\r\n\t[m1]x[/m]\r\n{{to }}go\r\n\t[m1]y[/m]\r\n", HEADER));
+    let out = run(&["check", &path]);
+    assert_eq!(out.stdout.lines().skip(1).take(2).collect::<Vec<_>>(), ["   4.unescaped '[' in headword at column 3 ==> a [b]", "   4.unescaped ']' in headword at column 5 ==> a [b]"]);
+    let fixed = dir.join("fixed.dsl");
+    run(&["fix-tags", &path, fixed.to_str().unwrap(), "--escape-headwords"]);
+    assert_eq!(decode(&fs::read(&fixed).unwrap()).lines().nth(4), Some("a \\[b\\]"));
+    assert_eq!(run(&["check", fixed.to_str().unwrap()]).stdout.lines().count(), 1);
+}
+
+#[test]
+fn context_lines() {
+    let dir = temp_dir("context");
+    let cards = "cat\r\n\t[m1][x]a[/m]\r\ndog\r\n\t[m1][b]b[/b][/m]\r\nfox\r\n\t[m1][b]c[/b][/m]\r\nowl\r\n\t[m1][b]d[/b][/m]\r\nbee\r\n\t[m1][y]e[/m]\r\n";
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}{}", HEADER, cards));
+    let out = run(&["check", &path, "--context", "1"]);
+    let expected = ["   4-cat", "   5.unknown tag '[x]' ==> \t[m1][x]a[/m]", "   6-dog", "--", "  12-bee", "  13.unknown tag '[y]' ==> \t[m1][y]e[/m]"];
+    assert_eq!(out.stdout.lines().skip(1).collect::<Vec<_>>(), expected);
+    // contexts that meet are printed as one group
+    let out = run(&["check", &path, "--context", "4"]);
+    let lines: Vec<&str> = out.stdout.lines().skip(1).collect();
+    assert_eq!(lines.len(), 13, "{}", out.stdout);
+    assert!(!lines.contains(&"--"), "{}", out.stdout);
+}
+
+#[test]
+fn require_translation() {
+    let dir = temp_dir("require-translation");
+    let cards = "cat\r\n\t[m1][trn]кошка[/trn][/m]\r\ndog\r\n\t[m1]собака[/m]\r\nfox\r\n\t[m1][com]a pet[/com][/m]\r\n\t[m2][ex]a red fox[/ex][/m]\r\n";
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}{}", HEADER, cards));
+    let out = run(&["check", &path, "--require-translation"]);
+    assert_eq!(out.stdout.lines().skip(1).collect::<Vec<_>>(), ["   8.warning: card has no translation ==> fox"]);
+    assert_eq!(run(&["check", &path]).stdout.lines().count(), 1);
+}
+
+#[test]
+fn near_duplicates() {
+    let dir = temp_dir("near-dup");
+    let cards = "receive\r\n\t[m1][trn]получать[/trn][/m]\r\nrecieve\r\n\t[m1][trn]получать[/trn][/m]\r\nremove\r\n\t[m1][trn]удалять[/trn][/m]\r\n";
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}{}", HEADER, cards));
+    let out = run(&["check", &path, "--near-dup"]);
+    assert_eq!(out.stdout.lines().skip(1).collect::<Vec<_>>(), ["   6.warning: possible duplicate of the keyword at 4"]);
+    assert_eq!(run(&["check", &path, "--near-dup-distance", "0"]).stdout.lines().count(), 1);
+    assert_eq!(run(&["check", &path]).stdout.lines().count(), 1);
+}
+
+#[test]
+fn list_tags() {
+    let out = run(&["list-tags", "--allow-tag", "sup"]);
+    assert_eq!(out.code, 0);
+    let lines: Vec<&str> = out.stdout.lines().collect();
+    assert!(lines.contains(&"[br]     self-closing"), "{}", out.stdout);
+    assert!(lines.contains(&"[m1]     paired, closed with [/m]"), "{}", out.stdout);
+    assert!(lines.contains(&"[lang]   paired, closed with [/lang], takes attributes"), "{}", out.stdout);
+    assert_eq!(lines.last(), Some(&"[sup]    paired, closed with [/sup], allowed with --allow-tag"));
+    let out = run(&["list-tags", "--format", "json", "--allow-tag", "sup"]);
+    let value = dsldoc::json::parse(&out.stdout).unwrap();
+    let tags = value.as_array().unwrap();
+    assert_eq!(tags.len(), dsldoc::TAGS.len() + 1);
+    assert_eq!(tags[tags.len() - 1].get("name").and_then(|n| n.as_str()), Some("sup"));
+}
+
+#[test]
+fn wrong_script() {
+    let dir = temp_dir("wrong-script");
+    let latin = "cat\r\n\t[m1][trn]a pet[/trn][/m]\r\ndog\r\n\t[m1][trn]a friend[/trn][/m]\r\n";
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}{}", HEADER, latin));
+    let out = run(&["check", &path, "--check-script"]);
+    assert_eq!(out.stdout.lines().skip(1).collect::<Vec<_>>(), ["   2.warning: 100% of text is not in Cyrillic script expected for Russian (mostly Latin)"]);
+    assert_eq!(run(&["check", &path]).stdout.lines().count(), 1);
+    // comments and examples are not sampled
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}cat\r\n\t[m1][trn]кошка[/trn] [com]a pet[/com][/m]\r\n", HEADER));
+    assert_eq!(run(&["check", &path, "--check-script"]).stdout.lines().count(), 1);
+}
+
+#[test]
+fn errors_only() {
+    let dir = temp_dir("errors-only");
+    let header = HEADER.replace("\"Russian\"", "\"English\"");
+    let path = write_dict(&dir.join("mixed.dsl"), &format!("{}cat\r\n\t[m1][x]a[/m]\r\n", header));
+    let out = run(&["check", &path]);
+    assert_eq!((out.code, out.stdout.lines().count()), (1, 3), "{}", out.stdout);
+    let out = run(&["check", &path, "--errors-only"]);
+    assert_eq!(out.code, 1);
+    assert_eq!(out.stdout.lines().skip(1).collect::<Vec<_>>(), ["   5.unknown tag '[x]' ==> \t[m1][x]a[/m]"]);
+    // warnings alone do not fail the check
+    let path = write_dict(&dir.join("warnings.dsl"), &format!("{}cat\r\n\t[m1]a[/m]\r\n", header));
+    let out = run(&["check", &path]);
+    assert_eq!((out.code, out.stdout.lines().count()), (0, 2), "{}", out.stdout);
+    let out = run(&["check", &path, "--errors-only"]);
+    assert_eq!((out.code, out.stdout.lines().count()), (0, 1), "{}", out.stdout);
+}
+
+#[test]
+fn optional_parts_of_headwords() {
+    let dir = temp_dir("optional");
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}word(s\r\n\t[m1]слово[/m]\r\n", HEADER));
+    let out = run(&["check", &path]);
+    assert_eq!((out.code, out.stdout.lines().skip(1).collect::<Vec<_>>()), (1, vec!["   4.unbalanced '(' in headword at column 5 ==> word(s"]));
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}colo(u)r\r\n\t[m1]цвет[/m]\r\n", HEADER));
+    let out = run(&["check", &path, "--expand-optional"]);
+    assert_eq!((out.code, out.stdout.lines().skip(1).collect::<Vec<_>>()), (0, vec!["   4.colo(u)r indexed as 'colour' and 'color'"]));
+}
+
+#[test]
+fn check_schema() {
+    let schema = fixture("style.json");
+    let out = run(&["check", &fixture("conforming.dsl"), "--schema", &schema]);
+    assert_eq!((out.code, out.stdout.lines().count()), (0, 1), "{}", out.stdout);
+    let out = run(&["check", &fixture("nonconforming.dsl"), "--schema", &schema]);
+    assert_eq!(out.code, 1);
+    assert_eq!(out.stdout.lines().skip(1).collect::<Vec<_>>(), [
+        "   4.schema violation: tags must go in order [p], [trn], found [trn], [p] ==> cat",
+        "   6.schema violation: [p] is required ==> dog",
+    ]);
+}
+
+#[test]
+fn reports_of_a_line_are_limited() {
+    let dir = temp_dir("line-reports");
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}{}\r\n\t[m1]x[/m]\r\n", HEADER, "}".repeat(100_000)));
+    let out = run(&["check", &path]);
+    let lines: Vec<&str> = out.stdout.lines().skip(1).collect();
+    assert_eq!(lines.len(), 11);
+    assert_eq!(lines[10], "   4.99990 more special characters in headword");
+}
+
+#[test]
+fn strip_bom() {
+    let dir = temp_dir("strip-bom");
+    let path = write_dict(&dir.join("joined.dsl"), &format!("{}cat\r\n\t[m1][trn]\u{feff}кошка[/trn][/m]\r\n", HEADER));
+    let out = run(&["check", &path]);
+    assert_eq!(out.code, 1);
+    assert!(out.stdout.contains("   5.byte order mark inside the text at column 11"), "{}", out.stdout);
+    let fixed = dir.join("fixed.dsl");
+    let fixed = fixed.to_str().unwrap();
+    assert_eq!(run(&["fix-tags", &path, fixed, "--strip-bom"]).code, 0);
+    let text = decode(&fs::read(fixed).unwrap());
+    assert!(text.ends_with("cat\r\n\t[m1][trn]кошка[/trn][/m]\r\n"), "{:?}", text);
+    assert_eq!(run(&["check", fixed]).code, 0);
+}
+
+#[test]
+fn missing_media() {
+    let dir = temp_dir("media");
+    fs::write(dir.join("cat.wav"), b"RIFF").unwrap();
+    let cards = "cat\r\n\t[m1][s]cat.wav[/s] кошка[/m]\r\ndog\r\n\t[m1][s]dog.wav[/s] [video]dog.mp4[/video][/m]\r\n";
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}{}", HEADER, cards));
+    let out = run(&["media", &path]);
+    assert_eq!(out.code, 1);
+    assert_eq!(out.stdout.lines().collect::<Vec<_>>(), [
+        format!("media --> {}", path),
+        String::from("   7.media file 'dog.wav' not found"),
+        String::from("   7.media file 'dog.mp4' not found"),
+    ]);
+    fs::write(dir.join("dog.wav"), b"RIFF").unwrap();
+    fs::write(dir.join("dog.mp4"), b"").unwrap();
+    assert_eq!(run(&["media", &path]).code, 0);
+    // files are looked up in the given directory
+    let out = run(&["media", &path, temp_dir("no-media").to_str().unwrap()]);
+    assert_eq!((out.code, out.stdout.lines().count()), (1, 4), "{}", out.stdout);
+}
+
+#[test]
+fn summary_only() {
+    let dir = temp_dir("summary-only");
+    let cards = "cat\r\n\t[m1][x]a[/m]\r\ndog\r\n\t[m1][x]b[/m]\r\n\t[m1][y]b[/m]\r\n\t[m1][b]c[/m]\r\nfox\r\n\t[m1]c[/m]\r\n";
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}{}", HEADER, cards));
+    let out = run(&["check", &path, "--summary-only"]);
+    assert_eq!(out.code, 1);
+    let cat = "   4.cat: 1 errors, 0 warnings (unknown-tag: 1)";
+    let dog = "   6.dog: 3 errors, 0 warnings (unknown-tag: 2, mismatched-tag: 1)";
+    assert_eq!(out.stdout.lines().skip(1).collect::<Vec<_>>(), [cat, dog]);
+    let out = run(&["check", &path, "--summary-only", "--sort-by-severity"]);
+    assert_eq!(out.stdout.lines().skip(1).collect::<Vec<_>>(), [dog, cat]);
+}
+
+#[test]
+fn check_directory() {
+    let dir = temp_dir("directory");
+    fs::create_dir_all(dir.join("sub/old")).unwrap();
+    let good = write_dict(&dir.join("good.dsl"), &format!("{}cat\r\n\t[m1]кошка[/m]\r\n", HEADER));
+    let bad = write_dict(&dir.join("sub/bad.dsl"), &format!("{}cat\r\n\t[m1][x]кошка[/m]\r\n", HEADER));
+    write_dict(&dir.join("sub/old/broken.dsl"), &format!("{}cat\r\n\t[m1][y]кошка[/m]\r\n", HEADER));
+    fs::write(dir.join("notes.txt"), "not a dictionary").unwrap();
+    let out = run(&["check", dir.to_str().unwrap()]);
+    assert_eq!(out.code, 1);
+    assert!(out.stdout.contains(&format!("\n=== {}\n\n=== ", good)), "{}", out.stdout);
+    assert!(out.stdout.contains(&format!("\n=== {}\n   5.unknown tag '[x]'", bad)), "{}", out.stdout);
+    assert!(out.stdout.ends_with("\n3 files checked, 1 passed, 2 failed\n"), "{}", out.stdout);
+    // a pattern matches a file name or a path relative to the directory
+    let out = run(&["check", dir.to_str().unwrap(), "--exclude", "old"]);
+    assert!(out.stdout.ends_with("\n2 files checked, 1 passed, 1 failed\n"), "{}", out.stdout);
+    let out = run(&["check", dir.to_str().unwrap(), "--exclude", "sub/*"]);
+    assert_eq!(out.code, 0);
+    assert!(out.stdout.ends_with("\n1 files checked, 1 passed, 0 failed\n"), "{}", out.stdout);
+}
+
+#[test]
+fn replace_tag_tabs() {
+    let dir = temp_dir("tag-tabs");
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}cat\r\n\t[m1][com]a\tpet[/com] [trn]кошка[/trn][/m]\r\n", HEADER));
+    let fixed = dir.join("fixed.dsl");
+    let fixed = fixed.to_str().unwrap();
+    let out = run(&["check", &path]);
+    assert_eq!(out.stdout.lines().skip(1).collect::<Vec<_>>(), ["   5.warning: tab inside tag at column 12 ==> \t[m1][com]a\tpet[/com] [trn]кошка[/trn][/m]"]);
+    assert_eq!(run(&["fix-tags", &path, fixed, "--replace-tag-tabs"]).code, 0);
+    let text = decode(&fs::read(fixed).unwrap());
+    assert!(text.ends_with("cat\r\n\t[m1][com]a pet[/com] [trn]кошка[/trn][/m]\r\n"), "{:?}", text);
+    assert_eq!(run(&["check", fixed]).stdout.lines().count(), 1);
+}
+
+#[test]
+fn unexpected_eof() {
+    let dir = temp_dir("eof");
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}cat\r\n\t[m1]кошка[/m]\r\ndog\r\n", HEADER));
+    let out = run(&["check", &path]);
+    assert_eq!((out.code, out.stdout.lines().skip(1).collect::<Vec<_>>()), (1, vec!["   6.unexpected end of file after Key"]));
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}cat\r\n\t[m1]кошка[/m]\r\n", HEADER));
+    assert_eq!(run(&["check", &path]).code, 0);
+}
+
+#[test]
+fn no_duplicate_check() {
+    let dir = temp_dir("no-duplicate-check");
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}cat\r\n\t[m1]кошка[/m]\r\ncat\r\n\t[m1]кот[/m]\r\n", HEADER));
+    let out = run(&["check", &path]);
+    assert_eq!((out.code, out.stdout.lines().count()), (1, 2), "{}", out.stdout);
+    let out = run(&["check", &path, "--no-duplicate-check"]);
+    assert_eq!((out.code, out.stdout.lines().count()), (0, 1), "{}", out.stdout);
+}
+
+#[test]
+fn fix_indent() {
+    let dir = temp_dir("fix-indent");
+    let cards = "cat\r\n\t\t\t[m1]кошка[/m]\r\n\t[m2]a pet[/m]\r\n";
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}{}", HEADER, cards));
+    assert_eq!(run(&["check", &path, "--check-indent"]).stdout.lines().count(), 3);
+    let fixed = dir.join("fixed.dsl");
+    let fixed = fixed.to_str().unwrap();
+    let out = run(&["fix-indent", &path, fixed]);
+    assert_eq!(out.code, 0);
+    assert_eq!(out.stdout.lines().nth(1), Some("no consistent TABs per margin level found, using 1"));
+    let text = decode(&fs::read(fixed).unwrap());
+    assert!(text.ends_with("cat\r\n\t[m1]кошка[/m]\r\n\t\t[m2]a pet[/m]\r\n"), "{:?}", text);
+    let out = run(&["check", fixed, "--check-indent"]);
+    assert_eq!((out.code, out.stdout.lines().count()), (0, 1), "{}", out.stdout);
+}
+
+#[test]
+fn check_glob() {
+    let dir = temp_dir("glob");
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    let good = write_dict(&dir.join("good.dsl"), &format!("{}cat\r\n\t[m1]кошка[/m]\r\n", HEADER));
+    let bad = write_dict(&dir.join("sub/bad.dsl"), &format!("{}cat\r\n\t[m1][x]кошка[/m]\r\n", HEADER));
+    let root = dir.to_str().unwrap();
+    let out = run(&["check", "--glob", &format!("{}/*.dsl", root)]);
+    assert_eq!(out.code, 0, "{}", out.stdout);
+    assert!(out.stdout.contains(&format!("=== {}\n", good)), "{}", out.stdout);
+    assert!(out.stdout.ends_with("\n1 files checked, 1 passed, 0 failed\n"), "{}", out.stdout);
+    let out = run(&["check", "--glob", &format!("{}/**/*.dsl", root)]);
+    assert_eq!(out.code, 1);
+    assert!(out.stdout.contains(&format!("=== {}\n   5.unknown tag '[x]'", bad)), "{}", out.stdout);
+    let out = run(&["check", "--glob", &format!("{}/*.txt", root)]);
+    assert_eq!(out.code, 1);
+    assert_eq!(out.stdout, format!("no files match '{}/*.txt'\n", root));
+}
+
+#[test]
+fn tag_cooccurrence() {
+    let dir = temp_dir("tag-cooccurrence");
+    let cards = "cat\r\n\t[m1][p]n[/p] [trn]кошка[/trn][/m]\r\n\t[m2][ex]a cat[/ex][/m]\r\ndog\r\n\t[m1][p]n[/p] [trn]собака[/trn][/m]\r\nrun\r\n\t[m1][p]v[/p] [trn]бежать[/trn] [com]fast[/com][/m]\r\n";
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}{}", HEADER, cards));
+    let out = run(&["tag-cooccurrence", &path]);
+    assert_eq!(out.code, 0);
+    let lines: Vec<&str> = out.stdout.lines().collect();
+    assert_eq!(lines.len(), 14);
+    assert_eq!(lines[..5], ["tag1,tag2,cards", "m1,p,3", "m1,trn,3", "p,trn,3", "com,m1,1"]);
+    let out = run(&["tag-cooccurrence", &path, "--top", "2", "--format", "json"]);
+    assert_eq!(out.stdout, "[\n  {\"tag1\": \"m1\", \"tag2\": \"p\", \"cards\": 3},\n  {\"tag1\": \"m1\", \"tag2\": \"trn\", \"cards\": 3}\n]\n");
+}
+
+#[test]
+fn fix_indent_of_two_tabs_per_level() {
+    let dir = temp_dir("fix-indent-2");
+    // nine lines of ten follow the convention
+    let cards = "cat\r\n\t\t[m1]кошка[/m]\r\n\t\t\t\t[m2]a pet[/m]\r\n".repeat(4) + "dog\r\n\t\t[m1]собака[/m]\r\n\t\t[m2]a friend[/m]\r\n";
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}{}", HEADER, cards));
+    let fixed = dir.join("fixed.dsl");
+    let fixed = fixed.to_str().unwrap();
+    assert_eq!(run(&["fix-indent", &path, fixed]).stdout.lines().nth(1), Some("detected 2 TAB(s) per margin level"));
+    let text = decode(&fs::read(fixed).unwrap());
+    assert!(text.ends_with("dog\r\n\t\t[m1]собака[/m]\r\n\t\t\t\t[m2]a friend[/m]\r\n"), "{:?}", text);
+}
+
+#[test]
+fn only_and_ignore() {
+    let dir = temp_dir("only");
+    let cards = "cat\r\n\t[m1][x]кошка[/m]\r\ncat\r\n\t[m1]a [b]cat[/m]\r\ndog\r\n\t[m1][y]собака[/m]\r\n";
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}{}", HEADER, cards));
+    let kinds = |args: &[&str]| -> Vec<String> {
+        let mut all = vec!["check", path.as_str(), "--format", "json"];
+        all.extend(args);
+        let v = dsldoc::json::parse(&run(&all).stdout).unwrap();
+        v.as_array().unwrap().iter().map(|d| d.get("kind").and_then(|k| k.as_str()).unwrap().to_string()).collect()
+    };
+    assert_eq!(kinds(&[]), ["unknown-tag", "duplicate", "mismatched-tag", "unknown-tag"]);
+    assert_eq!(kinds(&["--only", "unknown-tag"]), ["unknown-tag", "unknown-tag"]);
+    assert_eq!(kinds(&["--only", "unknown-tag", "--only", "duplicate"]), ["unknown-tag", "duplicate", "unknown-tag"]);
+    // --only wins, then --ignore takes kinds away
+    assert_eq!(kinds(&["--only", "unknown-tag", "--only", "duplicate", "--ignore", "unknown-tag"]), ["duplicate"]);
+    assert_eq!(kinds(&["--ignore", "unknown-tag"]), ["duplicate", "mismatched-tag"]);
+    let out = run(&["check", &path, "--only", "duplicate"]);
+    assert_eq!(out.code, 1, "{}", out.stdout);
+    let out = run(&["check", &path, "--only", "same-languages"]);
+    assert_eq!((out.code, out.stdout.lines().count()), (0, 1), "{}", out.stdout);
+}
+
+#[test]
+fn error_limit_after_filters() {
+    let dir = temp_dir("only-max");
+    let cards = "cat\r\n\t[m1][x]кошка[/m]\r\ncat\r\n\t[m1]кошка[/m]\r\n";
+    let path = write_dict(&dir.join("d.dsl"), &format!("{}{}", HEADER, cards));
+    // the unknown tag on line 5 comes first, but it is not counted
+    let out = run(&["check", &path, "--only", "duplicate", "--max-errors", "1"]);
+    assert_eq!(out.code, 1, "{}", out.stdout);
+    let lines: Vec<&str> = out.stdout.lines().skip(1).collect();
+    assert_eq!(lines.len(), 1, "{}", out.stdout);
+    assert!(lines[0].starts_with("   6."), "{}", out.stdout);
+}
+
+#[test]
+fn head() {
+    let small = fixture("small.dsl");
+    let out = run(&["head", &small, "--count", "1"]);
+    assert_eq!(out.code, 0);
+    assert_eq!(out.stdout, "#NAME \"Test\"\n#INDEX_LANGUAGE \"English\"\n#CONTENTS_LANGUAGE \"Russian\"\n\ncat\n  кошка\n    a black cat\n");
+    // 5 cards by default, compressed files are read too
+    let out = run(&["head", &fixture("large.dsl.dz")]);
+    assert_eq!(out.stdout.split("\n\n").count(), 6, "{}", out.stdout);
+    assert_eq!(run(&["head", &small]).stdout, run(&["head", &fixture("small.dsl.dz")]).stdout);
+}
+
+#[test]
+fn report_file() {
+    let dir = temp_dir("report-file");
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}cat\r\n\t[m1][x]кошка[/m]\r\n", HEADER));
+    let report = dir.join("report.json");
+    let report = report.to_str().unwrap();
+    fs::write(report, "old contents").unwrap();
+    let out = run(&["check", &path, "--format", "json", "--report-file", report]);
+    assert_eq!(out.code, 1);
+    assert_eq!(fs::read_to_string(report).unwrap(), out.stdout);
+    let out = run(&["check", &path, "--report-file", report]);
+    assert_eq!(out.code, 1);
+    // the text report has no command header
+    assert_eq!(fs::read_to_string(report).unwrap(), out.stdout.lines().skip(1).map(|l| format!("{}\n", l)).collect::<String>());
+}
+
+#[test]
+fn follow_includes() {
+    let dir = temp_dir("includes");
+    let header = HEADER.trim_end();
+    let main = write_dict(&dir.join("main.dsl"), &format!("{}\r\n#INCLUDE \"other.dsl\"\r\n\r\ncat\r\n\t[m1]кошка[/m]\r\ndog\r\n\t[m1]собака[/m]\r\n", header));
+    write_dict(&dir.join("other.dsl"), &format!("{}dog\r\n\t[m1]пёс[/m]\r\n", HEADER));
+    let out = run(&["check", &main]);
+    assert_eq!((out.code, out.stdout.lines().count()), (0, 1), "{}", out.stdout);
+    let out = run(&["check", &main, "--follow-includes"]);
+    assert_eq!(out.code, 1);
+    assert_eq!(out.stdout.lines().skip(1).collect::<Vec<_>>(), ["   7.dog already exists in other.dsl"]);
+    fs::remove_file(dir.join("other.dsl")).unwrap();
+    let out = run(&["check", &main, "--follow-includes"]);
+    assert_eq!(out.code, 1);
+    assert_eq!(out.stdout.lines().skip(1).collect::<Vec<_>>(), ["   3.included file 'other.dsl' not found ==> #INCLUDE \"other.dsl\""]);
+    // the missing file counts as an error for --max-errors
+    let out = run(&["check", &main, "--follow-includes", "--max-errors", "1", "--only", "missing-include"]);
+    assert_eq!(out.stdout.lines().skip(1).collect::<Vec<_>>(), ["   3.included file 'other.dsl' not found ==> #INCLUDE \"other.dsl\""]);
+}
+
+#[test]
+fn fail_on() {
+    let dir = temp_dir("fail-on");
+    // the same index and contents languages are only a warning
+    let header = HEADER.replace("\"Russian\"", "\"English\"");
+    let path = write_dict(&dir.join("warnings.dsl"), &format!("{}cat\r\n\t[m1]a[/m]\r\n", header));
+    for (level, code) in [(None, 0), (Some("error"), 0), (Some("warning"), 1), (Some("never"), 0)] {
+        let mut args = vec!["check", path.as_str()];
+        args.extend(level.iter().flat_map(|l| ["--fail-on", l]));
+        let out = run(&args);
+        assert_eq!((out.code, out.stdout.lines().count()), (code, 2), "{:?}: {}", level, out.stdout);
+    }
+    let path = write_dict(&dir.join("errors.dsl"), &format!("{}cat\r\n\t[m1][x]a[/m]\r\n", HEADER));
+    assert_eq!(run(&["check", &path, "--fail-on", "warning"]).code, 1);
+    assert_eq!(run(&["check", &path, "--fail-on", "never"]).code, 0);
+    let out = run(&["check", &path, "--fail-on", "sometimes"]);
+    assert!(out.stdout.contains("invalid --fail-on level: sometimes"), "{}", out.stdout);
+}
+
+#[test]
+fn fix_all() {
+    let dir = temp_dir("fix-all");
+    let messy = write_dict(&dir.join("messy.dsl"), &format!("{}cat\r\n\t[m1][trn]кош\u{feff}ка[/trn][/m]\r\n\t[m2][ex]a\u{7} cat[/ex][/m]  \r\ndog\r\n\t[m1][x]собака[/m]\r\n\t[m1][trn]пёс[/trn][/m]\r\n", HEADER));
+    let fixed = dir.join("fixed.dsl");
+    let out = run(&["fix-all", &messy, fixed.to_str().unwrap()]);
+    assert_eq!(out.code, 0, "{}", out.stdout);
+    assert_eq!(out.stdout.lines().skip(1).collect::<Vec<_>>(), [
+        "byte order marks: 1 line(s) fixed",
+        "control characters: 1 line(s) fixed",
+        "trailing whitespace: 1 line(s) fixed",
+        "unknown tags: 1 line(s) fixed",
+        "indentation: 1 line(s) fixed",
+    ]);
+    let text = decode(&fs::read(&fixed).unwrap());
+    assert_eq!(text.lines().skip(4).map(|l| l.trim_end_matches('\r')).collect::<Vec<_>>(), [
+        "cat", "\t[m1][trn]кошка[/trn][/m]", "\t\t[m2][ex]a cat[/ex][/m]", "dog", "\t[m1]\\[x\\]собака[/m]", "\t[m1][trn]пёс[/trn][/m]",
+    ]);
+    let out = run(&["check", fixed.to_str().unwrap()]);
+    assert_eq!((out.code, out.stdout.lines().count()), (0, 1), "{}", out.stdout);
+    // mismatched tags cannot be fixed, nothing is written
+    let broken = write_dict(&dir.join("broken.dsl"), &format!("{}cat \r\n\t[m1][b]кошка[/i][/m]\r\n", HEADER));
+    let not_written = dir.join("not-written.dsl");
+    let out = run(&["fix-all", &broken, not_written.to_str().unwrap()]);
+    assert_eq!(out.code, 1, "{}", out.stdout);
+    assert!(out.stdout.contains("is not written: the errors above cannot be fixed automatically"), "{}", out.stdout);
+    assert!(!not_written.exists());
+}
+
+#[test]
+fn video_files() {
+    let dir = temp_dir("video");
+    fs::write(dir.join("clip.mp4"), b"").unwrap();
+    fs::write(dir.join("clip.flv"), b"").unwrap();
+    // the contents of [video] is a path, brackets in it are not tags
+    let cards = "cat\r\n\t[m1][video]clip.mp4[/video] кошка[/m]\r\ndog\r\n\t[m1][video]clip.flv[/video][/m]\r\nfox\r\n\t[m1][video]fox [1].mp4[/video][/m]\r\n";
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}{}", HEADER, cards));
+    let out = run(&["check", &path]);
+    assert_eq!((out.code, out.stdout.lines().count()), (0, 1), "{}", out.stdout);
+    let out = run(&["media", &path]);
+    assert_eq!(out.code, 1);
+    assert_eq!(out.stdout.lines().skip(1).collect::<Vec<_>>(), ["   9.media file 'fox [1].mp4' not found"]);
+    let out = run(&["media", &path, "--strict-media"]);
+    assert_eq!(out.stdout.lines().skip(1).collect::<Vec<_>>(), [
+        "   7.warning: video file 'clip.flv' has unsupported format",
+        "   9.media file 'fox [1].mp4' not found",
+    ]);
+}
+
+#[test]
+fn sort_by_kind() {
+    let dir = temp_dir("sort-by-kind");
+    let cards = "cat\r\n\t[m1][x]a[/m]\r\ndog\r\n\t[m1][b]b[/i][/m]\r\nfox\r\n\t[m1][y]c[/m]\r\nowl\r\n\t[m1][i]d[/b][/m]\r\n";
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}{}", HEADER, cards));
+    let lines = |out: Output| out.stdout.lines().skip(1).map(|l| l[..4].trim().parse::<usize>().unwrap()).collect::<Vec<_>>();
+    assert_eq!(lines(run(&["check", &path])), [5, 7, 9, 11]);
+    assert_eq!(lines(run(&["check", &path, "--sort-by", "line"])), [5, 7, 9, 11]);
+    assert_eq!(lines(run(&["check", &path, "--sort-by", "kind"])), [7, 11, 5, 9]);
+    let out = run(&["check", &path, "--sort-by", "kind", "--context", "1"]);
+    let problems: Vec<&str> = out.stdout.lines().skip(1).filter(|l| l.get(4..5) == Some(".")).map(|l| &l[..4]).collect();
+    assert_eq!(problems, ["   7", "  11", "   5", "   9"], "{}", out.stdout);
+}
+
+#[test]
+fn index() {
+    let dir = temp_dir("index");
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}Straße\r\n\t[m1]a[/m]\r\nİzmir\r\n\t[m1]b[/m]\r\n", HEADER));
+    let out = run(&["index", &path]);
+    assert_eq!(out.code, 0);
+    assert_eq!(out.stdout.lines().collect::<Vec<_>>(), ["strasse\tStraße", "izmir\tİzmir"]);
+    let out = run(&["index", &path, "--format", "json"]);
+    assert!(out.stdout.contains("{\"key\": \"strasse\", \"headword\": \"Straße\"},"), "{}", out.stdout);
+}
+
+#[test]
+fn sort_preserve_comments() {
+    let dir = temp_dir("sort-comments");
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}## about zebra\r\nzebra\r\n\t[m1]зебра[/m]\r\n## about ant\r\nant\r\n\t[m1]муравей[/m]\r\n", HEADER));
+    let sorted = dir.join("sorted.dsl");
+    let cards = |args: &[&str]| {
+        let out = run(args);
+        assert_eq!(out.code, 0, "{}", out.stdout);
+        decode(&fs::read(&sorted).unwrap()).lines().skip(4).map(|l| l.trim_end_matches('\r').to_string()).collect::<Vec<_>>()
+    };
+    assert_eq!(cards(&["sort", &path, sorted.to_str().unwrap(), "--preserve-comments"]),
+        ["## about ant", "ant", "\t[m1]муравей[/m]", "## about zebra", "zebra", "\t[m1]зебра[/m]"]);
+    // without the option a comment goes with the card above it
+    assert_eq!(cards(&["sort", &path, sorted.to_str().unwrap()]),
+        ["## about zebra", "ant", "\t[m1]муравей[/m]", "zebra", "\t[m1]зебра[/m]", "## about ant"]);
+}
+
+#[test]
+fn wrong_byte_order() {
+    let dir = temp_dir("byte-order");
+    let text = format!("{}cat\r\n\t[m1]кошка[/m]\r\n", HEADER);
+    let path = dir.join("be.dsl");
+    fs::write(&path, text.encode_utf16().flat_map(|c| c.to_be_bytes()).collect::<Vec<u8>>()).unwrap();
+    let path = path.to_str().unwrap();
+    let out = run(&["check", path]);
+    assert_eq!(out.code, 1);
+    assert_eq!(out.stdout.lines().skip(1).collect::<Vec<_>>(), ["   0.the file looks like UTF-16BE without a BOM, pass --input-encoding utf16be"]);
+    let out = run(&["check", path, "--input-encoding", "utf16be"]);
+    assert_eq!((out.code, out.stdout.lines().count()), (0, 1), "{}", out.stdout);
+}
+
+#[test]
+fn extract_lang() {
+    let dir = temp_dir("extract-lang");
+    let cards = "cat\r\n\t[m1]кошка[/m]\r\n\t[m1][lang id=1033]cat[/lang] [lang id=1031]Katze[/lang][/m]\r\n\t[m1][lang id=1033]a cat[/lang][/m]\r\ndog\r\n\t[m1][lang id=1033]dog[/lang][/m]\r\n";
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}{}", HEADER, cards));
+    let out_path = dir.join("german.dsl");
+    let out = run(&["extract-lang", &path, out_path.to_str().unwrap(), "--id", "1031"]);
+    assert_eq!(out.stdout.lines().last(), Some("1 card(s) with language 1031 extracted"), "{}", out.stdout);
+    let text = decode(&fs::read(&out_path).unwrap());
+    assert_eq!(text.lines().skip(4).map(|l| l.trim_end_matches('\r')).collect::<Vec<_>>(),
+        ["cat", "\t[m1]кошка[/m]", "\t[m1] [lang id=1031]Katze[/lang][/m]"]);
+    let out = run(&["check", out_path.to_str().unwrap()]);
+    assert_eq!((out.code, out.stdout.lines().count()), (0, 1), "{}", out.stdout);
+    let out = run(&["extract-lang", &path, out_path.to_str().unwrap()]);
+    assert!(out.stdout.contains("language id is undefined, pass --id N"), "{}", out.stdout);
+}
+
+#[test]
+fn json_indent_fields() {
+    let dir = temp_dir("json-indent");
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}cat\r\n\t[m1]кошка[/m]\r\n\t[m2]a pet[/m]\r\n", HEADER));
+    let out = run(&["check", &path, "--check-indent", "--format", "json"]);
+    assert_eq!(out.code, 0, "{}", out.stdout);
+    let lines: Vec<&str> = out.stdout.lines().collect();
+    assert_eq!(lines.len(), 3, "{}", out.stdout);
+    assert!(lines[1].contains("\"kind\": \"wrong-indent\""), "{}", lines[1]);
+    assert!(lines[1].ends_with(", \"expected_tabs\": 2, \"actual_tabs\": 1, \"margin_level\": 2}"), "{}", lines[1]);
+}
+
+#[test]
+fn trace() {
+    let dir = temp_dir("trace");
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}cat\r\n## note\r\n\t[m1]кошка[/m]\r\ndog\r\n\r\n", HEADER));
+    let out = run(&["check", &path, "--trace"]);
+    assert_eq!(out.code, 1);
+    assert_eq!(out.stderr.lines().collect::<Vec<_>>(), [
+        "   0.Name after Begin, accepted",
+        "   1.Index after Name, accepted",
+        "   2.Lang after Index, accepted",
+        "   3.EmptyLine after Lang, accepted",
+        "   4.Key after EmptyLine, accepted",
+        "   5.EditorComment, skipped",
+        "   6.M1 after Key, accepted",
+        "   7.Key after M1, accepted",
+        "   8.EmptyLine after Key, rejected",
+    ]);
+    // the trace does not go to the report
+    assert!(!out.stdout.contains("accepted"), "{}", out.stdout);
+    assert_eq!(run(&["check", &path]).stderr, "");
+}
+
+#[test]
+fn lenient() {
+    let dir = temp_dir("lenient");
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}cat\r\n\t[m1]кошка[/m]\r\n\r\ndog\r\n\t[m1]собака[/m]\r\n", HEADER));
+    assert_eq!(run(&["check", &path]).code, 1);
+    let out = run(&["check", &path, "--lenient"]);
+    assert_eq!((out.code, out.stdout.lines().count()), (0, 1), "{}", out.stdout);
+}
+
+#[test]
+fn stats() {
+    let dir = temp_dir("stats");
+    let cards = "cat\r\n\t[m1]кошка[/m]\r\ndog\r\n\t[m1][b]собака[/i][/m]\r\n\t[m1][x]пёс[/m]\r\nfox\r\n\t[m1][i]лиса[/b][/m]\r\nowl\r\n\t[m1][b]сова[/b][/m]\r\n";
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}{}", HEADER, cards));
+    let out = run(&["stats", &path]);
+    assert_eq!(out.stdout.lines().collect::<Vec<_>>(), [
+        "cards: 4",
+        "cards with balanced tags: 2",
+        "cards with tag errors: 2",
+        "most common tag error: mismatched-tag (2 line(s))",
+    ]);
+    let out = run(&["stats", &path, "--format", "json"]);
+    assert_eq!(out.stdout.trim_end(), "{\"cards\": 4, \"balanced\": 2, \"unbalanced\": 2, \"most_common_error\": \"mismatched-tag\", \"most_common_count\": 2}");
+    let path = write_dict(&dir.join("clean.dsl"), &format!("{}cat\r\n\t[m1]кошка[/m]\r\n", HEADER));
+    let out = run(&["stats", &path, "--format", "json"]);
+    assert_eq!(out.stdout.trim_end(), "{\"cards\": 1, \"balanced\": 1, \"unbalanced\": 0, \"most_common_error\": null, \"most_common_count\": 0}");
+}
+
+#[test]
+fn body_freq() {
+    let dir = temp_dir("body-freq");
+    let cards = "cat\r\n\t[m1][trn]a small cat[/trn][/m]\r\n\t[m1][ex]A cat, a [b]Cat[/b][/ex][/m]\r\ndog\r\n\t[m1][trn]a dog[/trn][/m]\r\n";
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}{}", HEADER, cards));
+    let out = run(&["body-freq", &path]);
+    assert_eq!(out.stdout.lines().collect::<Vec<_>>(), ["word,count", "a,3", "cat,2", "A,1", "Cat,1", "dog,1", "small,1"]);
+    let out = run(&["body-freq", &path, "--ignore-case", "--min-count", "2"]);
+    assert_eq!(out.stdout.lines().collect::<Vec<_>>(), ["word,count", "a,4", "cat,3"]);
+    let out = run(&["body-freq", &path, "--ignore-case", "--top", "1", "--format", "json"]);
+    assert_eq!(out.stdout.lines().collect::<Vec<_>>(), ["[", "  {\"word\": \"a\", \"count\": 4}", "]"]);
+}
+
+#[test]
+fn retag() {
+    let dir = temp_dir("retag");
+    let cards = "cat\r\n\t[m1][p]n[/p] [i]кошка[/i][/m]\r\ndog\r\n\t[m1][p]n[/p] собака[/m]\r\n\t[m1][p]v[/p] [i]выслеживать[/i][/m]\r\n";
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}{}", HEADER, cards));
+    let renamed = dir.join("renamed.dsl");
+    let out = run(&["retag", &path, renamed.to_str().unwrap(), "--map", "[p]=[pos]", "--map", "i=b", "--allow-tag", "pos"]);
+    assert_eq!(out.code, 0, "{}", out.stdout);
+    assert_eq!(out.stdout.lines().skip(1).collect::<Vec<_>>(), ["[p] -> [pos]: 3 tag(s) renamed", "[i] -> [b]: 2 tag(s) renamed"]);
+    let text = decode(&fs::read(&renamed).unwrap());
+    assert_eq!(text.lines().skip(4).map(|l| l.trim_end_matches('\r')).collect::<Vec<_>>(),
+        ["cat", "\t[m1][pos]n[/pos] [b]кошка[/b][/m]", "dog", "\t[m1][pos]n[/pos] собака[/m]", "\t[m1][pos]v[/pos] [b]выслеживать[/b][/m]"]);
+    let not_written = dir.join("not-written.dsl");
+    let out = run(&["retag", &path, not_written.to_str().unwrap(), "--map", "[p]=[pos]"]);
+    assert_eq!(out.code, 1);
+    assert!(out.stdout.contains("[pos] is not a known tag, pass --allow-tag pos to use it"), "{}", out.stdout);
+    // [/m] closes [m1], but not [b]
+    let out = run(&["retag", &path, not_written.to_str().unwrap(), "--map", "m1=b"]);
+    assert_eq!(out.code, 1);
+    assert!(out.stdout.contains("is not written: renaming breaks the lines above"), "{}", out.stdout);
+    assert!(!not_written.exists());
+}
+
+#[test]
+fn lone_surrogates() {
+    let dir = temp_dir("surrogates");
+    let path = dir.join("dict.dsl");
+    let mut units: Vec<u16> = vec![0xfeff];
+    units.extend(format!("{}cat\r\n\t[m1]ко", HEADER).encode_utf16());
+    units.push(0xdc00);
+    units.extend("шка[/m]\r\n".encode_utf16());
+    fs::write(&path, units.iter().flat_map(|c| c.to_le_bytes()).collect::<Vec<u8>>()).unwrap();
+    let out = run(&["check", path.to_str().unwrap()]);
+    assert_eq!(out.code, 1);
+    assert_eq!(out.stdout.lines().skip(1).collect::<Vec<_>>(),
+        ["   5.invalid UTF-16 surrogate at column 8 is replaced with U+FFFD ==> \t[m1]ко\u{fffd}шка[/m]"]);
+    let out = run(&["check", path.to_str().unwrap(), "--format", "json"]);
+    assert!(out.stdout.contains("\"kind\": \"invalid-surrogate\""), "{}", out.stdout);
+    assert!(out.stdout.contains("\"column\": 8"), "{}", out.stdout);
+}
+
+#[test]
+fn info() {
+    let dir = temp_dir("info");
+    // the broken card is never read
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}cat\r\n\t[m1][x]кошка[/m]\r\n", HEADER));
+    let out = run(&["info", &path]);
+    assert_eq!(out.code, 0);
+    assert_eq!(out.stdout.lines().collect::<Vec<_>>(),
+        ["name: Test", "index language: English", "contents language: Russian", "encoding: UTF-16LE"]);
+    let out = run(&["info", &path, "--format", "json"]);
+    assert_eq!(out.stdout.trim_end(), "{\"name\": \"Test\", \"index_language\": \"English\", \"contents_language\": \"Russian\", \"includes\": [], \"encoding\": \"UTF-16LE\", \"compressed\": false, \"errors\": []}");
+    let path = write_dict(&dir.join("bad-header.dsl"), "#NAME \"Test\"\r\n#CONTENTS_LANGUAGE \"Russian\"\r\n\r\ncat\r\n\t[m1]кошка[/m]\r\n");
+    let out = run(&["info", &path]);
+    assert_eq!(out.code, 1);
+    assert_eq!(out.stdout.lines().last(), Some("   1.PREV Name, CURR: Lang#CONTENTS_LANGUAGE \"Russian\""));
+}
+
+#[test]
+fn unknown_tags() {
+    let dir = temp_dir("unknown-tags");
+    let cards = "cat\r\n\t[m1][sup]2[/sup] кошка[/m]\r\ndog\r\n\t[m1][x]собака[/m]\r\n\t[m1][sup]3[/sup] \\[y\\][/m]\r\nfox\r\n\t[m1][u]лиса[/u][/m]\r\n";
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}{}", HEADER, cards));
+    let out = run(&["unknown-tags", &path]);
+    assert_eq!(out.stdout.lines().collect::<Vec<_>>(), ["tag,count,first_line", "sup,4,5", "u,2,10", "x,1,7"]);
+    let out = run(&["unknown-tags", &path, "--format", "json"]);
+    assert_eq!(out.stdout.lines().nth(1), Some("  {\"tag\": \"sup\", \"count\": 4, \"first_line\": 5},"));
+}
+
+#[test]
+fn missing_final_newline() {
+    let dir = temp_dir("final-newline");
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}cat\r\n\t[m1]кошка[/m]", HEADER));
+    let out = run(&["check", &path]);
+    assert_eq!(out.code, 0);
+    assert_eq!(out.stdout.lines().skip(1).collect::<Vec<_>>(), ["   5.warning: the file does not end with a line end ==> \t[m1]кошка[/m]"]);
+    // fixing adds the line end
+    let fixed = dir.join("fixed.dsl");
+    run(&["fix-tags", &path, fixed.to_str().unwrap()]);
+    assert!(decode(&fs::read(&fixed).unwrap()).ends_with("[/m]\r\n"));
+    let out = run(&["check", fixed.to_str().unwrap()]);
+    assert_eq!(out.stdout.lines().count(), 1, "{}", out.stdout);
+}
+
+#[test]
+fn severity_overrides() {
+    let dir = temp_dir("severity");
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}cat\r\n\t[m1][x]кошка[/m]", HEADER));
+    let out = run(&["check", &path]);
+    assert_eq!(out.code, 1);
+    let out = run(&["check", &path, "--severity", "unknown-tag=warning"]);
+    assert_eq!(out.code, 0, "{}", out.stdout);
+    assert!(out.stdout.contains("   5.warning: unknown tag '[x]'"), "{}", out.stdout);
+    // a warning promoted to an error fails the check
+    let out = run(&["check", &path, "--severity", "unknown-tag=info", "--severity", "missing-final-newline=error"]);
+    assert_eq!(out.code, 1, "{}", out.stdout);
+    assert!(out.stdout.contains("   5.the file does not end with a line end"), "{}", out.stdout);
+    let out = run(&["check", &path, "--severity", "unknown-tag=info", "--fail-on", "warning"]);
+    assert_eq!(out.code, 1, "{}", out.stdout);
+    let out = run(&["check", &path, "--severity", "unknown-tag=fatal"]);
+    assert!(out.stdout.contains("--severity: level must be error, warning, or info"), "{}", out.stdout);
+}
+
+#[test]
+fn collapse_margins() {
+    let dir = temp_dir("collapse-margins");
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}cat\r\n\t[m1][m1]word[/m][/m]\r\n", HEADER));
+    let fixed = dir.join("fixed.dsl");
+    run(&["fix-tags", &path, fixed.to_str().unwrap(), "--collapse-margins"]);
+    assert_eq!(decode(&fs::read(&fixed).unwrap()).lines().nth(5), Some("\t[m1]word[/m]"));
+    let out = run(&["check", fixed.to_str().unwrap(), "--check-redundant-margins"]);
+    assert_eq!(out.stdout.lines().count(), 1, "{}", out.stdout);
+}
+
+#[test]
+fn bare_cr_line_ends() {
+    let dir = temp_dir("bare-cr");
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}cat\r\t[m1]кошка[/m]\r", HEADER));
+    let out = run(&["check", &path]);
+    assert_eq!(out.code, 1);
+    assert!(out.stdout.contains("   4.line ends with a bare CR"), "{}", out.stdout);
+    // fixing replaces the line ends
+    let fixed = dir.join("fixed.dsl");
+    run(&["fix-tags", &path, fixed.to_str().unwrap()]);
+    assert!(decode(&fs::read(&fixed).unwrap()).ends_with("cat\r\n\t[m1]кошка[/m]\r\n"));
+    assert_eq!(run(&["check", fixed.to_str().unwrap()]).code, 0);
+}
+
+#[test]
+fn verify_normalize() {
+    let dir = temp_dir("verify-normalize");
+    let cards = "cat\r\n [m1][b]кошка[/m] \r\n\t[m1]a\tcat[/m]\r\n";
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}{}", HEADER, cards));
+    let out = run(&["verify-normalize", &path]);
+    assert_eq!(out.code, 0);
+    assert_eq!(out.stdout.lines().last(), Some("normalizing is stable: its output is not changed by another run"));
+}
+
+#[test]
+fn examples() {
+    let dir = temp_dir("examples");
+    let cards = "cat\r\n\t[m1]кошка[/m]\r\n\t[m2][ex][i]a[/i] cat[/ex] [ex][lang id=1031]eine Katze[/lang][/ex][/m]\r\n\t[m2][ex]a\tcat's toy[/ex][/m]\r\ndog\r\n\t[m1]собака[/m]\r\n\t[m2][ex][lang id=1031]ein Hund[/lang][/ex][/m]\r\n";
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}{}", HEADER, cards));
+    let out = run(&["examples", &path]);
+    assert_eq!(out.stdout.lines().collect::<Vec<_>>(),
+        ["headword\texample", "cat\ta cat", "cat\teine Katze", "cat\ta cat's toy", "dog\tein Hund"]);
+    let out = run(&["examples", &path, "--lang", "1031"]);
+    assert_eq!(out.stdout.lines().collect::<Vec<_>>(), ["headword\texample", "cat\teine Katze", "dog\tein Hund"]);
+}
+
+#[test]
+fn report_shared_media() {
+    let dir = temp_dir("shared-media");
+    for f in ["bank.wav", "cat.wav"] {
+        fs::write(dir.join(f), b"RIFF").unwrap();
+    }
+    // variants of one card share its sound, that is not reported
+    let cards = "bank\r\n\t[m1][s]bank.wav[/s] берег[/m]\r\nbench\r\n\t[m1][s]bank.wav[/s] скамья[/m]\r\ncat\r\ncats\r\n\t[m1][s]cat.wav[/s] кошка[/m]\r\n\t[m1][s]cat.wav[/s] кошки[/m]\r\n";
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}{}", HEADER, cards));
+    let out = run(&["media", &path]);
+    assert_eq!((out.code, out.stdout.lines().count()), (0, 1), "{}", out.stdout);
+    let out = run(&["media", &path, "--report-shared"]);
+    assert_eq!(out.code, 0);
+    assert_eq!(out.stdout.lines().skip(1).collect::<Vec<_>>(), ["   7.warning: media file 'bank.wav' is used by 2 cards: bank, bench"]);
+}
+
+#[test]
+fn html_pages() {
+    let dir = temp_dir("html-pages");
+    let cards = "apple\r\n\t[m1]яблоко[/m]\r\nbear\r\n\t[m1]медведь[/m]\r\ncat\r\n\t[m1]кошка[/m]\r\ndog\r\n\t[m1]собака[/m]\r\neel\r\n\t[m1]угорь, не <<apple>>[/m]\r\n";
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}{}", HEADER, cards));
+    let index = dir.join("book.html");
+    let out = run(&["html", &path, index.to_str().unwrap(), "--entries-per-page", "2"]);
+    assert_eq!(out.stdout.lines().last(), Some("5 card(s) written to 3 page(s)"), "{}", out.stdout);
+    let mut files: Vec<String> = fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().to_string()).collect();
+    files.sort();
+    assert_eq!(files, ["book-1.html", "book-2.html", "book-3.html", "book.html", "dict.dsl"]);
+    let index = fs::read_to_string(&index).unwrap();
+    assert!(index.contains("<li><a href=\"book-2.html\">C \u{2013} D</a> (cat \u{2013} dog)</li>"), "{}", index);
+    let second = fs::read_to_string(dir.join("book-2.html")).unwrap();
+    assert!(second.contains("<nav><a href=\"book-1.html\">&larr; previous</a> | <a href=\"book.html\">index</a> | <a href=\"book-3.html\">next &rarr;</a></nav>"), "{}", second);
+    // a reference to a card of another page
+    let last = fs::read_to_string(dir.join("book-3.html")).unwrap();
+    assert!(last.contains("<a href=\"book-1.html#apple\">apple</a>"), "{}", last);
+    assert!(!last.contains("next &rarr;"), "{}", last);
+    let single = dir.join("single.html");
+    let out = run(&["html", &path, single.to_str().unwrap()]);
+    assert_eq!(out.stdout.lines().last(), Some("5 card(s) written"));
+    assert!(fs::read_to_string(&single).unwrap().contains("<a href=\"#apple\">apple</a>"));
+}
+
+#[test]
+fn escape_audit() {
+    let dir = temp_dir("escape-audit");
+    let clean = write_dict(&dir.join("clean.dsl"), &format!("{}cat\r\n\t[m1]\\[1\\] кошка[/m]\r\n", HEADER));
+    let messy = write_dict(&dir.join("messy.dsl"), &format!("{}cat\r\n\t[m1]кошка[/m]\r\ndog\r\n\t[m1]собака [2][/m]\r\n", HEADER));
+    let out = run(&["escape-audit", &clean, &messy]);
+    assert_eq!(out.stdout.lines().collect::<Vec<_>>(), [
+        format!("{}: 2 escaped bracket(s), no unescaped literal brackets", clean),
+        format!("{}: 0 escaped bracket(s), 2 unescaped literal bracket(s), the first at line 7; fix-tags escapes them", messy),
+    ]);
+    let out = run(&["escape-audit", &messy, "--format", "json"]);
+    assert_eq!(out.stdout.lines().nth(1), Some(format!("  {{\"file\": \"{}\", \"escaped\": 0, \"literal\": 2, \"first_literal_line\": 7}}", messy).as_str()));
+}
+
+#[test]
+fn only_card() {
+    let dir = temp_dir("only-card");
+    let cards = "cat\r\n\t[m1][x]кошка[/m]\r\nDog\r\n\t[m1][y]собака[/m]\r\nfox\r\n\t[m1][z]лиса[/m]\r\ndog\r\n\t[m1][w]пёс[/m]\r\n";
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}{}", HEADER, cards));
+    assert_eq!(run(&["check", &path]).stdout.lines().count(), 5);
+    // the headword is compared case-folded
+    let out = run(&["check", &path, "--only-card", "DOG"]);
+    assert_eq!(out.code, 1);
+    assert_eq!(out.stdout.lines().skip(1).map(|l| &l[..5]).collect::<Vec<_>>(), ["   7.", "  11."]);
+    let out = run(&["check", &path, "--only-card", "cat"]);
+    assert_eq!(out.stdout.lines().skip(1).collect::<Vec<_>>(), ["   5.unknown tag '[x]' ==> \t[m1][x]кошка[/m]"]);
+    // other cards do not count towards the limit of errors
+    let out = run(&["check", &path, "--only-card", "dog", "--max-errors", "1"]);
+    assert_eq!(out.stdout.lines().skip(1).map(|l| &l[..5]).collect::<Vec<_>>(), ["   7."]);
+    // the whole file is checked, a later duplicate of the card is found
+    let dup = write_dict(&dir.join("dup.dsl"), &format!("{}cat\r\n\t[m1]кошка[/m]\r\nfox\r\n\t[m1][z]лиса[/m]\r\ncat\r\n\t[m1]кот[/m]\r\n", HEADER));
+    let out = run(&["check", &dup, "--only-card", "cat"]);
+    assert_eq!(out.stdout.lines().skip(1).collect::<Vec<_>>(), ["   8.cat already exists at 4"]);
+    let out = run(&["check", &path, "--only-card", "owl"]);
+    assert_eq!(out.code, 1);
+    assert_eq!(out.stdout.lines().last(), Some("card 'owl' not found"));
+}
+
+#[test]
+fn show() {
+    let dir = temp_dir("show");
+    let cards = "cat\r\n\t[m1][b]кошка[/b][/m]\r\n\t[m2][ex][i]a cat[/i][/ex][/m]\r\nCAT\r\n\t[m1]КОТ[/m]\r\ndog\r\n\t[m1]собака[/m]\r\n";
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}{}", HEADER, cards));
+    // not a terminal: no ANSI escapes
+    let out = run(&["show", &path, "Cat"]);
+    assert_eq!(out.code, 0);
+    assert_eq!(out.stdout, "cat\n  кошка\n    a cat\n\nCAT\n  КОТ\n");
+    let out = run(&["show", &path, "owl"]);
+    assert_eq!((out.code, out.stdout.as_str()), (1, "card 'owl' not found\n"));
+}
+
+#[test]
+fn remove_empty_formatting() {
+    let dir = temp_dir("remove-empty-formatting");
+    let path = write_dict(&dir.join("dict.dsl"), &format!("{}cat\r\n\t[m1][b][/b]кошка [i] [/i]кот[/m]\r\n", HEADER));
+    let out = run(&["check", &path, "--flag-empty-formatting"]);
+    assert_eq!(out.stdout.matches("tag without text").count(), 2, "{}", out.stdout);
+    let fixed = dir.join("fixed.dsl");
+    run(&["fix-tags", &path, fixed.to_str().unwrap(), "--remove-empty-formatting"]);
+    assert_eq!(decode(&fs::read(&fixed).unwrap()).lines().nth(5), Some("\t[m1]кошка  кот[/m]"));
+    let out = run(&["check", fixed.to_str().unwrap(), "--flag-empty-formatting"]);
+    assert_eq!(out.stdout.lines().count(), 1, "{}", out.stdout);
+}
+
+#[test]
+fn diff() {
+    let (old, new) = (fixture("diff_old.dsl"), fixture("diff_new.dsl"));
+    // a re-indented line and a headword in another case are the same card
+    let out = run(&["diff", &old, &new]);
+    assert_eq!(out.code, 1);
+    assert_eq!(out.stdout.lines().collect::<Vec<_>>(), ["- dog", "~ cat", "+ owl", "1 card(s) removed, 1 changed, 1 added"]);
+    let out = run(&["diff", &old, &new, "--format", "json"]);
+    assert_eq!(out.stdout, "{\"removed\": [\"dog\"], \"changed\": [\"cat\"], \"added\": [\"owl\"]}\n");
+    let out = run(&["diff", &old, &old]);
+    assert_eq!((out.code, out.stdout.as_str()), (0, "0 card(s) removed, 0 changed, 0 added\n"));
+}